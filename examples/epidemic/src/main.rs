@@ -17,6 +17,10 @@ use kernel::{
     ReadOnlyEntity,
     ReadOnlyRelation,
     RelationType,
+    Recorder,
+    Observable,
+    Aggregate,
+    MemorySink,
 };
 use rand::Rng;
 
@@ -37,8 +41,8 @@ fn main() {
     let mary = model.borrow().create_entity("Mary".to_string(), EntityType::Agent);
 
     // 状態の設定
-    john.get_state().borrow_mut().set("age".to_string(), Value::Integer(30));
-    mary.get_state().borrow_mut().set("age".to_string(), Value::Integer(28));
+    john.get_state().borrow_mut().set("age".to_string(), 30.into());
+    mary.get_state().borrow_mut().set("age".to_string(), 28.into());
 
     // 関数とプロセスの追加
     add_age_increment_function(&john);
@@ -52,6 +56,18 @@ fn main() {
     add_processes_to_model(&model, &john);
     add_processes_to_model(&model, &mary);
 
+    // 時系列の記録
+    let sink = MemorySink::new();
+    let recorder = Recorder::new(Box::new(sink.clone()))
+        .observe("agents", Observable::EntityCount(EntityType::Agent))
+        .observe("mean_age", Observable::StateAggregate {
+            entity_type: Some(EntityType::Agent),
+            key: "age".to_string(),
+            aggregate: Aggregate::Mean,
+        })
+        .observe("parent_relations", Observable::RelationCount("parent".to_string()));
+    model.borrow().add_observer(Box::new(recorder));
+
     // シミュレーションの実行
     println!("Initial state:");
     print_model_state(&model.borrow());
@@ -61,6 +77,14 @@ fn main() {
         model.borrow().simulate();
         print_model_state(&model.borrow());
     }
+
+    println!("\nTime series:");
+    let series = sink.series();
+    println!("step\t{}", series.columns.join("\t"));
+    for record in &series.records {
+        let values: Vec<String> = record.values.iter().map(|v| v.to_string()).collect();
+        println!("{}\t{}", record.step, values.join("\t"));
+    }
 }
 
 fn add_birth_function(entity: &Rc<Entity>) {
@@ -73,13 +97,13 @@ fn add_birth_function(entity: &Rc<Entity>) {
             let mut results = Vec::new();
             let mut rng = rand::thread_rng();
             
-            if let Some(Value::Integer(age)) = context.owner_entity.get_state().get("age") {
-                if *age >= 18 && rng.gen_bool(0.1) {
+            if let Ok(age) = context.owner_entity.get_state().get_as::<i32>("age") {
+                if age >= 18 && rng.gen_bool(0.1) {
                     println!("  {} (age {}) is giving birth!", entity_clone.get_name(), age);
                     let new_entity_info = EntityCreationInfo {
                         name: format!("Baby of {}", entity_clone.get_name()),
                         entity_type: EntityType::Agent,
-                        initial_state: vec![("age".to_string(), Value::Integer(0))].into_iter().collect(),
+                        initial_state: vec![("age".to_string(), Value::from(0))].into_iter().collect(),
                         functions: vec![
                             FunctionCreationInfo {
                                 name: "age_increment".to_string(),
@@ -87,17 +111,7 @@ fn add_birth_function(entity: &Rc<Entity>) {
                                 processes: vec![
                                     ProcessCreationInfo {
                                         name: "increment_age".to_string(),
-                                        action: Box::new(|context: &ExecutionContext| {
-                                            let mut results = Vec::new();
-                                            if let Some(age) = context.owner_entity.get_state().get("age") {
-                                                if let Value::Integer(current_age) = age {
-                                                    let new_age = current_age + 1;
-                                                    println!("  Incrementing age of {} from {} to {}", context.owner_entity.get_name(), current_age, new_age);
-                                                    results.push(ExecutionResult::UpdateEntityState(context.owner_entity.get_id(), "age".to_string(), Value::Integer(new_age)));
-                                                }
-                                            }
-                                            results
-                                        }),
+                                        action: Box::new(increment_age),
                                         condition: None,
                                     },
                                 ],
@@ -133,23 +147,24 @@ fn add_age_increment_function(entity: &Rc<Entity>) {
     let age_increment_process = Rc::new(Process::new(
         "increment_age".to_string(),
         Rc::downgrade(&age_increment_function),
-        Box::new(|context: &ExecutionContext| {
-            let mut results = Vec::new();
-            if let Some(age) = context.owner_entity.get_state().get("age") {
-                if let Value::Integer(current_age) = age {
-                    let new_age = current_age + 1;
-                    println!("  Incrementing age of {} from {} to {}", context.owner_entity.get_name(), current_age, new_age);
-                    results.push(ExecutionResult::UpdateEntityState(context.owner_entity.get_id(), "age".to_string(), Value::Integer(new_age)));
-                }
-            }
-            results
-        }),
+        Box::new(increment_age),
     ));
     age_increment_function.add_process(Rc::clone(&age_increment_process));
     entity.add_function(Rc::clone(&age_increment_function));
     age_increment_function.activate();
 }
 
+fn increment_age(context: &ExecutionContext) -> Vec<ExecutionResult> {
+    let state = context.owner_entity.get_state();
+    match state.get("age").map(|age| (age, age + 1)) {
+        Some((current_age, Ok(new_age))) => {
+            println!("  Incrementing age of {} from {} to {}", context.owner_entity.get_name(), current_age, new_age);
+            vec![ExecutionResult::update_state(context.owner_entity.get_id(), "age", new_age)]
+        }
+        _ => Vec::new(),
+    }
+}
+
 fn add_death_function(entity: &Rc<Entity>) {
     let entity_clone = Rc::clone(entity);
     let death_function = Rc::new(Function::new("death".to_string(), Rc::downgrade(entity)));
@@ -157,16 +172,13 @@ fn add_death_function(entity: &Rc<Entity>) {
         "die".to_string(),
         Rc::downgrade(&death_function),
         Box::new(move |_context: &ExecutionContext| {
-            let mut results = Vec::new();
-            if let Some(age) = entity_clone.get_state().borrow().get("age") {
-                if let Value::Integer(current_age) = age {
-                    if *current_age >= 80 {
-                        println!("  {} has died at age {}", entity_clone.get_name(), current_age);
-                        results.push(ExecutionResult::DeleteEntity(entity_clone.id));
-                    }
+            match entity_clone.get_state().borrow().get_as::<i32>("age") {
+                Ok(current_age) if current_age >= 80 => {
+                    println!("  {} has died at age {}", entity_clone.get_name(), current_age);
+                    vec![ExecutionResult::DeleteEntity(entity_clone.id)]
                 }
+                _ => Vec::new(),
             }
-            results
         }),
     ));
     death_function.add_process(Rc::clone(&death_process));
@@ -180,7 +192,7 @@ fn print_model_state(model: &Model) {
         println!("  State:");
         let state = entity.get_state().borrow();
         for (key, value) in state.iter() {
-            println!("    {}: {:?}", key, value);
+            println!("    {}: {}", key, value);
         }
         println!("  Relations:");
         for relation in entity.get_all_relations() {
@@ -198,4 +210,46 @@ fn add_processes_to_model(model: &Rc<RefCell<Model>>, entity: &Rc<Entity>) {
             model.borrow_mut().add_process(Rc::clone(&process));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context_of<'a>(model: &'a Model, entity: &'a Rc<Entity>, function: &'a Function) -> ExecutionContext<'a> {
+        ExecutionContext { owner_function: function, owner_entity: &**entity, model }
+    }
+
+    #[test]
+    fn age_is_incremented_by_one() {
+        let model = Model::new();
+        let entity = model.create_entity("a".to_string(), EntityType::Agent);
+        let function = Function::new("age_increment", Rc::downgrade(&entity));
+        assert!(increment_age(&context_of(&model, &entity, &function)).is_empty());
+
+        entity.get_state().borrow_mut().set("age", Value::Integer(41));
+        match increment_age(&context_of(&model, &entity, &function)).as_slice() {
+            [ExecutionResult::UpdateEntityState(id, key, value)] => {
+                assert_eq!(*id, entity.id);
+                assert_eq!(*key, "age");
+                assert_eq!(*value, Value::Integer(42));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn entities_die_at_eighty() {
+        let model = Model::new();
+        let entity = model.create_entity("a".to_string(), EntityType::Agent);
+        add_death_function(&entity);
+        let function = entity.get_function("death").unwrap();
+        let process = function.get_process("die").unwrap();
+        entity.get_state().borrow_mut().set("age", Value::Integer(79));
+        assert!(process.execute(&context_of(&model, &entity, &function)).is_empty());
+
+        entity.get_state().borrow_mut().set("age", Value::Integer(80));
+        let results = process.execute(&context_of(&model, &entity, &function));
+        assert!(matches!(results.as_slice(), [ExecutionResult::DeleteEntity(id)] if *id == entity.id));
+    }
+}
@@ -0,0 +1,135 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, LitStr, Path};
+
+// #[derive(EntityState)] を名前付きフィールドの構造体に実装する。
+// キー名は既定でフィールド名、#[state(rename = "...")] で変更できる。
+// kernel を別の名前や再エクスポート経由で使う場合は、構造体に #[state(crate = "mcss::kernel")] のように指定する
+#[proc_macro_derive(EntityState, attributes(state))]
+pub fn derive_entity_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(name, "EntityState requires a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(name, "EntityState can only be derived for structs")),
+    };
+
+    let mut krate: Path = parse_quote!(::kernel);
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("state")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                krate = meta.value()?.parse::<LitStr>()?.parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported state attribute"))
+            }
+        })?;
+    }
+
+    let mut idents = Vec::new();
+    let mut types = Vec::new();
+    let mut keys = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let mut key = ident.to_string();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("state")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    key = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported state attribute"))
+                }
+            })?;
+        }
+        idents.push(ident);
+        types.push(&field.ty);
+        keys.push(key);
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::EntityState for #name #ty_generics #where_clause {
+            fn keys() -> &'static [&'static str] {
+                &[#(#keys),*]
+            }
+
+            fn from_variable(state: &#krate::Variable) -> ::std::result::Result<Self, #krate::ValueError> {
+                ::std::result::Result::Ok(Self {
+                    #(#idents: #krate::read_field::<#types>(state, #keys)?,)*
+                })
+            }
+
+            fn to_values(&self) -> ::std::vec::Vec<(&'static str, ::std::option::Option<#krate::Value>)> {
+                ::std::vec![
+                    #((#keys, #krate::StateField::write(&self.#idents)),)*
+                ]
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(input: DeriveInput) -> String {
+        expand(&input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn keys_follow_field_names_and_renames() {
+        let input: DeriveInput = parse_quote! {
+            struct Person {
+                age: i32,
+                #[state(rename = "is_sick")]
+                sick: bool,
+            }
+        };
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains("& [\"age\" , \"is_sick\"]"));
+        assert!(tokens.contains("read_field :: < bool > (state , \"is_sick\")"));
+    }
+
+    #[test]
+    fn crate_path_can_be_overridden() {
+        let input: DeriveInput = parse_quote! {
+            #[state(crate = "mcss::kernel")]
+            struct Person {
+                age: i32,
+            }
+        };
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains("impl mcss :: kernel :: EntityState for Person"));
+        assert!(tokens.contains("mcss :: kernel :: read_field :: < i32 >"));
+        assert!(tokens.contains("state : & mcss :: kernel :: Variable"));
+
+        let input: DeriveInput = parse_quote! {
+            #[state(crate = "not a path")]
+            struct Person {
+                age: i32,
+            }
+        };
+        assert!(expand(&input).is_err());
+    }
+
+    #[test]
+    fn only_structs_with_named_fields_are_accepted() {
+        assert_eq!(error_of(parse_quote! { struct Pair(i32, i32); }), "EntityState requires a struct with named fields");
+        assert_eq!(error_of(parse_quote! { enum Kind { A } }), "EntityState can only be derived for structs");
+        assert_eq!(
+            error_of(parse_quote! { struct Person { #[state(skip)] age: i32 } }),
+            "unsupported state attribute"
+        );
+    }
+}
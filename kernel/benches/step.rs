@@ -0,0 +1,104 @@
+// 各エンティティがステップごとに数個のキーを更新するモデルで、Model::simulate の 1 ステップを測る。
+// 同じ読み書きを String キーの状態表と Symbol キーの状態表で行う最小のステップループも測り、キー表現の差を比べる。
+// cargo bench -p kernel --bench step
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use kernel::{Behavior, EntityType, ExecutionResult, Model, Symbol, Value};
+use uuid::Uuid;
+
+const ENTITIES: usize = 10_000;
+const KEYS: usize = 4;
+const STEPS: usize = 20;
+
+fn key_names() -> Vec<String> {
+    (0..KEYS).map(|i| format!("counter-{}", i)).collect()
+}
+
+fn measure(mut step: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        step();
+    }
+    start.elapsed() / STEPS as u32
+}
+
+fn model_step() -> Duration {
+    let model = Model::new();
+    let keys = key_names();
+    for i in 0..ENTITIES {
+        let entity = model.create_entity(format!("agent-{}", i), EntityType::Agent);
+        let mut state = entity.get_state().borrow_mut();
+        for key in &keys {
+            state.set(key.as_str(), Value::Integer(0));
+        }
+    }
+    let symbols: Vec<Symbol> = keys.iter().map(|key| Symbol::new(key)).collect();
+    model.define_behavior(EntityType::Agent, Behavior::new("count").process("increment", move |context| {
+        let state = context.owner_entity.get_state();
+        symbols.iter()
+            .map(|key| {
+                let count = state.get(key).and_then(|value| i32::try_from(value).ok()).unwrap_or(0);
+                ExecutionResult::UpdateEntityState(context.owner_entity.get_id(), *key, Value::Integer(count + 1))
+            })
+            .collect()
+    }));
+    measure(|| model.simulate())
+}
+
+// Symbol 化する前と同じく、結果と状態表のキーに String を使うステップ
+fn string_step() -> Duration {
+    let keys = key_names();
+    let mut states: Vec<(Uuid, HashMap<String, Value>)> = (0..ENTITIES)
+        .map(|_| (Uuid::new_v4(), keys.iter().map(|key| (key.clone(), Value::Integer(0))).collect()))
+        .collect();
+    let slots: HashMap<Uuid, usize> = states.iter().enumerate().map(|(slot, (id, _))| (*id, slot)).collect();
+    measure(|| {
+        let mut results: Vec<(Uuid, String, Value)> = Vec::with_capacity(ENTITIES * KEYS);
+        for (id, state) in &states {
+            for key in &keys {
+                let count = state.get(key).and_then(|value| i32::try_from(value).ok()).unwrap_or(0);
+                results.push((*id, key.clone(), Value::Integer(count + 1)));
+            }
+        }
+        for (id, key, value) in results {
+            states[slots[&id]].1.insert(key, value);
+        }
+        black_box(&states);
+    })
+}
+
+fn symbol_step() -> Duration {
+    let keys: Vec<Symbol> = key_names().iter().map(|key| Symbol::new(key)).collect();
+    let mut states: Vec<(Uuid, HashMap<Symbol, Value>)> = (0..ENTITIES)
+        .map(|_| (Uuid::new_v4(), keys.iter().map(|key| (*key, Value::Integer(0))).collect()))
+        .collect();
+    let slots: HashMap<Uuid, usize> = states.iter().enumerate().map(|(slot, (id, _))| (*id, slot)).collect();
+    measure(|| {
+        let mut results: Vec<(Uuid, Symbol, Value)> = Vec::with_capacity(ENTITIES * KEYS);
+        for (id, state) in &states {
+            for key in &keys {
+                let count = state.get(key).and_then(|value| i32::try_from(value).ok()).unwrap_or(0);
+                results.push((*id, *key, Value::Integer(count + 1)));
+            }
+        }
+        for (id, key, value) in results {
+            states[slots[&id]].1.insert(key, value);
+        }
+        black_box(&states);
+    })
+}
+
+fn main() {
+    println!("entities={} keys={} steps={}", ENTITIES, KEYS, STEPS);
+    println!("{:<8} {:>9.2?}/step", "model", model_step());
+    let string = string_step();
+    let symbol = symbol_step();
+    println!("{:<8} {:>9.2?}/step", "string", string);
+    println!(
+        "{:<8} {:>9.2?}/step speedup={:.1}x",
+        "symbol",
+        symbol,
+        string.as_secs_f64() / symbol.as_secs_f64()
+    );
+}
@@ -0,0 +1,116 @@
+// シンボルの読み取りを、表全体を RwLock で守る以前の実装と比べる。
+// cargo bench -p kernel --bench symbol
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use kernel::Symbol;
+
+const KEYS: usize = 1_000;
+const ROUNDS: usize = 200;
+
+// 以前の実装と同じく、検索と文字列の取り出しのたびに読み取りロックを取る表
+#[derive(Default)]
+struct LockedInterner {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+impl LockedInterner {
+    fn intern(table: &RwLock<Self>, name: &str) -> u32 {
+        let mut table = table.write().unwrap();
+        if let Some(id) = table.ids.get(name) {
+            return *id;
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let id = table.names.len() as u32;
+        table.names.push(name);
+        table.ids.insert(name, id);
+        id
+    }
+}
+
+fn measure(threads: usize, work: impl Fn() + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(&work);
+        }
+    });
+    start.elapsed()
+}
+
+fn report(name: &str, threads: usize, locked: Duration, symbol: Duration) {
+    println!(
+        "{:<8} threads={:<2} locked={:>9.2?} symbol={:>9.2?} speedup={:.1}x",
+        name,
+        threads,
+        locked,
+        symbol,
+        locked.as_secs_f64() / symbol.as_secs_f64()
+    );
+}
+
+fn main() {
+    let names: Vec<String> = (0..KEYS).map(|i| format!("bench-key-{}", i)).collect();
+    let locked = RwLock::new(LockedInterner::default());
+    let ids: Vec<u32> = names.iter().map(|name| LockedInterner::intern(&locked, name)).collect();
+    let symbols: Vec<Symbol> = names.iter().map(|name| Symbol::new(name)).collect();
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(8);
+
+    let mut thread_counts = vec![1, threads];
+    thread_counts.dedup();
+    for threads in thread_counts {
+        let old = measure(threads, || {
+            for _ in 0..ROUNDS {
+                for name in &names {
+                    black_box(locked.read().unwrap().ids.get(name.as_str()).copied());
+                }
+            }
+        });
+        let new = measure(threads, || {
+            for _ in 0..ROUNDS {
+                for name in &names {
+                    black_box(Symbol::lookup(name));
+                }
+            }
+        });
+        report("lookup", threads, old, new);
+
+        let old = measure(threads, || {
+            for _ in 0..ROUNDS {
+                for id in &ids {
+                    black_box(locked.read().unwrap().names[*id as usize].len());
+                }
+            }
+        });
+        let new = measure(threads, || {
+            for _ in 0..ROUNDS {
+                for symbol in &symbols {
+                    black_box(symbol.as_str().len());
+                }
+            }
+        });
+        report("as_str", threads, old, new);
+
+        let old = measure(threads, || {
+            for _ in 0..ROUNDS / 10 {
+                let mut sorted = ids.clone();
+                sorted.sort_by(|a, b| {
+                    let table = locked.read().unwrap();
+                    table.names[*a as usize].cmp(table.names[*b as usize])
+                });
+                black_box(sorted);
+            }
+        });
+        let new = measure(threads, || {
+            for _ in 0..ROUNDS / 10 {
+                let mut sorted = symbols.clone();
+                sorted.sort();
+                black_box(sorted);
+            }
+        });
+        report("sort", threads, old, new);
+    }
+}
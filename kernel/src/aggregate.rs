@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::fmt;
+use crate::context::{ReadOnlyEntity, ReadOnlyModel};
+use crate::types::EntityType;
+use crate::variable::{format_datetime, Value};
+
+pub type EntityPredicate<'a> = Box<dyn Fn(&dyn ReadOnlyEntity) -> bool + 'a>;
+
+pub struct Aggregation<'a> {
+    model: &'a dyn ReadOnlyModel,
+    key: String,
+    entity_type: Option<EntityType>,
+    predicate: Option<EntityPredicate<'a>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    pub count: usize,
+    pub numeric_count: usize,
+    pub sum: Option<f64>,
+    pub mean: Option<f64>,
+    pub variance: Option<f64>,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub edges: Vec<f64>,
+    pub counts: Vec<usize>,
+}
+
+impl<'a> Aggregation<'a> {
+    pub fn new(model: &'a dyn ReadOnlyModel, key: &str) -> Self {
+        Self {
+            model,
+            key: key.to_string(),
+            entity_type: None,
+            predicate: None,
+        }
+    }
+
+    pub fn of_type(mut self, entity_type: EntityType) -> Self {
+        self.entity_type = Some(entity_type);
+        self
+    }
+
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&dyn ReadOnlyEntity) -> bool + 'a,
+    {
+        self.predicate = Some(Box::new(predicate));
+        self
+    }
+
+    fn entities(&self) -> Vec<std::rc::Rc<dyn ReadOnlyEntity>> {
+        let entities = match &self.entity_type {
+            Some(entity_type) => self.model.get_entities_by_type(entity_type),
+            None => self.model.get_all_entities(),
+        };
+        entities
+            .into_iter()
+            .filter(|e| self.predicate.as_ref().is_none_or(|p| p(e.as_ref())))
+            .collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        self.entities()
+            .iter()
+            .filter_map(|e| e.get_state_value(&self.key))
+            .collect()
+    }
+
+    pub fn count(&self) -> usize {
+        self.values().len()
+    }
+
+    pub fn sum(&self) -> Option<f64> {
+        self.summary().sum
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        self.summary().mean
+    }
+
+    pub fn variance(&self) -> Option<f64> {
+        self.summary().variance
+    }
+
+    pub fn min(&self) -> Option<Value> {
+        self.summary().min
+    }
+
+    pub fn max(&self) -> Option<Value> {
+        self.summary().max
+    }
+
+    pub fn summary(&self) -> Summary {
+        summarize(&self.values())
+    }
+
+    // 数値の値を等幅のビンに分割する
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        let numbers: Vec<f64> = self.values().iter().filter_map(numeric_value).collect();
+        histogram(&numbers, bins)
+    }
+
+    // カテゴリ値ごとの件数
+    pub fn frequencies(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for value in self.values() {
+            *counts.entry(category_label(&value)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn group_by(&self, group_key: &str) -> BTreeMap<String, Summary> {
+        let mut groups: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+        for entity in self.entities() {
+            if let (Some(group), Some(value)) = (entity.get_state_value(group_key), entity.get_state_value(&self.key)) {
+                groups.entry(category_label(&group)).or_default().push(value);
+            }
+        }
+        groups.into_iter().map(|(group, values)| (group, summarize(&values))).collect()
+    }
+}
+
+impl fmt::Debug for Aggregation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Aggregation")
+            .field("key", &self.key)
+            .field("entity_type", &self.entity_type)
+            .field("predicate", &self.predicate.is_some())
+            .finish()
+    }
+}
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn aggregate(&self, key: &str) -> Aggregation<'_> {
+        Aggregation::new(self, key)
+    }
+}
+
+pub(crate) fn numeric_value(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Int64(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f as f64),
+        Value::Float64(f) => Some(*f),
+        Value::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+pub(crate) fn category_label(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Int64(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Float64(f) => f.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(category_label).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Map(entries) => {
+            let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}:{}", k, category_label(v))).collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::EntityRef(id) => id.to_string(),
+        Value::DateTime(datetime) => format_datetime(datetime),
+        Value::Null => "null".to_string(),
+    }
+}
+
+// 数値同士は数値として、文字列同士は辞書順で、日時同士は時刻順で比較する
+pub(crate) fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (numeric_value(a), numeric_value(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y),
+        _ => match (a, b) {
+            (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+            (Value::DateTime(x), Value::DateTime(y)) => Some(x.cmp(y)),
+            _ => None,
+        },
+    }
+}
+
+pub(crate) fn summarize(values: &[Value]) -> Summary {
+    let numbers: Vec<f64> = values.iter().filter_map(numeric_value).collect();
+    let mut summary = Summary {
+        count: values.len(),
+        numeric_count: numbers.len(),
+        ..Summary::default()
+    };
+
+    if !numbers.is_empty() {
+        let n = numbers.len() as f64;
+        let sum: f64 = numbers.iter().sum();
+        let mean = sum / n;
+        let variance = numbers.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        summary.sum = Some(sum);
+        summary.mean = Some(mean);
+        summary.variance = Some(variance);
+    }
+
+    for value in values {
+        if summary.min.as_ref().is_none_or(|m| compare_values(value, m) == Some(Ordering::Less)) {
+            summary.min = Some(value.clone());
+        }
+        if summary.max.as_ref().is_none_or(|m| compare_values(value, m) == Some(Ordering::Greater)) {
+            summary.max = Some(value.clone());
+        }
+    }
+    summary
+}
+
+pub(crate) fn histogram(numbers: &[f64], bins: usize) -> Histogram {
+    let bins = bins.max(1);
+    let finite: Vec<f64> = numbers.iter().cloned().filter(|x| x.is_finite()).collect();
+    if finite.is_empty() {
+        return Histogram { edges: Vec::new(), counts: Vec::new() };
+    }
+    let min = finite.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = finite.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let width = if max > min { (max - min) / bins as f64 } else { 1.0 };
+    let edges = (0..=bins).map(|i| min + width * i as f64).collect();
+    let mut counts = vec![0; bins];
+    for x in finite {
+        let index = (((x - min) / width) as usize).min(bins - 1);
+        counts[index] += 1;
+    }
+    Histogram { edges, counts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    fn model() -> Model {
+        let model = Model::new();
+        for (name, age, group) in [("a", 10, "x"), ("b", 20, "x"), ("c", 40, "y")] {
+            let entity = model.create_entity(name.to_string(), EntityType::Agent);
+            let mut state = entity.get_state().borrow_mut();
+            state.set("age", Value::Integer(age));
+            state.set("group", Value::String(group.to_string()));
+        }
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        spot.get_state().borrow_mut().set("age", Value::Integer(100));
+        model
+    }
+
+    #[test]
+    fn summary_of_numeric_values() {
+        let model = model();
+        let summary = model.aggregate("age").of_type(EntityType::Agent).summary();
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.sum, Some(70.0));
+        assert!((summary.mean.unwrap() - 70.0 / 3.0).abs() < 1e-9);
+        assert_eq!(summary.min, Some(Value::Integer(10)));
+        assert_eq!(summary.max, Some(Value::Integer(40)));
+        assert_eq!(model.aggregate("age").count(), 4);
+    }
+
+    #[test]
+    fn empty_and_non_numeric_values() {
+        let model = model();
+        let missing = model.aggregate("height").summary();
+        assert_eq!(missing.count, 0);
+        assert_eq!(missing.mean, None);
+        assert_eq!(missing.min, None);
+
+        let groups = model.aggregate("group").summary();
+        assert_eq!(groups.count, 3);
+        assert_eq!(groups.numeric_count, 0);
+        assert_eq!(groups.sum, None);
+        assert_eq!(groups.min, Some(Value::String("x".to_string())));
+        assert_eq!(groups.max, Some(Value::String("y".to_string())));
+    }
+
+    #[test]
+    fn filter_group_and_frequencies() {
+        let model = model();
+        let old = model.aggregate("age")
+            .filter(|e| e.get_state().get("age").is_some_and(|v| *v >= Value::Integer(20)))
+            .count();
+        assert_eq!(old, 3);
+
+        let groups = model.aggregate("age").group_by("group");
+        assert_eq!(groups["x"].mean, Some(15.0));
+        assert_eq!(groups["y"].count, 1);
+
+        let frequencies = model.aggregate("group").frequencies();
+        assert_eq!(frequencies["x"], 2);
+        assert_eq!(frequencies["y"], 1);
+    }
+
+    #[test]
+    fn histogram_bins() {
+        let h = histogram(&[0.0, 1.0, 2.0, 3.0, 4.0, f64::NAN], 2);
+        assert_eq!(h.edges, vec![0.0, 2.0, 4.0]);
+        assert_eq!(h.counts, vec![2, 3]);
+
+        let single = histogram(&[5.0, 5.0], 3);
+        assert_eq!(single.counts, vec![2, 0, 0]);
+        assert!(histogram(&[], 3).counts.is_empty());
+    }
+}
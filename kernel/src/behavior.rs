@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::context::ExecutionContext;
+use crate::process::{Action, Condition};
+use crate::result::ExecutionResult;
+use crate::variable::Value;
+
+type SharedAction = Rc<dyn Fn(&ExecutionContext) -> Vec<ExecutionResult> + 'static>;
+pub(crate) type ProcessParts = (String, Action, Option<Box<dyn Condition>>);
+
+// 型レベルの関数の雛形。Model::define_behavior で EntityType に登録すると、
+// その型 (派生型を含む) の既存・新規エンティティに同名の Function として付与される
+#[derive(Clone)]
+pub struct Behavior {
+    pub name: String,
+    pub parameters: HashMap<String, Value>,
+    pub active: bool,
+    processes: Vec<BehaviorProcess>,
+}
+
+#[derive(Clone)]
+struct BehaviorProcess {
+    name: String,
+    action: SharedAction,
+    condition: Option<Rc<dyn Condition>>,
+}
+
+impl Behavior {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            parameters: HashMap::new(),
+            active: true,
+            processes: Vec::new(),
+        }
+    }
+
+    pub fn parameter(mut self, key: &str, value: Value) -> Self {
+        self.parameters.insert(key.to_string(), value);
+        self
+    }
+
+    // 付与された Function を非アクティブの状態で始める
+    pub fn inactive(mut self) -> Self {
+        self.active = false;
+        self
+    }
+
+    pub fn process<F>(mut self, name: &str, action: F) -> Self
+    where
+        F: Fn(&ExecutionContext) -> Vec<ExecutionResult> + 'static,
+    {
+        self.processes.push(BehaviorProcess {
+            name: name.to_string(),
+            action: Rc::new(action),
+            condition: None,
+        });
+        self
+    }
+
+    pub fn process_when<C, F>(mut self, name: &str, condition: C, action: F) -> Self
+    where
+        C: Condition + 'static,
+        F: Fn(&ExecutionContext) -> Vec<ExecutionResult> + 'static,
+    {
+        self.processes.push(BehaviorProcess {
+            name: name.to_string(),
+            action: Rc::new(action),
+            condition: Some(Rc::new(condition)),
+        });
+        self
+    }
+
+    pub fn process_names(&self) -> Vec<&str> {
+        self.processes.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    // 各エンティティのプロセスはクロージャと条件を共有する
+    pub(crate) fn instantiate(&self) -> Vec<ProcessParts> {
+        self.processes
+            .iter()
+            .map(|process| {
+                let action = Rc::clone(&process.action);
+                let condition = process.condition.clone().map(|c| Box::new(SharedCondition(c)) as Box<dyn Condition>);
+                (process.name.clone(), Box::new(move |context: &ExecutionContext| action(context)) as Action, condition)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for Behavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Behavior")
+            .field("name", &self.name)
+            .field("parameters", &self.parameters)
+            .field("active", &self.active)
+            .field("processes", &self.process_names())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct SharedCondition(Rc<dyn Condition>);
+
+impl Condition for SharedCondition {
+    fn is_met(&self, context: &ExecutionContext) -> bool {
+        self.0.is_met(context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::model::Model;
+    use crate::types::EntityType;
+
+    fn counter() -> Behavior {
+        Behavior::new("count").parameter("step", Value::Integer(1)).process("increment", |context| {
+            let id = context.owner_entity.get_id();
+            let count = context.owner_entity.get_state().get("count").cloned().unwrap_or(Value::Integer(0));
+            vec![ExecutionResult::UpdateEntityState(id, "count".into(), (count + 1).unwrap())]
+        })
+    }
+
+    #[test]
+    fn behavior_is_attached_to_existing_and_new_entities() {
+        let model = Model::new();
+        let before = model.create_entity("a".to_string(), EntityType::Agent);
+        model.define_behavior(EntityType::Agent, counter());
+        let after = model.create_entity("b".to_string(), EntityType::Agent);
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+
+        model.simulate();
+        for entity in [&before, &after] {
+            assert_eq!(entity.get_state().borrow().get("count"), Some(&Value::Integer(1)));
+            let function = entity.get_function("count").unwrap();
+            assert_eq!(function.get_parameter().borrow().get("step"), Some(&Value::Integer(1)));
+        }
+        assert!(spot.get_function("count").is_none());
+    }
+
+    #[test]
+    fn instance_functions_and_subtypes_take_precedence() {
+        let model = Model::new();
+        let person = EntityType::Custom("Person".to_string());
+        model.register_entity_type(person.clone(), EntityType::Agent).unwrap();
+        let own = model.create_entity("own".to_string(), EntityType::Agent);
+        own.add_function(Rc::new(Function::new("count".to_string(), Rc::downgrade(&own))));
+        let member = model.create_entity("p".to_string(), person.clone());
+
+        model.define_behavior(EntityType::Agent, counter());
+        model.define_behavior(person.clone(), Behavior::new("count").inactive());
+        assert!(own.get_function("count").unwrap().get_all_processes().is_empty());
+        let function = member.get_function("count").unwrap();
+        assert!(!function.is_active());
+        assert!(function.get_all_processes().is_empty());
+        assert_eq!(model.get_behaviors(&person).len(), 1);
+
+        assert!(model.remove_behavior(&person, "count").is_some());
+        assert_eq!(model.get_behaviors(&person)[0].process_names(), vec!["increment"]);
+    }
+}
@@ -0,0 +1,1146 @@
+use std::cell::{Cell, OnceCell, Ref, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use crate::context::{ReadOnlyEntity, ReadOnlyFunction, ReadOnlyModel, ReadOnlyRelation};
+use crate::lattice::Lattice;
+use crate::model::ModelError;
+use crate::multiplex::ContextDefinition;
+use crate::recorder::Observer;
+use crate::result::{ExecutionResult, RelationCreationInfo};
+use crate::schedule::Timetable;
+use crate::schema::StateSchema;
+use crate::space::Space;
+use crate::system::System;
+use crate::symbol::{AsSymbol, Symbol};
+use crate::types::{EntityType, RelationType, TypeRegistry};
+use crate::value::ValueError;
+use crate::variable::{Value, Variable};
+
+// 密な配列上のエンティティ位置。削除されたスロットは世代を進めて再利用する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntityHandle {
+    pub index: u32,
+    pub generation: u32,
+}
+
+// 状態キーごとの列。最初に書き込まれた値の型で作られ、以降の値は列の型に変換して格納する
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Integer(Vec<Option<i32>>),
+    Int64(Vec<Option<i64>>),
+    Float(Vec<Option<f32>>),
+    Float64(Vec<Option<f64>>),
+    Boolean(Vec<Option<bool>>),
+    Dynamic(Vec<Option<Value>>),
+}
+
+impl Column {
+    fn for_value(value: &Value, len: usize) -> Self {
+        match value {
+            Value::Integer(_) => Column::Integer(vec![None; len]),
+            Value::Int64(_) => Column::Int64(vec![None; len]),
+            Value::Float(_) => Column::Float(vec![None; len]),
+            Value::Float64(_) => Column::Float64(vec![None; len]),
+            Value::Boolean(_) => Column::Boolean(vec![None; len]),
+            _ => Column::Dynamic(vec![None; len]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Integer(values) => values.len(),
+            Column::Int64(values) => values.len(),
+            Column::Float(values) => values.len(),
+            Column::Float64(values) => values.len(),
+            Column::Boolean(values) => values.len(),
+            Column::Dynamic(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value> {
+        match self {
+            Column::Integer(values) => values.get(index).copied().flatten().map(Value::Integer),
+            Column::Int64(values) => values.get(index).copied().flatten().map(Value::Int64),
+            Column::Float(values) => values.get(index).copied().flatten().map(Value::Float),
+            Column::Float64(values) => values.get(index).copied().flatten().map(Value::Float64),
+            Column::Boolean(values) => values.get(index).copied().flatten().map(Value::Boolean),
+            Column::Dynamic(values) => values.get(index).cloned().flatten(),
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<&[Option<i32>]> {
+        match self {
+            Column::Integer(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer_mut(&mut self) -> Option<&mut [Option<i32>]> {
+        match self {
+            Column::Integer(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_int64(&self) -> Option<&[Option<i64>]> {
+        match self {
+            Column::Int64(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_int64_mut(&mut self) -> Option<&mut [Option<i64>]> {
+        match self {
+            Column::Int64(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<&[Option<f32>]> {
+        match self {
+            Column::Float(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_float_mut(&mut self) -> Option<&mut [Option<f32>]> {
+        match self {
+            Column::Float(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_float64(&self) -> Option<&[Option<f64>]> {
+        match self {
+            Column::Float64(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_float64_mut(&mut self) -> Option<&mut [Option<f64>]> {
+        match self {
+            Column::Float64(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_boolean(&self) -> Option<&[Option<bool>]> {
+        match self {
+            Column::Boolean(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_boolean_mut(&mut self) -> Option<&mut [Option<bool>]> {
+        match self {
+            Column::Boolean(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn resize(&mut self, len: usize) {
+        match self {
+            Column::Integer(values) => values.resize(len, None),
+            Column::Int64(values) => values.resize(len, None),
+            Column::Float(values) => values.resize(len, None),
+            Column::Float64(values) => values.resize(len, None),
+            Column::Boolean(values) => values.resize(len, None),
+            Column::Dynamic(values) => values.resize(len, None),
+        }
+    }
+
+    // 変換できない値は書き込まずにエラーを返す。型付きの列への Null はその行を空にする
+    pub fn set(&mut self, index: usize, value: Value) -> Result<(), ValueError> {
+        if value == Value::Null && !matches!(self, Column::Dynamic(_)) {
+            self.clear(index);
+            return Ok(());
+        }
+        match self {
+            Column::Integer(values) => values[index] = Some(i32::try_from(&value)?),
+            Column::Int64(values) => values[index] = Some(i64::try_from(&value)?),
+            Column::Float(values) => values[index] = Some(f32::try_from(&value)?),
+            Column::Float64(values) => values[index] = Some(f64::try_from(&value)?),
+            Column::Boolean(values) => values[index] = Some(bool::try_from(&value)?),
+            Column::Dynamic(values) => values[index] = Some(value),
+        }
+        Ok(())
+    }
+
+    // 書き込まずに、値が列の型に変換できるかだけを調べる
+    fn check(&self, value: &Value) -> Result<(), ValueError> {
+        if *value == Value::Null {
+            return Ok(());
+        }
+        match self {
+            Column::Integer(_) => i32::try_from(value).map(drop),
+            Column::Int64(_) => i64::try_from(value).map(drop),
+            Column::Float(_) => f32::try_from(value).map(drop),
+            Column::Float64(_) => f64::try_from(value).map(drop),
+            Column::Boolean(_) => bool::try_from(value).map(drop),
+            Column::Dynamic(_) => Ok(()),
+        }
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        match self {
+            Column::Integer(values) => values[index] = None,
+            Column::Int64(values) => values[index] = None,
+            Column::Float(values) => values[index] = None,
+            Column::Float64(values) => values[index] = None,
+            Column::Boolean(values) => values[index] = None,
+            Column::Dynamic(values) => values[index] = None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Edge {
+    id: Uuid,
+    relation_type: RelationType,
+    source: u32,
+    target: u32,
+    meta: HashMap<Symbol, Value>,
+}
+
+// 関係名ごとの隣接配列。削除された辺のスロットは None にして free から再利用する
+#[derive(Debug, Clone, Default)]
+struct Adjacency {
+    edges: Vec<Option<Edge>>,
+    free: Vec<u32>,
+    outgoing: Vec<Vec<u32>>,
+    incoming: Vec<Vec<u32>>,
+}
+
+impl Adjacency {
+    fn edges_of(&self, index: u32) -> impl Iterator<Item = u32> + '_ {
+        let outgoing = self.outgoing.get(index as usize).into_iter().flatten();
+        let incoming = self.incoming.get(index as usize).into_iter().flatten();
+        outgoing.chain(incoming).copied()
+    }
+
+    fn insert_edge(&mut self, edge: Edge) -> u32 {
+        let (source, target) = (edge.source as usize, edge.target as usize);
+        let edge_index = match self.free.pop() {
+            Some(edge_index) => {
+                self.edges[edge_index as usize] = Some(edge);
+                edge_index
+            }
+            None => {
+                self.edges.push(Some(edge));
+                self.edges.len() as u32 - 1
+            }
+        };
+        self.outgoing[source].push(edge_index);
+        self.incoming[target].push(edge_index);
+        edge_index
+    }
+
+    fn remove_edge(&mut self, edge_index: u32) -> Option<Edge> {
+        let edge = self.edges.get_mut(edge_index as usize)?.take()?;
+        self.outgoing[edge.source as usize].retain(|e| *e != edge_index);
+        self.incoming[edge.target as usize].retain(|e| *e != edge_index);
+        self.free.push(edge_index);
+        Some(edge)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+    ids: Vec<Uuid>,
+    names: Vec<String>,
+    types: Vec<EntityType>,
+    by_id: HashMap<Uuid, u32>,
+    by_name: HashMap<String, HashSet<u32>>,
+    // 状態キーのシンボルから列番号を引く。column_keys は列番号の順に並ぶ
+    keys: HashMap<Symbol, u32>,
+    column_keys: Vec<Symbol>,
+    columns: Vec<Column>,
+    relations: HashMap<Symbol, Adjacency>,
+    relation_ids: HashMap<Uuid, (Symbol, u32)>,
+    type_registry: TypeRegistry,
+}
+
+impl Store {
+    fn alive_indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.alive.iter().enumerate().filter(|(_, alive)| **alive).map(|(index, _)| index as u32)
+    }
+
+    fn allocate(&mut self, name: String, entity_type: EntityType) -> u32 {
+        let id = Uuid::new_v4();
+        let index = match self.free.pop() {
+            Some(index) => {
+                let i = index as usize;
+                self.generations[i] += 1;
+                self.alive[i] = true;
+                self.ids[i] = id;
+                self.names[i] = name.clone();
+                self.types[i] = entity_type;
+                index
+            }
+            None => {
+                let index = self.ids.len() as u32;
+                self.generations.push(0);
+                self.alive.push(true);
+                self.ids.push(id);
+                self.names.push(name.clone());
+                self.types.push(entity_type);
+                let len = self.ids.len();
+                for column in &mut self.columns {
+                    column.resize(len);
+                }
+                index
+            }
+        };
+        self.by_id.insert(id, index);
+        self.by_name.entry(name).or_default().insert(index);
+        index
+    }
+
+    fn release(&mut self, index: u32) {
+        let i = index as usize;
+        self.alive[i] = false;
+        self.by_id.remove(&self.ids[i]);
+        if let Some(indices) = self.by_name.get_mut(&self.names[i]) {
+            indices.remove(&index);
+            if indices.is_empty() {
+                self.by_name.remove(&self.names[i]);
+            }
+        }
+        for column in &mut self.columns {
+            column.clear(i);
+        }
+        for adjacency in self.relations.values_mut() {
+            let edges: Vec<u32> = adjacency.edges_of(index).collect();
+            for edge_index in edges {
+                if let Some(edge) = adjacency.remove_edge(edge_index) {
+                    self.relation_ids.remove(&edge.id);
+                }
+            }
+        }
+        self.free.push(index);
+    }
+
+    fn column_of<K: AsSymbol + ?Sized>(&self, key: &K) -> Option<usize> {
+        self.keys.get(&key.as_symbol()?).map(|key_id| *key_id as usize)
+    }
+
+    fn set_state(&mut self, index: u32, key: Symbol, value: Value) -> Result<(), ValueError> {
+        let key_id = match self.keys.get(&key) {
+            Some(key_id) => *key_id as usize,
+            None => {
+                self.keys.insert(key, self.columns.len() as u32);
+                self.column_keys.push(key);
+                self.columns.push(Column::for_value(&value, self.ids.len()));
+                self.columns.len() - 1
+            }
+        };
+        self.columns[key_id]
+            .set(index as usize, value)
+            .map_err(|error| ValueError::InvalidKey { key: key.to_string(), error: Box::new(error) })
+    }
+
+    // まだ列のないキーは、バッチ内で最初に書かれる値の型で列ができるものとして pending に記録する
+    fn check_state(&self, pending: &mut HashMap<Symbol, Column>, key: Symbol, value: &Value) -> Result<(), ValueError> {
+        let column = match self.keys.get(&key) {
+            Some(key_id) => &self.columns[*key_id as usize],
+            None => pending.entry(key).or_insert_with(|| Column::for_value(value, 0)),
+        };
+        column.check(value).map_err(|error| ValueError::InvalidKey { key: key.to_string(), error: Box::new(error) })
+    }
+
+    fn state_value<K: AsSymbol + ?Sized>(&self, index: u32, key: &K) -> Option<Value> {
+        self.columns[self.column_of(key)?].get(index as usize)
+    }
+
+    // 同名のエンティティが複数あるときは最も小さいスロットを返す
+    fn first_by_name(&self, name: &str) -> Option<u32> {
+        self.by_name.get(name)?.iter().min().copied()
+    }
+
+    fn remove_state<K: AsSymbol + ?Sized>(&mut self, index: u32, key: &K) {
+        if let Some(key_id) = self.column_of(key) {
+            self.columns[key_id].clear(index as usize);
+        }
+    }
+
+    fn state_of(&self, index: u32) -> Variable {
+        let mut state = Variable::new();
+        for (key, column) in self.column_keys.iter().zip(&self.columns) {
+            if let Some(value) = column.get(index as usize) {
+                state.set(*key, value);
+            }
+        }
+        state
+    }
+
+    fn add_edge(&mut self, name: Symbol, relation_type: RelationType, source: u32, target: u32) -> Uuid {
+        let len = self.ids.len();
+        let adjacency = self.relations.entry(name).or_default();
+        adjacency.outgoing.resize(len, Vec::new());
+        adjacency.incoming.resize(len, Vec::new());
+        let id = Uuid::new_v4();
+        let edge_index = adjacency.insert_edge(Edge { id, relation_type, source, target, meta: HashMap::new() });
+        self.relation_ids.insert(id, (name, edge_index));
+        id
+    }
+
+    fn edge(&self, id: &Uuid) -> Option<(Symbol, &Edge)> {
+        let (name, edge_index) = self.relation_ids.get(id)?;
+        let edge = self.relations.get(name)?.edges.get(*edge_index as usize)?.as_ref()?;
+        Some((*name, edge))
+    }
+
+    fn adjacency<K: AsSymbol + ?Sized>(&self, name: &K) -> Option<&Adjacency> {
+        self.relations.get(&name.as_symbol()?)
+    }
+
+    fn edge_mut(&mut self, id: &Uuid) -> Option<&mut Edge> {
+        let (name, edge_index) = self.relation_ids.get(id)?;
+        self.relations.get_mut(name)?.edges.get_mut(*edge_index as usize)?.as_mut()
+    }
+}
+
+// 大規模集団向けの列指向ストレージ。状態はキーごとの型付き列、関係は隣接配列で持つ。
+// 関数とプロセスは持たないので、振る舞いは System として登録する。
+// ReadOnlyModel 経由で既存のクエリや集計がそのまま使える
+pub struct ColumnarModel {
+    store: Rc<RefCell<Store>>,
+    systems: RefCell<Vec<Rc<System>>>,
+    observers: RefCell<Vec<Box<dyn Observer>>>,
+    step: Cell<u64>,
+}
+
+impl Default for ColumnarModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ColumnarModel {
+    pub fn new() -> Self {
+        Self {
+            store: Rc::new(RefCell::new(Store::default())),
+            systems: RefCell::new(Vec::new()),
+            observers: RefCell::new(Vec::new()),
+            step: Cell::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.borrow().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_current_step(&self) -> u64 {
+        self.step.get()
+    }
+
+    pub fn register_entity_type(&self, entity_type: EntityType, parent: EntityType) -> Result<(), ModelError> {
+        self.store.borrow_mut().type_registry.register(entity_type, parent).map_err(ModelError::InvalidTypeHierarchy)
+    }
+
+    pub fn create_entity(&self, name: String, entity_type: EntityType) -> Uuid {
+        let mut store = self.store.borrow_mut();
+        let index = store.allocate(name, entity_type);
+        store.ids[index as usize]
+    }
+
+    // 列の型に変換できない値があれば作成しない
+    pub fn create_entity_with_state(&self, name: String, entity_type: EntityType, initial_state: HashMap<String, Value>) -> Result<Uuid, ModelError> {
+        let mut store = self.store.borrow_mut();
+        let index = store.allocate(name, entity_type);
+        for (key, value) in initial_state {
+            if let Err(error) = store.set_state(index, Symbol::new(&key), value) {
+                store.release(index);
+                return Err(ModelError::InvalidValue(error));
+            }
+        }
+        Ok(store.ids[index as usize])
+    }
+
+    pub fn delete_entity(&self, id: &Uuid) -> Result<(), ModelError> {
+        let mut store = self.store.borrow_mut();
+        let index = *store.by_id.get(id).ok_or(ModelError::EntityNotFound(*id))?;
+        store.release(index);
+        Ok(())
+    }
+
+    pub fn handle(&self, id: &Uuid) -> Option<EntityHandle> {
+        let store = self.store.borrow();
+        let index = *store.by_id.get(id)?;
+        Some(EntityHandle { index, generation: store.generations[index as usize] })
+    }
+
+    // 世代が一致しなければ、スロットは別のエンティティに再利用されている
+    pub fn id_of(&self, handle: EntityHandle) -> Option<Uuid> {
+        let store = self.store.borrow();
+        let i = handle.index as usize;
+        (store.alive.get(i) == Some(&true) && store.generations[i] == handle.generation).then(|| store.ids[i])
+    }
+
+    pub fn handles(&self) -> Vec<EntityHandle> {
+        let store = self.store.borrow();
+        store.alive_indices()
+            .map(|index| EntityHandle { index, generation: store.generations[index as usize] })
+            .collect()
+    }
+
+    pub fn set_state(&self, id: &Uuid, key: impl Into<Symbol>, value: Value) -> Result<(), ModelError> {
+        let mut store = self.store.borrow_mut();
+        let index = *store.by_id.get(id).ok_or(ModelError::EntityNotFound(*id))?;
+        store.set_state(index, key.into(), value).map_err(ModelError::InvalidValue)
+    }
+
+    pub fn get_state<K: AsSymbol + ?Sized>(&self, id: &Uuid, key: &K) -> Option<Value> {
+        let store = self.store.borrow();
+        store.state_value(*store.by_id.get(id)?, key)
+    }
+
+    pub fn remove_state<K: AsSymbol + ?Sized>(&self, id: &Uuid, key: &K) -> Result<(), ModelError> {
+        let mut store = self.store.borrow_mut();
+        let index = *store.by_id.get(id).ok_or(ModelError::EntityNotFound(*id))?;
+        store.remove_state(index, key);
+        Ok(())
+    }
+
+    // 列は EntityHandle::index で引く。削除済みのスロットは None になっている
+    pub fn column(&self, key: &str) -> Option<Ref<'_, Column>> {
+        Ref::filter_map(self.store.borrow(), |store| {
+            store.column_of(key).map(|key_id| &store.columns[key_id])
+        }).ok()
+    }
+
+    // 結果を経由せずに列を直接書き換える。長さは変えられない
+    pub fn column_mut(&self, key: &str) -> Option<RefMut<'_, Column>> {
+        RefMut::filter_map(self.store.borrow_mut(), |store| {
+            let key_id = store.column_of(key)?;
+            Some(&mut store.columns[key_id])
+        }).ok()
+    }
+
+    pub fn state_keys(&self) -> Vec<Symbol> {
+        self.store.borrow().column_keys.clone()
+    }
+
+    pub fn add_relation(&self, name: impl Into<Symbol>, relation_type: RelationType, source: &Uuid, target: &Uuid) -> Result<Uuid, ModelError> {
+        let mut store = self.store.borrow_mut();
+        let source = *store.by_id.get(source).ok_or(ModelError::EntityNotFound(*source))?;
+        let target = *store.by_id.get(target).ok_or(ModelError::EntityNotFound(*target))?;
+        Ok(store.add_edge(name.into(), relation_type, source, target))
+    }
+
+    pub fn remove_relation(&self, id: &Uuid) -> Result<(), ModelError> {
+        let mut store = self.store.borrow_mut();
+        let (name, edge_index) = store.relation_ids.remove(id).ok_or(ModelError::RelationNotFound(*id))?;
+        if let Some(adjacency) = store.relations.get_mut(&name) {
+            adjacency.remove_edge(edge_index);
+        }
+        Ok(())
+    }
+
+    pub fn outgoing(&self, relation_name: &str, id: &Uuid) -> Vec<Uuid> {
+        let store = self.store.borrow();
+        let (Some(index), Some(adjacency)) = (store.by_id.get(id), store.adjacency(relation_name)) else {
+            return Vec::new();
+        };
+        adjacency.outgoing.get(*index as usize)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| adjacency.edges[*e as usize].as_ref())
+            .map(|edge| store.ids[edge.target as usize])
+            .collect()
+    }
+
+    pub fn incoming(&self, relation_name: &str, id: &Uuid) -> Vec<Uuid> {
+        let store = self.store.borrow();
+        let (Some(index), Some(adjacency)) = (store.by_id.get(id), store.adjacency(relation_name)) else {
+            return Vec::new();
+        };
+        adjacency.incoming.get(*index as usize)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| adjacency.edges[*e as usize].as_ref())
+            .map(|edge| store.ids[edge.source as usize])
+            .collect()
+    }
+
+    pub fn add_system(&self, system: System) -> Rc<System> {
+        let system = Rc::new(system);
+        let mut systems = self.systems.borrow_mut();
+        match systems.iter().position(|s| s.name == system.name) {
+            Some(index) => systems[index] = Rc::clone(&system),
+            None => systems.push(Rc::clone(&system)),
+        }
+        system
+    }
+
+    pub fn remove_system(&self, name: &str) -> Option<Rc<System>> {
+        let mut systems = self.systems.borrow_mut();
+        let index = systems.iter().position(|s| s.name == name)?;
+        Some(systems.remove(index))
+    }
+
+    // Model と同じく、登録時点の状態をすぐに観測させる
+    pub fn add_observer(&self, mut observer: Box<dyn Observer>) {
+        observer.observe(self.step.get(), self);
+        self.observers.borrow_mut().push(observer);
+    }
+
+    // 結果の適用に失敗したステップは何も書き込まず、ステップも進めない
+    pub fn simulate(&self) -> Result<(), ModelError> {
+        let mut results = Vec::new();
+        let systems = self.systems.borrow().clone();
+        for system in systems {
+            system.run(self, &mut results);
+        }
+        self.apply_results(results)?;
+
+        self.step.set(self.step.get() + 1);
+        let step = self.step.get();
+        for observer in self.observers.borrow_mut().iter_mut() {
+            observer.observe(step, self);
+        }
+        Ok(())
+    }
+
+    // 状態と関係に関する結果のみ扱う。関数やプロセス、位置に関する結果や、列の型に変換できない値が
+    // 含まれていれば何も適用せずに拒否する。存在しない対象への結果は無視する
+    pub fn apply_results(&self, results: Vec<ExecutionResult>) -> Result<(), ModelError> {
+        if let Some(result) = results.iter().find(|result| !result.is_state_or_relation()) {
+            return Err(ModelError::UnsupportedResult(result.kind()));
+        }
+        self.check_values(&results)?;
+        let mut first_error = None;
+        for result in results {
+            let applied = match result {
+                ExecutionResult::UpdateEntityState(id, key, value) => self.set_state(&id, key, value),
+                ExecutionResult::DeleteEntityState(id, key) => self.remove_state(&id, &key),
+                ExecutionResult::CreateEntity(info) => {
+                    self.create_entity_with_state(info.name, info.entity_type, info.initial_state).map(|id| {
+                        for relation_info in info.relations {
+                            self.create_relation_internal(relation_info, Some(id));
+                        }
+                    })
+                }
+                ExecutionResult::DeleteEntity(id) => self.delete_entity(&id),
+                ExecutionResult::CreateRelation(info) => {
+                    self.create_relation_internal(info, None);
+                    Ok(())
+                }
+                ExecutionResult::DeleteRelation(id) => self.remove_relation(&id),
+                ExecutionResult::AddRelationMetadata(id, key, value) => {
+                    if let Some(edge) = self.store.borrow_mut().edge_mut(&id) {
+                        edge.meta.insert(key, value);
+                    }
+                    Ok(())
+                }
+                ExecutionResult::RemoveRelationMetadata(id, key) => {
+                    if let Some(edge) = self.store.borrow_mut().edge_mut(&id) {
+                        edge.meta.remove(&key);
+                    }
+                    Ok(())
+                }
+                other => Err(ModelError::UnsupportedResult(other.kind())),
+            };
+            if let Err(error @ (ModelError::InvalidValue(_) | ModelError::UnsupportedResult(_))) = applied {
+                first_error.get_or_insert(error);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    fn check_values(&self, results: &[ExecutionResult]) -> Result<(), ModelError> {
+        let store = self.store.borrow();
+        let mut pending = HashMap::new();
+        for result in results {
+            match result {
+                ExecutionResult::UpdateEntityState(id, key, value) if store.by_id.contains_key(id) => {
+                    store.check_state(&mut pending, *key, value).map_err(ModelError::InvalidValue)?;
+                }
+                ExecutionResult::CreateEntity(info) => {
+                    for (key, value) in &info.initial_state {
+                        store.check_state(&mut pending, Symbol::new(key), value).map_err(ModelError::InvalidValue)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // 端点の解決は Model と同じ規則に従う
+    fn create_relation_internal(&self, info: RelationCreationInfo, source_entity_id: Option<Uuid>) {
+        let Some(source) = source_entity_id.or(info.target_entity_id) else {
+            return;
+        };
+        let target = info.target_entity_id.or_else(|| {
+            let store = self.store.borrow();
+            let index = store.first_by_name(info.target_entity_name.as_ref()?)?;
+            Some(store.ids[index as usize])
+        });
+        let Some(id) = target.and_then(|target| self.add_relation(&info.name, info.relation_type, &source, &target).ok()) else {
+            return;
+        };
+        if let Some(metadata) = info.metadata {
+            if let Some(edge) = self.store.borrow_mut().edge_mut(&id) {
+                edge.meta.extend(metadata.into_iter().map(|(key, value)| (Symbol::new(&key), value)));
+            }
+        }
+    }
+
+    fn views(&self, indices: impl Iterator<Item = u32>) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        indices.map(|index| ColumnarEntity::new(&self.store, index) as Rc<dyn ReadOnlyEntity>).collect()
+    }
+}
+
+// 読み取り時に列から組み立てるエンティティのビュー
+struct ColumnarEntity {
+    store: Rc<RefCell<Store>>,
+    index: u32,
+    id: Uuid,
+    name: String,
+    entity_type: EntityType,
+    state: OnceCell<RefCell<Variable>>,
+}
+
+impl ColumnarEntity {
+    fn new(store: &Rc<RefCell<Store>>, index: u32) -> Rc<Self> {
+        let s = store.borrow();
+        let i = index as usize;
+        Rc::new(Self {
+            store: Rc::clone(store),
+            index,
+            id: s.ids[i],
+            name: s.names[i].clone(),
+            entity_type: s.types[i].clone(),
+            state: OnceCell::new(),
+        })
+    }
+}
+
+impl ReadOnlyEntity for ColumnarEntity {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_entity_type(&self) -> &EntityType {
+        &self.entity_type
+    }
+
+    // 状態は最初に参照されたときに列から組み立てる
+    fn get_state(&self) -> Ref<'_, Variable> {
+        self.state.get_or_init(|| RefCell::new(self.store.borrow().state_of(self.index))).borrow()
+    }
+
+    fn get_state_value(&self, key: &str) -> Option<Value> {
+        match self.state.get() {
+            Some(state) => state.borrow().get(key).cloned(),
+            None => self.store.borrow().state_value(self.index, key),
+        }
+    }
+
+    // 関数は持たない
+    fn get_function(&self, _name: &str) -> Option<Rc<dyn ReadOnlyFunction>> {
+        None
+    }
+
+    fn get_relations(&self, name: &str) -> Vec<Rc<dyn ReadOnlyRelation>> {
+        let store = self.store.borrow();
+        let Some(name) = name.as_symbol() else {
+            return Vec::new();
+        };
+        let Some(adjacency) = store.relations.get(&name) else {
+            return Vec::new();
+        };
+        adjacency.edges_of(self.index)
+            .filter_map(|e| adjacency.edges[e as usize].as_ref())
+            .map(|edge| ColumnarRelation::new(&self.store, name, edge) as Rc<dyn ReadOnlyRelation>)
+            .collect()
+    }
+}
+
+struct ColumnarRelation {
+    store: Rc<RefCell<Store>>,
+    id: Uuid,
+    name: Symbol,
+    relation_type: RelationType,
+    source: u32,
+    target: u32,
+}
+
+impl ColumnarRelation {
+    fn new(store: &Rc<RefCell<Store>>, name: Symbol, edge: &Edge) -> Rc<Self> {
+        Rc::new(Self {
+            store: Rc::clone(store),
+            id: edge.id,
+            name,
+            relation_type: edge.relation_type,
+            source: edge.source,
+            target: edge.target,
+        })
+    }
+}
+
+impl ReadOnlyRelation for ColumnarRelation {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+
+    fn get_name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn get_relation_type(&self) -> &RelationType {
+        &self.relation_type
+    }
+
+    fn get_entity1(&self) -> Option<Rc<dyn ReadOnlyEntity>> {
+        Some(ColumnarEntity::new(&self.store, self.source) as Rc<dyn ReadOnlyEntity>)
+    }
+
+    fn get_entity2(&self) -> Option<Rc<dyn ReadOnlyEntity>> {
+        Some(ColumnarEntity::new(&self.store, self.target) as Rc<dyn ReadOnlyEntity>)
+    }
+
+    fn get_meta_value(&self, key: &str) -> Option<Value> {
+        self.store.borrow().edge(&self.id).and_then(|(_, edge)| edge.meta.get(&key.as_symbol()?).cloned())
+    }
+
+    fn iter_meta(&self) -> HashMap<String, Value> {
+        self.store.borrow().edge(&self.id).map(|(_, edge)| edge.meta.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()).unwrap_or_default()
+    }
+}
+
+impl ReadOnlyModel for ColumnarModel {
+    fn get_entity(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyEntity>> {
+        let index = *self.store.borrow().by_id.get(id)?;
+        Some(ColumnarEntity::new(&self.store, index) as Rc<dyn ReadOnlyEntity>)
+    }
+
+    fn get_all_entities(&self) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let indices: Vec<u32> = self.store.borrow().alive_indices().collect();
+        self.views(indices.into_iter())
+    }
+
+    fn get_entities_by_type(&self, entity_type: &EntityType) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let indices: Vec<u32> = {
+            let store = self.store.borrow();
+            let subtypes = store.type_registry.subtypes(entity_type);
+            store.alive_indices().filter(|i| subtypes.contains(&store.types[*i as usize])).collect()
+        };
+        self.views(indices.into_iter())
+    }
+
+    fn get_entities_by_name(&self, name: &str) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let mut indices: Vec<u32> = self.store.borrow().by_name.get(name).into_iter().flatten().copied().collect();
+        indices.sort_unstable();
+        self.views(indices.into_iter())
+    }
+
+    fn get_entities_by_state(&self, key: &str, value: &Value) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let indices: Vec<u32> = {
+            let store = self.store.borrow();
+            let Some(key_id) = store.column_of(key) else {
+                return Vec::new();
+            };
+            let column = &store.columns[key_id];
+            store.alive_indices().filter(|i| column.get(*i as usize).as_ref() == Some(value)).collect()
+        };
+        self.views(indices.into_iter())
+    }
+
+    fn has_state_index(&self, _key: &str) -> bool {
+        false
+    }
+
+    fn get_lattice(&self, _relation_name: &str) -> Option<Rc<Lattice>> {
+        None
+    }
+
+    fn get_space(&self) -> Option<Ref<'_, Space>> {
+        None
+    }
+
+    fn get_context(&self, _name: &str) -> Option<ContextDefinition> {
+        None
+    }
+
+    fn get_active_context(&self, _entity_id: &Uuid) -> Option<String> {
+        None
+    }
+
+    fn get_current_time(&self) -> Option<NaiveDateTime> {
+        None
+    }
+
+    fn get_timetable(&self, _entity_id: &Uuid) -> Option<Timetable> {
+        None
+    }
+
+    fn get_schema(&self, _entity_type: &EntityType) -> Option<Rc<StateSchema>> {
+        None
+    }
+
+    fn is_subtype(&self, entity_type: &EntityType, ancestor: &EntityType) -> bool {
+        self.store.borrow().type_registry.is_subtype(entity_type, ancestor)
+    }
+
+    fn get_subtypes(&self, entity_type: &EntityType) -> Vec<EntityType> {
+        self.store.borrow().type_registry.subtypes(entity_type)
+    }
+
+    fn get_base_type(&self, entity_type: &EntityType) -> EntityType {
+        self.store.borrow().type_registry.base_type(entity_type)
+    }
+
+    fn get_relation(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyRelation>> {
+        let store = self.store.borrow();
+        let (name, edge) = store.edge(id)?;
+        Some(ColumnarRelation::new(&self.store, name, edge) as Rc<dyn ReadOnlyRelation>)
+    }
+
+    fn get_all_relations(&self) -> Vec<Rc<dyn ReadOnlyRelation>> {
+        let store = self.store.borrow();
+        store.relations
+            .iter()
+            .flat_map(|(name, adjacency)| adjacency.edges.iter().flatten().map(move |edge| (name, edge)))
+            .map(|(name, edge)| ColumnarRelation::new(&self.store, *name, edge) as Rc<dyn ReadOnlyRelation>)
+            .collect()
+    }
+
+    fn read_column(&self, key: &str, ids: &[Uuid]) -> Option<Vec<Option<Value>>> {
+        let store = self.store.borrow();
+        let column = store.column_of(key).map(|key_id| &store.columns[key_id]);
+        Some(ids.iter().map(|id| column?.get(*store.by_id.get(id)? as usize)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::EntityCreationInfo;
+
+    #[test]
+    fn columns_coerce_values_to_their_type() {
+        let mut column = Column::for_value(&Value::Integer(1), 3);
+        column.set(0, Value::Integer(1)).unwrap();
+        column.set(1, Value::Int64(2)).unwrap();
+        assert!(column.set(2, Value::Float64(0.5)).is_err());
+        assert!(column.set(2, Value::Int64(i64::MAX)).is_err());
+        assert_eq!(column.as_integer(), Some(&[Some(1), Some(2), None][..]));
+
+        column.set(0, Value::Null).unwrap();
+        assert_eq!(column.get(0), None);
+
+        let mut column = Column::for_value(&Value::Float64(0.0), 1);
+        column.set(0, Value::Integer(3)).unwrap();
+        assert_eq!(column.get(0), Some(Value::Float64(3.0)));
+
+        let mut column = Column::for_value(&Value::String("a".to_string()), 1);
+        column.set(0, Value::Integer(3)).unwrap();
+        assert_eq!(column.get(0), Some(Value::Integer(3)));
+    }
+
+    #[test]
+    fn mismatched_state_is_rejected() {
+        let model = ColumnarModel::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        model.set_state(&a, "sick", Value::Boolean(true)).unwrap();
+        assert!(matches!(model.set_state(&a, "sick", Value::Integer(1)), Err(ModelError::InvalidValue(_))));
+        assert_eq!(model.get_state(&a, "sick"), Some(Value::Boolean(true)));
+
+        let state = HashMap::from([("sick".to_string(), Value::String("yes".to_string()))]);
+        assert!(model.create_entity_with_state("b".to_string(), EntityType::Agent, state).is_err());
+        assert_eq!(model.len(), 1);
+        assert!(model.get_entities_by_name("b").is_empty());
+    }
+
+    #[test]
+    fn unsupported_results_are_rejected_before_applying() {
+        let model = ColumnarModel::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let results = vec![
+            ExecutionResult::update_state(a, "age", Value::Integer(1)),
+            ExecutionResult::remove_function(a, "f"),
+        ];
+        assert!(matches!(model.apply_results(results), Err(ModelError::UnsupportedResult("RemoveFunction"))));
+        assert_eq!(model.get_state(&a, "age"), None);
+
+        model.add_system(System::for_type("grow", EntityType::Agent, move |_, results| {
+            results.push(ExecutionResult::SetPosition(a, crate::space::Position::new(0.0, 0.0)));
+        }));
+        assert!(model.simulate().is_err());
+        assert_eq!(model.get_current_step(), 0);
+    }
+
+    #[test]
+    fn invalid_values_reject_the_whole_batch() {
+        let model = ColumnarModel::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        model.set_state(&a, "age", Value::Integer(1)).unwrap();
+        let create = || ExecutionResult::CreateEntity(EntityCreationInfo {
+            name: "b".to_string(),
+            entity_type: EntityType::Agent,
+            initial_state: HashMap::new(),
+            functions: Vec::new(),
+            relations: Vec::new(),
+        });
+        let results = vec![
+            ExecutionResult::update_state(a, "height", Value::Float64(1.5)),
+            create(),
+            ExecutionResult::update_state(a, "age", Value::Boolean(true)),
+        ];
+        assert!(matches!(model.apply_results(results), Err(ModelError::InvalidValue(_))));
+        assert_eq!(model.get_state(&a, "age"), Some(Value::Integer(1)));
+        assert_eq!(model.get_state(&a, "height"), None);
+        assert_eq!(model.len(), 1);
+
+        // 同じバッチで作られる列の型とも照合する
+        let results = vec![
+            ExecutionResult::update_state(a, "height", Value::Float64(1.5)),
+            ExecutionResult::update_state(a, "height", Value::String("tall".to_string())),
+        ];
+        assert!(model.apply_results(results).is_err());
+        assert!(model.state_keys().iter().all(|key| key.as_str() != "height"));
+
+        let results = vec![ExecutionResult::update_state(a, "age", Value::Int64(2)), create()];
+        model.apply_results(results).unwrap();
+        assert_eq!(model.get_state(&a, "age"), Some(Value::Integer(2)));
+        assert_eq!(model.len(), 2);
+    }
+
+    #[test]
+    fn rejected_steps_can_be_retried() {
+        let model = ColumnarModel::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        model.set_state(&a, "count", Value::Integer(0)).unwrap();
+        let broken = Rc::new(Cell::new(true));
+        let flag = Rc::clone(&broken);
+        let query = crate::query::EntityQuery::new().of_type(EntityType::Agent);
+        model.add_system(System::per_entity("count", query, move |entity, _, results| {
+            let count = i32::try_from(&entity.get_state_value("count").unwrap()).unwrap();
+            results.push(ExecutionResult::update_state(entity.get_id(), "count", Value::Integer(count + 1)));
+            if flag.get() {
+                results.push(ExecutionResult::update_state(entity.get_id(), "count", Value::Boolean(true)));
+            }
+        }));
+
+        assert!(matches!(model.simulate(), Err(ModelError::InvalidValue(_))));
+        assert_eq!(model.get_current_step(), 0);
+        assert_eq!(model.get_state(&a, "count"), Some(Value::Integer(0)));
+
+        broken.set(false);
+        model.simulate().unwrap();
+        assert_eq!(model.get_current_step(), 1);
+        assert_eq!(model.get_state(&a, "count"), Some(Value::Integer(1)));
+    }
+
+    #[test]
+    fn state_values_are_read_from_columns() {
+        let model = ColumnarModel::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        model.set_state(&a, "age", Value::Integer(1)).unwrap();
+        let view = model.get_entity(&a).unwrap();
+        assert_eq!(view.get_state_value("age"), Some(Value::Integer(1)));
+        assert_eq!(view.get_state_value("height"), None);
+
+        // 状態を組み立てた後のビューはその時点の値を返し続ける
+        assert_eq!(view.get_state().get("age"), Some(&Value::Integer(1)));
+        model.set_state(&a, "age", Value::Integer(2)).unwrap();
+        assert_eq!(view.get_state_value("age"), Some(Value::Integer(1)));
+        assert_eq!(model.get_entity(&a).unwrap().get_state_value("age"), Some(Value::Integer(2)));
+    }
+
+    #[test]
+    fn names_resolve_after_slots_are_reused() {
+        let model = ColumnarModel::new();
+        let first = model.create_entity("x".to_string(), EntityType::Agent);
+        let second = model.create_entity("x".to_string(), EntityType::Agent);
+        let ids: Vec<Uuid> = model.get_entities_by_name("x").iter().map(|e| e.get_id()).collect();
+        assert_eq!(ids, vec![first, second]);
+
+        let handle = model.handle(&first).unwrap();
+        model.delete_entity(&first).unwrap();
+        assert_eq!(model.id_of(handle), None);
+        let third = model.create_entity("y".to_string(), EntityType::Agent);
+        assert_eq!(model.handle(&third).unwrap().index, handle.index);
+        assert_eq!(model.get_entities_by_name("x").len(), 1);
+
+        model.apply_results(vec![ExecutionResult::CreateEntity(EntityCreationInfo {
+            name: "n".to_string(),
+            entity_type: EntityType::Agent,
+            initial_state: HashMap::new(),
+            functions: Vec::new(),
+            relations: vec![RelationCreationInfo {
+                name: "knows".to_string(),
+                relation_type: RelationType::OneToOne,
+                target_entity_id: None,
+                target_entity_name: Some("x".to_string()),
+                metadata: None,
+            }],
+        })]).unwrap();
+        let n = model.get_entities_by_name("n")[0].get_id();
+        assert_eq!(model.outgoing("knows", &n), vec![second]);
+    }
+
+    #[test]
+    fn removed_edge_slots_are_reused() {
+        let model = ColumnarModel::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        let first = model.add_relation("knows", RelationType::ManyToMany, &a, &b).unwrap();
+        let mut stale = Uuid::nil();
+        for _ in 0..10 {
+            stale = model.add_relation("knows", RelationType::ManyToMany, &b, &a).unwrap();
+            model.remove_relation(&stale).unwrap();
+        }
+        let c = model.create_entity("c".to_string(), EntityType::Agent);
+        model.add_relation("knows", RelationType::ManyToMany, &a, &c).unwrap();
+        model.delete_entity(&c).unwrap();
+        let last = model.add_relation("knows", RelationType::ManyToMany, &b, &a).unwrap();
+
+        let store = model.store.borrow();
+        assert_eq!(store.relations[&Symbol::new("knows")].edges.len(), 2);
+        assert_eq!(store.relation_ids.len(), 2);
+        drop(store);
+        // 再利用されたスロットでも、古い ID は引けず新しい ID だけが引ける
+        assert!(model.get_relation(&stale).is_none());
+        assert!(matches!(model.remove_relation(&stale), Err(ModelError::RelationNotFound(_))));
+        assert!(model.remove_relation(&first).is_ok());
+        assert_eq!(model.incoming("knows", &a), vec![b]);
+        assert_eq!(model.get_relation(&last).unwrap().get_entity1().unwrap().get_id(), b);
+    }
+
+    #[test]
+    fn observers_see_the_initial_step() {
+        #[derive(Default)]
+        struct Steps(Vec<u64>);
+        impl Observer for Steps {
+            fn observe(&mut self, step: u64, _model: &dyn ReadOnlyModel) {
+                self.0.push(step);
+            }
+        }
+
+        let model = ColumnarModel::new();
+        let steps = Rc::new(RefCell::new(Steps::default()));
+        model.add_observer(Box::new(Rc::clone(&steps)));
+        model.simulate().unwrap();
+        assert_eq!(steps.borrow().0, vec![0, 1]);
+    }
+}
@@ -4,12 +4,22 @@ use std::collections::HashMap;
 use uuid::Uuid;
 use crate::types::{EntityType, RelationType};
 use crate::variable::{Variable, Value};
+use crate::lattice::Lattice;
+use crate::space::{Position, Space};
+use crate::multiplex::ContextDefinition;
+use crate::schedule::Timetable;
+use crate::schema::StateSchema;
+use chrono::NaiveDateTime;
 
 pub trait ReadOnlyEntity {
     fn get_id(&self) -> Uuid;
     fn get_name(&self) -> &str;
     fn get_entity_type(&self) -> &EntityType;
-    fn get_state(&self) -> Ref<Variable>;
+    fn get_state(&self) -> Ref<'_, Variable>;
+    // 一つのキーだけを読む。状態全体を組み立てずに読める実装は上書きする
+    fn get_state_value(&self, key: &str) -> Option<Value> {
+        self.get_state().get(key).cloned()
+    }
     fn get_function(&self, name: &str) -> Option<Rc<dyn ReadOnlyFunction>>;
     fn get_relations(&self, name: &str) -> Vec<Rc<dyn ReadOnlyRelation>>;
 }
@@ -35,12 +45,115 @@ pub trait ReadOnlyModel {
     fn get_all_entities(&self) -> Vec<Rc<dyn ReadOnlyEntity>>;
     fn get_entities_by_type(&self, entity_type: &EntityType) -> Vec<Rc<dyn ReadOnlyEntity>>;
     fn get_entities_by_name(&self, name: &str) -> Vec<Rc<dyn ReadOnlyEntity>>;
+    fn get_entities_by_state(&self, key: &str, value: &Value) -> Vec<Rc<dyn ReadOnlyEntity>>;
+    fn has_state_index(&self, key: &str) -> bool;
+    fn get_lattice(&self, relation_name: &str) -> Option<Rc<Lattice>>;
+    fn get_space(&self) -> Option<Ref<'_, Space>>;
+    fn get_context(&self, name: &str) -> Option<ContextDefinition>;
+    fn get_active_context(&self, entity_id: &Uuid) -> Option<String>;
+    fn get_current_time(&self) -> Option<NaiveDateTime>;
+    fn get_timetable(&self, entity_id: &Uuid) -> Option<Timetable>;
+    fn get_schema(&self, entity_type: &EntityType) -> Option<Rc<StateSchema>>;
+    fn is_subtype(&self, entity_type: &EntityType, ancestor: &EntityType) -> bool;
+    fn get_subtypes(&self, entity_type: &EntityType) -> Vec<EntityType>;
+    fn get_base_type(&self, entity_type: &EntityType) -> EntityType;
     fn get_relation(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyRelation>>;
     fn get_all_relations(&self) -> Vec<Rc<dyn ReadOnlyRelation>>;
+
+    // エンティティの追加・削除や型の登録で変わる番号。System はこれが変わるまで一致したエンティティを
+    // 使い回す。返したビューを次のステップで使えない実装は None を返す
+    fn entity_set_version(&self) -> Option<u64> {
+        None
+    }
+
+    // ids と同じ順序で状態 key の値を並べる。列を直接読める実装だけが Some を返す
+    fn read_column(&self, _key: &str, _ids: &[Uuid]) -> Option<Vec<Option<Value>>> {
+        None
+    }
 }
 
 pub struct ExecutionContext<'a> {
     pub owner_function: &'a dyn ReadOnlyFunction,
     pub owner_entity: &'a dyn ReadOnlyEntity,
     pub model: &'a dyn ReadOnlyModel,
-}
\ No newline at end of file
+}
+
+// 所有エンティティの位置を中心とした空間検索。結果に自身は含まない
+impl ExecutionContext<'_> {
+    pub fn position(&self) -> Option<Position> {
+        self.model.get_space()?.position(&self.owner_entity.get_id())
+    }
+
+    pub fn within_radius(&self, radius: f64) -> Vec<(Uuid, f64)> {
+        let Some(space) = self.model.get_space() else {
+            return Vec::new();
+        };
+        let id = self.owner_entity.get_id();
+        match space.position(&id) {
+            Some(center) => space.within_radius(&center, radius).into_iter().filter(|(other, _)| *other != id).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn nearest(&self, k: usize) -> Vec<(Uuid, f64)> {
+        let Some(space) = self.model.get_space() else {
+            return Vec::new();
+        };
+        let id = self.owner_entity.get_id();
+        match space.position(&id) {
+            Some(center) => space.nearest(&center, k + 1).into_iter().filter(|(other, _)| *other != id).take(k).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::model::Model;
+
+    #[test]
+    fn spatial_helpers_exclude_the_owner() {
+        let model = Model::new();
+        model.enable_space(2, 1.0).unwrap();
+        let owner = model.create_entity("owner".to_string(), EntityType::Agent);
+        let near = model.create_entity("near".to_string(), EntityType::Agent);
+        let far = model.create_entity("far".to_string(), EntityType::Agent);
+        model.set_position(&owner.id, Position::new(0.0, 0.0)).unwrap();
+        model.set_position(&near.id, Position::new(1.0, 0.0)).unwrap();
+        model.set_position(&far.id, Position::new(5.0, 0.0)).unwrap();
+
+        let function = Function::new("f", Rc::downgrade(&owner));
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*owner, model: &model };
+        assert_eq!(context.position(), Some(Position::new(0.0, 0.0)));
+        assert_eq!(context.within_radius(2.0), vec![(near.id, 1.0)]);
+        let nearest: Vec<Uuid> = context.nearest(2).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(nearest, vec![near.id, far.id]);
+    }
+
+    #[test]
+    fn spatial_helpers_are_empty_without_a_position() {
+        let model = Model::new();
+        let owner = model.create_entity("owner".to_string(), EntityType::Agent);
+        let function = Function::new("f", Rc::downgrade(&owner));
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*owner, model: &model };
+        assert_eq!(context.position(), None);
+        assert!(context.within_radius(10.0).is_empty());
+
+        model.enable_space(2, 1.0).unwrap();
+        assert!(context.nearest(3).is_empty());
+    }
+
+    #[test]
+    fn state_values_default_to_the_state() {
+        let model = Model::new();
+        let entity = model.create_entity("a".to_string(), EntityType::Agent);
+        entity.get_state().borrow_mut().set("age", Value::Integer(3));
+        let view: &dyn ReadOnlyEntity = &*entity;
+        assert_eq!(view.get_state_value("age"), Some(Value::Integer(3)));
+        assert_eq!(view.get_state_value("height"), None);
+        let model: &dyn ReadOnlyModel = &model;
+        assert_eq!(model.read_column("age", &[entity.id]), None);
+    }
+}
@@ -3,6 +3,7 @@ use std::rc::{Rc, Weak};
 use std::cell::{Ref, RefCell};
 use uuid::Uuid;
 use crate::relation::Relation;
+use crate::symbol::{AsSymbol, Symbol};
 use crate::types::EntityType;
 use crate::variable::Variable;
 use crate::function::Function;
@@ -14,8 +15,8 @@ pub struct Entity {
     pub name: String,
     pub entity_type: EntityType,
     pub state: RefCell<Variable>,
-    pub functions: RefCell<HashMap<String, Rc<Function>>>,
-    pub relations: RefCell<HashMap<String, Vec<Weak<Relation>>>>,
+    pub functions: RefCell<HashMap<Symbol, Rc<Function>>>,
+    pub relations: RefCell<HashMap<Symbol, Vec<Weak<Relation>>>>,
 }
 
 impl Entity {
@@ -34,27 +35,46 @@ impl Entity {
         &self.state
     }
 
-    pub fn add_function(&self, function: Rc<Function>) {
-        self.functions.borrow_mut().insert(function.name.clone(), function);
+    // パラメータの変更は状態と同じくモデルに知らせる
+    pub fn add_function(&self, function: Rc<Function>) -> Option<Rc<Function>> {
+        function.parameter.borrow_mut().share_watch(&self.state.borrow());
+        self.functions.borrow_mut().insert(function.name, function)
     }
 
-    pub fn get_function(&self, name: &str) -> Option<Rc<Function>> {
-        self.functions.borrow().get(name).cloned()
+    pub fn get_function<K: AsSymbol + ?Sized>(&self, name: &K) -> Option<Rc<Function>> {
+        self.functions.borrow().get(&name.as_symbol()?).cloned()
     }
 
     pub fn get_all_functions(&self) -> Vec<Rc<Function>> {
         self.functions.borrow().values().cloned().collect()
     }
 
-    pub fn remove_function(&self, name: &str) -> Option<Rc<Function>> {
-        self.functions.borrow_mut().remove(name)
+    pub fn remove_function<K: AsSymbol + ?Sized>(&self, name: &K) -> Option<Rc<Function>> {
+        self.functions.borrow_mut().remove(&name.as_symbol()?)
     }
 
     pub fn get_relations(&self, name: &str) -> Vec<Rc<Relation>> {
+        let Some(name) = name.as_symbol() else {
+            return Vec::new();
+        };
         self.relations.borrow()
-            .get(name)
+            .get(&name)
             .map(|vec| vec.iter().filter_map(Weak::upgrade).collect())
-            .unwrap_or_else(Vec::new)
+            .unwrap_or_default()
+    }
+
+    pub fn get_outgoing_relations(&self, name: &str) -> Vec<Rc<Relation>> {
+        self.get_relations(name)
+            .into_iter()
+            .filter(|r| r.entity1.upgrade().map(|e| e.id) == Some(self.id))
+            .collect()
+    }
+
+    pub fn get_incoming_relations(&self, name: &str) -> Vec<Rc<Relation>> {
+        self.get_relations(name)
+            .into_iter()
+            .filter(|r| r.entity2.upgrade().map(|e| e.id) == Some(self.id))
+            .collect()
     }
 
     pub fn get_all_relations(&self) -> Vec<Rc<Relation>> {
@@ -65,19 +85,19 @@ impl Entity {
             .collect()
     }
 
-    pub(crate) fn add_relation(&self, name: String, relation: Weak<Relation>) {
+    pub(crate) fn add_relation(&self, name: Symbol, relation: Weak<Relation>) {
         self.relations.borrow_mut()
             .entry(name)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(relation);
     }
 
-    pub(crate) fn remove_relation(&self, name: &str, relation_id: Uuid) {
+    pub(crate) fn remove_relation(&self, name: Symbol, relation_id: Uuid) {
         let mut relations = self.relations.borrow_mut();
-        if let Some(rel_vec) = relations.get_mut(name) {
+        if let Some(rel_vec) = relations.get_mut(&name) {
             rel_vec.retain(|r| r.upgrade().map(|rc| rc.id) != Some(relation_id));
             if rel_vec.is_empty() {
-                relations.remove(name);
+                relations.remove(&name);
             }
         }
     }
@@ -96,7 +116,7 @@ impl ReadOnlyEntity for Entity {
         &self.entity_type
     }
 
-    fn get_state(&self) -> Ref<Variable> {
+    fn get_state(&self) -> Ref<'_, Variable> {
         self.state.borrow()
     }
 
@@ -107,4 +127,60 @@ impl ReadOnlyEntity for Entity {
     fn get_relations(&self, name: &str) -> Vec<Rc<dyn ReadOnlyRelation>> {
         self.get_relations(name).into_iter().map(|r| r as Rc<dyn ReadOnlyRelation>).collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::types::RelationType;
+
+    #[test]
+    fn functions_are_keyed_by_symbol() {
+        let entity = Rc::new(Entity::new("a".to_string(), EntityType::Agent));
+        assert!(entity.add_function(Rc::new(Function::new("walk", Rc::downgrade(&entity)))).is_none());
+        assert!(entity.get_function("walk").is_some());
+        assert!(entity.get_function(&Symbol::new("walk")).is_some());
+        assert!(entity.get_function("entity-test-unknown").is_none());
+        assert_eq!("entity-test-unknown".as_symbol(), None);
+
+        let replaced = entity.add_function(Rc::new(Function::new("walk".to_string(), Rc::downgrade(&entity))));
+        assert!(replaced.is_some());
+        assert_eq!(entity.get_all_functions().len(), 1);
+        assert!(entity.remove_function("walk").is_some());
+        assert!(entity.get_function("walk").is_none());
+    }
+
+    #[test]
+    fn relations_are_split_by_direction() {
+        let model = Model::new();
+        model.define_relationship("follows".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        let relation = model.add_relation("follows".to_string(), &a.id, &b.id).unwrap();
+
+        assert_eq!(a.get_relations("follows").len(), 1);
+        assert_eq!(a.get_outgoing_relations("follows").len(), 1);
+        assert!(a.get_incoming_relations("follows").is_empty());
+        assert_eq!(b.get_incoming_relations("follows").len(), 1);
+        assert!(a.get_relations("entity-test-unknown").is_empty());
+
+        a.remove_relation(relation.name, relation.id);
+        assert!(a.get_all_relations().is_empty());
+        assert_eq!(b.get_all_relations().len(), 1);
+    }
+
+    #[test]
+    fn read_only_view_exposes_state_and_functions() {
+        let entity = Rc::new(Entity::new("a".to_string(), EntityType::Agent));
+        entity.get_state().borrow_mut().set("age", crate::variable::Value::Integer(1));
+        let function = Rc::new(Function::new("walk", Rc::downgrade(&entity)));
+        function.activate();
+        entity.add_function(function);
+
+        let view: &dyn ReadOnlyEntity = &*entity;
+        assert_eq!(view.get_name(), "a");
+        assert_eq!(view.get_state().len(), 1);
+        assert!(view.get_function("walk").is_some_and(|f| f.is_active() && f.get_name() == "walk"));
+    }
+}
@@ -0,0 +1,558 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use uuid::Uuid;
+use crate::context::ReadOnlyModel;
+use crate::graph::Graph;
+use crate::recorder::{json_string, json_value, Observer};
+use crate::types::EntityType;
+use crate::variable::{format_datetime, Value};
+
+#[derive(Debug, Clone)]
+pub struct NodeRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub entity_type: EntityType,
+    pub attributes: Vec<(String, Value)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EdgeRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub source: Uuid,
+    pub target: Uuid,
+    pub attributes: Vec<(String, Value)>,
+}
+
+// ある時点のグラフを書き出し用に複製したもの
+#[derive(Debug, Clone, Default)]
+pub struct GraphSnapshot {
+    pub nodes: Vec<NodeRecord>,
+    pub edges: Vec<EdgeRecord>,
+}
+
+impl GraphSnapshot {
+    // node_type を指定すると、関係を持たないその型のエンティティもノードに含める
+    pub fn capture(graph: &Graph, node_type: Option<&EntityType>) -> Self {
+        let model = graph.model();
+        let mut node_ids = Vec::new();
+        let mut seen = HashSet::new();
+        if let Some(entity_type) = node_type {
+            for entity in model.get_entities_by_type(entity_type) {
+                if seen.insert(entity.get_id()) {
+                    node_ids.push(entity.get_id());
+                }
+            }
+        }
+        for id in graph.nodes() {
+            if seen.insert(id) {
+                node_ids.push(id);
+            }
+        }
+
+        let nodes = node_ids
+            .iter()
+            .filter_map(|id| model.get_entity(id))
+            .map(|entity| NodeRecord {
+                id: entity.get_id(),
+                name: entity.get_name().to_string(),
+                entity_type: entity.get_entity_type().clone(),
+                attributes: sorted(entity.get_state().iter().map(|(k, v)| (k.to_string(), v.clone())).collect()),
+            })
+            .collect();
+
+        let edges = graph
+            .edges()
+            .iter()
+            .filter_map(|edge| {
+                let relation = model.get_relation(&edge.relation_id)?;
+                Some(EdgeRecord {
+                    id: edge.relation_id,
+                    name: relation.get_name().to_string(),
+                    source: edge.source,
+                    target: edge.target,
+                    attributes: sorted(relation.iter_meta().into_iter().collect()),
+                })
+            })
+            .collect();
+
+        Self { nodes, edges }
+    }
+
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "digraph G {{")?;
+        for node in &self.nodes {
+            let mut attributes = vec![
+                format!("label={}", json_string(&node.name)),
+                format!("entity_type={}", json_string(&node.entity_type.to_string())),
+            ];
+            attributes.extend(node.attributes.iter().map(|(k, v)| dot_attribute(k, v)));
+            writeln!(writer, "  \"{}\" [{}];", node.id, attributes.join(", "))?;
+        }
+        for edge in &self.edges {
+            let mut attributes = vec![format!("relation={}", json_string(&edge.name))];
+            attributes.extend(edge.attributes.iter().map(|(k, v)| dot_attribute(k, v)));
+            writeln!(writer, "  \"{}\" -> \"{}\" [{}];", edge.source, edge.target, attributes.join(", "))?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    pub fn write_graphml<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let node_keys = attribute_types(self.nodes.iter().map(|n| &n.attributes));
+        let edge_keys = attribute_types(self.edges.iter().map(|e| &e.attributes));
+
+        writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(writer, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+        writeln!(writer, "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>")?;
+        writeln!(writer, "  <key id=\"entity_type\" for=\"node\" attr.name=\"entity_type\" attr.type=\"string\"/>")?;
+        writeln!(writer, "  <key id=\"relation\" for=\"edge\" attr.name=\"relation\" attr.type=\"string\"/>")?;
+        for (i, (name, kind)) in node_keys.iter().enumerate() {
+            writeln!(writer, "  <key id=\"n{}\" for=\"node\" attr.name=\"{}\" attr.type=\"{}\"/>", i, xml_escape(name), kind.graphml())?;
+        }
+        for (i, (name, kind)) in edge_keys.iter().enumerate() {
+            writeln!(writer, "  <key id=\"e{}\" for=\"edge\" attr.name=\"{}\" attr.type=\"{}\"/>", i, xml_escape(name), kind.graphml())?;
+        }
+        writeln!(writer, "  <graph id=\"G\" edgedefault=\"directed\">")?;
+        for node in &self.nodes {
+            writeln!(writer, "    <node id=\"{}\">", node.id)?;
+            writeln!(writer, "      <data key=\"label\">{}</data>", xml_escape(&node.name))?;
+            writeln!(writer, "      <data key=\"entity_type\">{}</data>", xml_escape(&node.entity_type.to_string()))?;
+            for (key, value) in &node.attributes {
+                let index = node_keys.iter().position(|(k, _)| k == key).unwrap();
+                writeln!(writer, "      <data key=\"n{}\">{}</data>", index, xml_escape(&plain_value(value)))?;
+            }
+            writeln!(writer, "    </node>")?;
+        }
+        for edge in &self.edges {
+            writeln!(writer, "    <edge id=\"{}\" source=\"{}\" target=\"{}\">", edge.id, edge.source, edge.target)?;
+            writeln!(writer, "      <data key=\"relation\">{}</data>", xml_escape(&edge.name))?;
+            for (key, value) in &edge.attributes {
+                let index = edge_keys.iter().position(|(k, _)| k == key).unwrap();
+                writeln!(writer, "      <data key=\"e{}\">{}</data>", index, xml_escape(&plain_value(value)))?;
+            }
+            writeln!(writer, "    </edge>")?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    pub fn write_gexf<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let node_keys = attribute_types(self.nodes.iter().map(|n| &n.attributes));
+        let edge_keys = attribute_types(self.edges.iter().map(|e| &e.attributes));
+
+        write_gexf_header(writer, "static")?;
+        write_gexf_attributes(writer, "node", None, &node_keys)?;
+        write_gexf_attributes(writer, "edge", None, &edge_keys)?;
+        writeln!(writer, "    <nodes>")?;
+        for node in &self.nodes {
+            writeln!(writer, "      <node id=\"{}\" label=\"{}\">", node.id, xml_escape(&node.name))?;
+            let values: Vec<_> = node.attributes.iter().map(|(k, v)| (k, v, None)).collect();
+            write_gexf_attvalues(writer, &node_keys, &values)?;
+            writeln!(writer, "      </node>")?;
+        }
+        writeln!(writer, "    </nodes>")?;
+        writeln!(writer, "    <edges>")?;
+        for edge in &self.edges {
+            writeln!(writer, "      <edge id=\"{}\" source=\"{}\" target=\"{}\" label=\"{}\">",
+                edge.id, edge.source, edge.target, xml_escape(&edge.name))?;
+            let values: Vec<_> = edge.attributes.iter().map(|(k, v)| (k, v, None)).collect();
+            write_gexf_attvalues(writer, &edge_keys, &values)?;
+            writeln!(writer, "      </edge>")?;
+        }
+        writeln!(writer, "    </edges>")?;
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</gexf>")
+    }
+
+    // NetworkX の node_link_graph で読み込める形式
+    pub fn write_node_link_json<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let nodes: Vec<String> = self.nodes
+            .iter()
+            .map(|node| {
+                let mut fields = vec![
+                    format!("\"id\":\"{}\"", node.id),
+                    format!("\"name\":{}", json_string(&node.name)),
+                    format!("\"entity_type\":{}", json_string(&node.entity_type.to_string())),
+                ];
+                fields.extend(node.attributes.iter().map(|(k, v)| format!("{}:{}", json_string(k), json_value(v))));
+                format!("{{{}}}", fields.join(","))
+            })
+            .collect();
+        let links: Vec<String> = self.edges
+            .iter()
+            .map(|edge| {
+                let mut fields = vec![
+                    format!("\"source\":\"{}\"", edge.source),
+                    format!("\"target\":\"{}\"", edge.target),
+                    format!("\"key\":\"{}\"", edge.id),
+                    format!("\"relation\":{}", json_string(&edge.name)),
+                ];
+                fields.extend(edge.attributes.iter().map(|(k, v)| format!("{}:{}", json_string(k), json_value(v))));
+                format!("{{{}}}", fields.join(","))
+            })
+            .collect();
+        writeln!(
+            writer,
+            "{{\"directed\":true,\"multigraph\":true,\"graph\":{{}},\"nodes\":[{}],\"links\":[{}]}}",
+            nodes.join(","),
+            links.join(",")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AttributeKind {
+    Integer,
+    Long,
+    Float,
+    Boolean,
+    String,
+}
+
+impl AttributeKind {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Integer(_) => AttributeKind::Integer,
+            Value::Int64(_) => AttributeKind::Long,
+            Value::Float(_) | Value::Float64(_) => AttributeKind::Float,
+            Value::Boolean(_) => AttributeKind::Boolean,
+            _ => AttributeKind::String,
+        }
+    }
+
+    fn graphml(&self) -> &'static str {
+        match self {
+            AttributeKind::Integer => "int",
+            AttributeKind::Long => "long",
+            AttributeKind::Float => "double",
+            AttributeKind::Boolean => "boolean",
+            AttributeKind::String => "string",
+        }
+    }
+
+    fn gexf(&self) -> &'static str {
+        match self {
+            AttributeKind::Integer => "integer",
+            AttributeKind::Long => "long",
+            AttributeKind::Float => "double",
+            AttributeKind::Boolean => "boolean",
+            AttributeKind::String => "string",
+        }
+    }
+}
+
+// キーごとの型を決める。型が混在するキーは文字列として扱う
+fn attribute_types<'a, I>(attributes: I) -> Vec<(String, AttributeKind)>
+where
+    I: Iterator<Item = &'a Vec<(String, Value)>>,
+{
+    let mut keys: Vec<(String, AttributeKind)> = Vec::new();
+    for (key, value) in attributes.flatten() {
+        let kind = AttributeKind::of(value);
+        match keys.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) if *existing != kind => *existing = AttributeKind::String,
+            Some(_) => {}
+            None => keys.push((key.clone(), kind)),
+        }
+    }
+    keys
+}
+
+fn sorted(mut attributes: Vec<(String, Value)>) -> Vec<(String, Value)> {
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+    attributes
+}
+
+fn plain_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Float(f) => f.to_string(),
+        Value::Float64(f) => f.to_string(),
+        Value::EntityRef(id) => id.to_string(),
+        Value::DateTime(datetime) => format_datetime(datetime),
+        other => json_value(other),
+    }
+}
+
+fn dot_attribute(key: &str, value: &Value) -> String {
+    format!("{}={}", json_string(key), json_string(&plain_value(value)))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn write_gexf_header<W: Write>(writer: &mut W, mode: &str) -> io::Result<()> {
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer, "<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">")?;
+    if mode == "dynamic" {
+        writeln!(writer, "  <graph mode=\"dynamic\" defaultedgetype=\"directed\" timeformat=\"integer\" timerepresentation=\"interval\">")
+    } else {
+        writeln!(writer, "  <graph mode=\"{}\" defaultedgetype=\"directed\">", mode)
+    }
+}
+
+fn write_gexf_attributes<W: Write>(
+    writer: &mut W,
+    class: &str,
+    mode: Option<&str>,
+    keys: &[(String, AttributeKind)],
+) -> io::Result<()> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    match mode {
+        Some(mode) => writeln!(writer, "    <attributes class=\"{}\" mode=\"{}\">", class, mode)?,
+        None => writeln!(writer, "    <attributes class=\"{}\">", class)?,
+    }
+    for (i, (name, kind)) in keys.iter().enumerate() {
+        writeln!(writer, "      <attribute id=\"{}\" title=\"{}\" type=\"{}\"/>", i, xml_escape(name), kind.gexf())?;
+    }
+    writeln!(writer, "    </attributes>")
+}
+
+type AttValue<'a> = (&'a String, &'a Value, Option<(u64, u64)>);
+
+fn write_gexf_attvalues<W: Write>(
+    writer: &mut W,
+    keys: &[(String, AttributeKind)],
+    values: &[AttValue],
+) -> io::Result<()> {
+    if values.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "        <attvalues>")?;
+    for (key, value, spell) in values {
+        let index = keys.iter().position(|(k, _)| k == *key).unwrap();
+        let value = xml_escape(&plain_value(value));
+        match spell {
+            Some((start, end)) => writeln!(writer,
+                "          <attvalue for=\"{}\" value=\"{}\" start=\"{}\" end=\"{}\"/>", index, value, start, end)?,
+            None => writeln!(writer, "          <attvalue for=\"{}\" value=\"{}\"/>", index, value)?,
+        }
+    }
+    writeln!(writer, "        </attvalues>")
+}
+
+fn write_gexf_spells<W: Write>(writer: &mut W, spells: &[(u64, u64)]) -> io::Result<()> {
+    writeln!(writer, "        <spells>")?;
+    for (start, end) in spells {
+        writeln!(writer, "          <spell start=\"{}\" end=\"{}\"/>", start, end)?;
+    }
+    writeln!(writer, "        </spells>")
+}
+
+#[derive(Debug, Default)]
+struct Timeline {
+    spells: Vec<(u64, u64)>,
+    attributes: Vec<(String, Value, u64, u64)>,
+}
+
+impl Timeline {
+    fn observe(&mut self, step: u64, previous: Option<u64>, attributes: &[(String, Value)]) {
+        let continues = |end: u64| previous == Some(end);
+        match self.spells.last_mut() {
+            Some((_, end)) if continues(*end) => *end = step,
+            _ => self.spells.push((step, step)),
+        }
+        for (key, value) in attributes {
+            let last = self.attributes.iter_mut().rev().find(|(k, _, _, _)| k == key);
+            match last {
+                Some((_, v, _, end)) if v == value && continues(*end) => *end = step,
+                _ => self.attributes.push((key.clone(), value.clone(), step, step)),
+            }
+        }
+    }
+}
+
+// ステップごとのスナップショットから、ノードと辺の出現期間と属性の変化を記録する
+#[derive(Debug)]
+pub struct DynamicGexf {
+    relation_names: Vec<String>,
+    node_type: Option<EntityType>,
+    previous_step: Option<u64>,
+    node_order: Vec<Uuid>,
+    nodes: HashMap<Uuid, (String, Timeline)>,
+    edge_order: Vec<Uuid>,
+    edges: HashMap<Uuid, (EdgeRecord, Timeline)>,
+}
+
+impl DynamicGexf {
+    pub fn new(relation_names: &[&str]) -> Self {
+        Self {
+            relation_names: relation_names.iter().map(|s| s.to_string()).collect(),
+            node_type: None,
+            previous_step: None,
+            node_order: Vec::new(),
+            nodes: HashMap::new(),
+            edge_order: Vec::new(),
+            edges: HashMap::new(),
+        }
+    }
+
+    pub fn with_node_type(mut self, entity_type: EntityType) -> Self {
+        self.node_type = Some(entity_type);
+        self
+    }
+
+    pub fn capture(&mut self, step: u64, model: &dyn ReadOnlyModel) {
+        let names: Vec<&str> = self.relation_names.iter().map(|s| s.as_str()).collect();
+        let snapshot = GraphSnapshot::capture(&Graph::new(model, &names), self.node_type.as_ref());
+        let previous = self.previous_step;
+
+        for node in snapshot.nodes {
+            let (_, timeline) = self.nodes.entry(node.id).or_insert_with(|| {
+                self.node_order.push(node.id);
+                (node.name.clone(), Timeline::default())
+            });
+            timeline.observe(step, previous, &node.attributes);
+        }
+        for edge in snapshot.edges {
+            let attributes = edge.attributes.clone();
+            let (_, timeline) = self.edges.entry(edge.id).or_insert_with(|| {
+                self.edge_order.push(edge.id);
+                (edge, Timeline::default())
+            });
+            timeline.observe(step, previous, &attributes);
+        }
+        self.previous_step = Some(step);
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let node_attributes: Vec<Vec<(String, Value)>> = self.nodes
+            .values()
+            .map(|(_, t)| t.attributes.iter().map(|(k, v, _, _)| (k.clone(), v.clone())).collect())
+            .collect();
+        let edge_attributes: Vec<Vec<(String, Value)>> = self.edges
+            .values()
+            .map(|(_, t)| t.attributes.iter().map(|(k, v, _, _)| (k.clone(), v.clone())).collect())
+            .collect();
+        let node_keys = attribute_types(node_attributes.iter());
+        let edge_keys = attribute_types(edge_attributes.iter());
+
+        write_gexf_header(writer, "dynamic")?;
+        write_gexf_attributes(writer, "node", Some("dynamic"), &node_keys)?;
+        write_gexf_attributes(writer, "edge", Some("dynamic"), &edge_keys)?;
+        writeln!(writer, "    <nodes>")?;
+        for id in &self.node_order {
+            let (name, timeline) = &self.nodes[id];
+            writeln!(writer, "      <node id=\"{}\" label=\"{}\">", id, xml_escape(name))?;
+            let values: Vec<AttValue> = timeline.attributes
+                .iter()
+                .map(|(k, v, start, end)| (k, v, Some((*start, *end))))
+                .collect();
+            write_gexf_attvalues(writer, &node_keys, &values)?;
+            write_gexf_spells(writer, &timeline.spells)?;
+            writeln!(writer, "      </node>")?;
+        }
+        writeln!(writer, "    </nodes>")?;
+        writeln!(writer, "    <edges>")?;
+        for id in &self.edge_order {
+            let (edge, timeline) = &self.edges[id];
+            writeln!(writer, "      <edge id=\"{}\" source=\"{}\" target=\"{}\" label=\"{}\">",
+                edge.id, edge.source, edge.target, xml_escape(&edge.name))?;
+            let values: Vec<AttValue> = timeline.attributes
+                .iter()
+                .map(|(k, v, start, end)| (k, v, Some((*start, *end))))
+                .collect();
+            write_gexf_attvalues(writer, &edge_keys, &values)?;
+            write_gexf_spells(writer, &timeline.spells)?;
+            writeln!(writer, "      </edge>")?;
+        }
+        writeln!(writer, "    </edges>")?;
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</gexf>")
+    }
+}
+
+impl Observer for DynamicGexf {
+    fn observe(&mut self, step: u64, model: &dyn ReadOnlyModel) {
+        self.capture(step, model);
+    }
+}
+
+impl Graph<'_> {
+    pub fn snapshot(&self) -> GraphSnapshot {
+        GraphSnapshot::capture(self, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::result::ExecutionResult;
+    use crate::types::RelationType;
+
+    fn model() -> (Model, Uuid, Uuid) {
+        let model = Model::new();
+        model.define_relationship("friend".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        let a = model.create_entity("a<&>".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        a.get_state().borrow_mut().set("age", Value::Integer(3));
+        model.add_relation("friend".to_string(), &a.id, &b.id).unwrap();
+        (model, a.id, b.id)
+    }
+
+    fn text<F: Fn(&mut Vec<u8>) -> io::Result<()>>(write: F) -> String {
+        let mut buffer = Vec::new();
+        write(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn snapshot_includes_isolated_nodes_of_the_requested_type() {
+        let (model, _, _) = model();
+        model.create_entity("c".to_string(), EntityType::Agent);
+        let graph = model.graph(&["friend"]);
+        assert_eq!(graph.snapshot().nodes.len(), 2);
+        assert_eq!(GraphSnapshot::capture(&graph, Some(&EntityType::Agent)).nodes.len(), 3);
+        assert_eq!(graph.snapshot().edges.len(), 1);
+    }
+
+    #[test]
+    fn dot_and_node_link_json() {
+        let (model, a, b) = model();
+        let snapshot = model.graph(&["friend"]).snapshot();
+        let dot = text(|w| snapshot.write_dot(w));
+        assert!(dot.starts_with("digraph G {"));
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [relation=\"friend\"]", a, b)));
+
+        let json = text(|w| snapshot.write_node_link_json(w));
+        assert!(json.starts_with("{\"directed\":true,\"multigraph\":true"));
+        assert!(json.contains("\"age\":3"));
+        assert!(json.contains(&format!("\"source\":\"{}\",\"target\":\"{}\"", a, b)));
+    }
+
+    #[test]
+    fn xml_formats_escape_names_and_type_attributes() {
+        let (model, _, _) = model();
+        let snapshot = model.graph(&["friend"]).snapshot();
+        let graphml = text(|w| snapshot.write_graphml(w));
+        assert!(graphml.contains("a&lt;&amp;&gt;"));
+        assert!(graphml.contains("attr.name=\"age\" attr.type=\"int\""));
+
+        let gexf = text(|w| snapshot.write_gexf(w));
+        assert!(gexf.contains("mode=\"static\""));
+        assert!(gexf.contains("label=\"a&lt;&amp;&gt;\""));
+    }
+
+    #[test]
+    fn dynamic_gexf_merges_consecutive_steps() {
+        let (model, a, _) = model();
+        let mut gexf = DynamicGexf::new(&["friend"]);
+        gexf.capture(0, &model);
+        gexf.capture(1, &model);
+        model.apply_results(vec![ExecutionResult::UpdateEntityState(a, "age".into(), Value::Integer(4))]);
+        gexf.capture(2, &model);
+        let out = text(|w| gexf.write(w));
+        assert!(out.contains("mode=\"dynamic\""));
+        assert!(out.contains("<spell start=\"0\" end=\"2\"/>"));
+        assert!(out.contains("value=\"3\" start=\"0\" end=\"1\""));
+        assert!(out.contains("value=\"4\" start=\"2\" end=\"2\""));
+    }
+}
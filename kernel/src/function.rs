@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::cell::{Cell, RefCell};
 use std::rc::{Rc, Weak};
+use crate::symbol::Symbol;
 use crate::variable::Variable;
 use crate::process::Process;
 use crate::entity::Entity;
@@ -8,7 +9,7 @@ use crate::context::ReadOnlyFunction;
 
 #[derive(Debug)]
 pub struct Function {
-    pub name: String,
+    pub name: Symbol,
     pub owner: Weak<Entity>,
     pub parameter: RefCell<Variable>,
     pub processes: RefCell<HashMap<String, Rc<Process>>>,
@@ -16,9 +17,9 @@ pub struct Function {
 }
 
 impl Function {
-    pub fn new(name: String, owner: Weak<Entity>) -> Self {
+    pub fn new(name: impl Into<Symbol>, owner: Weak<Entity>) -> Self {
         Function {
-            name,
+            name: name.into(),
             owner,
             parameter: RefCell::new(Variable::new()),
             processes: RefCell::new(HashMap::new()),
@@ -61,7 +62,7 @@ impl Function {
 
 impl ReadOnlyFunction for Function {
     fn get_name(&self) -> &str {
-        &self.name
+        self.name.as_str()
     }
 
     fn get_parameter(&self) -> &RefCell<Variable> {
@@ -71,4 +72,43 @@ impl ReadOnlyFunction for Function {
     fn is_active(&self) -> bool {
         self.is_active()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::ExecutionContext;
+    use crate::types::EntityType;
+
+    fn process(name: &str, owner: &Rc<Function>) -> Rc<Process> {
+        Rc::new(Process::new(name.to_string(), Rc::downgrade(owner), Box::new(|_: &ExecutionContext| Vec::new())))
+    }
+
+    #[test]
+    fn processes_are_replaced_by_name() {
+        let entity = Rc::new(Entity::new("a".to_string(), EntityType::Agent));
+        let function = Rc::new(Function::new("walk", Rc::downgrade(&entity)));
+        let first = process("step", &function);
+        function.add_process(Rc::clone(&first));
+        function.add_process(process("rest", &function));
+        assert_eq!(function.get_all_processes().len(), 2);
+
+        function.add_process(process("step", &function));
+        assert_eq!(function.get_all_processes().len(), 2);
+        assert!(!Rc::ptr_eq(&function.get_process("step").unwrap(), &first));
+        assert!(function.remove_process("step").is_some());
+        assert!(function.get_process("step").is_none());
+    }
+
+    #[test]
+    fn functions_start_inactive() {
+        let function = Function::new("walk".to_string(), Weak::new());
+        assert_eq!(function.name, "walk");
+        assert_eq!(ReadOnlyFunction::get_name(&function), "walk");
+        assert!(!function.is_active());
+        function.activate();
+        assert!(function.is_active());
+        function.deactivate();
+        assert!(!ReadOnlyFunction::is_active(&function));
+    }
+}
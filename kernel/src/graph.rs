@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use uuid::Uuid;
+use crate::context::ReadOnlyModel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge {
+    pub relation_id: Uuid,
+    pub source: Uuid,
+    pub target: Uuid,
+}
+
+// 指定した関係名の集合をひとつのグラフとして扱うビュー
+pub struct Graph<'a> {
+    model: &'a dyn ReadOnlyModel,
+    relation_names: Vec<String>,
+    direction: Direction,
+}
+
+impl<'a> Graph<'a> {
+    pub fn new(model: &'a dyn ReadOnlyModel, relation_names: &[&str]) -> Self {
+        Self {
+            model,
+            relation_names: relation_names.iter().map(|s| s.to_string()).collect(),
+            direction: Direction::Both,
+        }
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    pub fn relation_names(&self) -> &[String] {
+        &self.relation_names
+    }
+
+    pub fn model(&self) -> &'a dyn ReadOnlyModel {
+        self.model
+    }
+
+    pub fn edges(&self) -> Vec<Edge> {
+        self.model
+            .get_all_relations()
+            .iter()
+            .filter(|r| self.relation_names.iter().any(|n| n == r.get_name()))
+            .filter_map(|r| {
+                let source = r.get_entity1()?.get_id();
+                let target = r.get_entity2()?.get_id();
+                Some(Edge { relation_id: r.get_id(), source, target })
+            })
+            .collect()
+    }
+
+    pub fn nodes(&self) -> Vec<Uuid> {
+        let mut seen = HashSet::new();
+        let mut nodes = Vec::new();
+        for edge in self.edges() {
+            for id in [edge.source, edge.target] {
+                if seen.insert(id) {
+                    nodes.push(id);
+                }
+            }
+        }
+        nodes
+    }
+
+    pub fn neighbors(&self, id: &Uuid) -> Vec<Uuid> {
+        let entity = match self.model.get_entity(id) {
+            Some(entity) => entity,
+            None => return Vec::new(),
+        };
+        let mut seen = HashSet::new();
+        let mut neighbors = Vec::new();
+        for name in &self.relation_names {
+            for relation in entity.get_relations(name) {
+                let (Some(e1), Some(e2)) = (relation.get_entity1(), relation.get_entity2()) else {
+                    continue;
+                };
+                let (source, target) = (e1.get_id(), e2.get_id());
+                let other = match self.direction {
+                    Direction::Outgoing if source == *id => Some(target),
+                    Direction::Incoming if target == *id => Some(source),
+                    Direction::Both if source == *id => Some(target),
+                    Direction::Both => Some(source),
+                    _ => None,
+                };
+                if let Some(other) = other {
+                    if seen.insert(other) {
+                        neighbors.push(other);
+                    }
+                }
+            }
+        }
+        neighbors
+    }
+
+    pub fn degree(&self, id: &Uuid) -> usize {
+        self.neighbors(id).len()
+    }
+
+    pub fn bfs(&self, start: Uuid) -> Bfs<'_, 'a> {
+        Bfs::new(self, start)
+    }
+
+    pub fn dfs(&self, start: Uuid) -> Dfs<'_, 'a> {
+        Dfs::new(self, start)
+    }
+
+    // 起点から max_depth ホップ以内のエンティティとその距離
+    pub fn distances(&self, start: Uuid, max_depth: Option<usize>) -> HashMap<Uuid, usize> {
+        self.bfs(start)
+            .take_while(|(_, depth)| max_depth.is_none_or(|max| *depth <= max))
+            .collect()
+    }
+
+    pub fn k_hop(&self, start: Uuid, k: usize) -> Vec<Uuid> {
+        self.bfs(start)
+            .take_while(|(_, depth)| *depth <= k)
+            .filter(|(_, depth)| *depth > 0)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    pub fn shortest_path(&self, from: Uuid, to: Uuid) -> Option<Vec<Uuid>> {
+        self.model.get_entity(&from)?;
+        let mut parents: HashMap<Uuid, Uuid> = HashMap::new();
+        let mut visited = HashSet::from([from]);
+        let mut queue = VecDeque::from([from]);
+
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                let mut path = vec![to];
+                let mut node = to;
+                while let Some(parent) = parents.get(&node) {
+                    path.push(*parent);
+                    node = *parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in self.neighbors(&current) {
+                if visited.insert(next) {
+                    parents.insert(next, current);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn distance(&self, from: Uuid, to: Uuid) -> Option<usize> {
+        self.shortest_path(from, to).map(|path| path.len() - 1)
+    }
+
+    pub fn is_reachable(&self, from: Uuid, to: Uuid) -> bool {
+        self.bfs(from).any(|(id, _)| id == to)
+    }
+}
+
+impl fmt::Debug for Graph<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Graph")
+            .field("relation_names", &self.relation_names)
+            .field("direction", &self.direction)
+            .finish()
+    }
+}
+
+pub struct Bfs<'g, 'a> {
+    graph: &'g Graph<'a>,
+    visited: HashSet<Uuid>,
+    queue: VecDeque<(Uuid, usize)>,
+}
+
+impl<'g, 'a> Bfs<'g, 'a> {
+    fn new(graph: &'g Graph<'a>, start: Uuid) -> Self {
+        let mut queue = VecDeque::new();
+        if graph.model.get_entity(&start).is_some() {
+            queue.push_back((start, 0));
+        }
+        Self {
+            graph,
+            visited: HashSet::from([start]),
+            queue,
+        }
+    }
+}
+
+impl Iterator for Bfs<'_, '_> {
+    type Item = (Uuid, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (current, depth) = self.queue.pop_front()?;
+        for next in self.graph.neighbors(&current) {
+            if self.visited.insert(next) {
+                self.queue.push_back((next, depth + 1));
+            }
+        }
+        Some((current, depth))
+    }
+}
+
+pub struct Dfs<'g, 'a> {
+    graph: &'g Graph<'a>,
+    visited: HashSet<Uuid>,
+    stack: Vec<Uuid>,
+}
+
+impl<'g, 'a> Dfs<'g, 'a> {
+    fn new(graph: &'g Graph<'a>, start: Uuid) -> Self {
+        let mut stack = Vec::new();
+        if graph.model.get_entity(&start).is_some() {
+            stack.push(start);
+        }
+        Self {
+            graph,
+            visited: HashSet::new(),
+            stack,
+        }
+    }
+}
+
+impl Iterator for Dfs<'_, '_> {
+    type Item = Uuid;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(current) = self.stack.pop() {
+            if !self.visited.insert(current) {
+                continue;
+            }
+            let neighbors = self.graph.neighbors(&current);
+            for next in neighbors.into_iter().rev() {
+                if !self.visited.contains(&next) {
+                    self.stack.push(next);
+                }
+            }
+            return Some(current);
+        }
+        None
+    }
+}
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn graph(&self, relation_names: &[&str]) -> Graph<'_> {
+        Graph::new(self, relation_names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::types::{EntityType, RelationType};
+
+    // a -> b -> c -> d, a -> c, e は孤立
+    fn chain() -> (Model, Vec<Uuid>) {
+        let model = Model::new();
+        model.define_relationship("link".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        let ids: Vec<Uuid> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|name| model.create_entity(name.to_string(), EntityType::Agent).id)
+            .collect();
+        for (i, j) in [(0, 1), (1, 2), (2, 3), (0, 2)] {
+            model.add_relation("link".to_string(), &ids[i], &ids[j]).unwrap();
+        }
+        (model, ids)
+    }
+
+    #[test]
+    fn neighbors_respect_direction() {
+        let (model, ids) = chain();
+        let graph = model.graph(&["link"]);
+        assert_eq!(graph.degree(&ids[2]), 3);
+        let outgoing = model.graph(&["link"]).direction(Direction::Outgoing);
+        assert_eq!(outgoing.neighbors(&ids[2]), vec![ids[3]]);
+        let incoming = model.graph(&["link"]).direction(Direction::Incoming);
+        let mut parents = incoming.neighbors(&ids[2]);
+        parents.sort();
+        let mut expected = vec![ids[0], ids[1]];
+        expected.sort();
+        assert_eq!(parents, expected);
+        assert!(graph.neighbors(&ids[4]).is_empty());
+        assert!(model.graph(&["other"]).neighbors(&ids[0]).is_empty());
+    }
+
+    #[test]
+    fn shortest_paths_and_reachability() {
+        let (model, ids) = chain();
+        let graph = model.graph(&["link"]).direction(Direction::Outgoing);
+        assert_eq!(graph.shortest_path(ids[0], ids[3]), Some(vec![ids[0], ids[2], ids[3]]));
+        assert_eq!(graph.distance(ids[0], ids[0]), Some(0));
+        assert_eq!(graph.distance(ids[3], ids[0]), None);
+        assert_eq!(model.graph(&["link"]).distance(ids[3], ids[0]), Some(2));
+        assert!(!graph.is_reachable(ids[0], ids[4]));
+    }
+
+    #[test]
+    fn bfs_depths_and_k_hop() {
+        let (model, ids) = chain();
+        let graph = model.graph(&["link"]).direction(Direction::Outgoing);
+        let distances = graph.distances(ids[0], None);
+        assert_eq!(distances[&ids[1]], 1);
+        assert_eq!(distances[&ids[2]], 1);
+        assert_eq!(distances[&ids[3]], 2);
+        assert_eq!(graph.distances(ids[0], Some(1)).len(), 3);
+        let mut hop = graph.k_hop(ids[0], 1);
+        hop.sort();
+        let mut expected = vec![ids[1], ids[2]];
+        expected.sort();
+        assert_eq!(hop, expected);
+    }
+
+    #[test]
+    fn dfs_visits_each_node_once() {
+        let (model, ids) = chain();
+        let visited: Vec<Uuid> = model.graph(&["link"]).dfs(ids[0]).collect();
+        assert_eq!(visited.len(), 4);
+        assert_eq!(visited[0], ids[0]);
+        assert_eq!(model.graph(&["link"]).dfs(Uuid::new_v4()).count(), 0);
+    }
+
+    #[test]
+    fn edges_and_nodes() {
+        let (model, _) = chain();
+        let graph = model.graph(&["link"]);
+        assert_eq!(graph.edges().len(), 4);
+        assert_eq!(graph.nodes().len(), 4);
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use uuid::Uuid;
+use crate::model::Model;
+use crate::types::EntityType;
+use crate::variable::{Value, ValueType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub entities: Vec<Uuid>,
+    pub relations: Vec<Uuid>,
+    pub errors: Vec<ImportError>,
+}
+
+impl ImportReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+// ノード CSV と辺 CSV からエンティティと関係を作成する
+#[derive(Debug, Clone)]
+pub struct CsvImporter {
+    entity_type: EntityType,
+    id_column: String,
+    name_column: Option<String>,
+    type_column: Option<String>,
+    source_column: String,
+    target_column: String,
+    column_types: HashMap<String, ValueType>,
+    ids: HashMap<String, Uuid>,
+}
+
+impl CsvImporter {
+    pub fn new(entity_type: EntityType) -> Self {
+        Self {
+            entity_type,
+            id_column: "id".to_string(),
+            name_column: Some("name".to_string()),
+            type_column: None,
+            source_column: "source".to_string(),
+            target_column: "target".to_string(),
+            column_types: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn id_column(mut self, column: &str) -> Self {
+        self.id_column = column.to_string();
+        self
+    }
+
+    pub fn name_column(mut self, column: Option<&str>) -> Self {
+        self.name_column = column.map(|c| c.to_string());
+        self
+    }
+
+    pub fn type_column(mut self, column: &str) -> Self {
+        self.type_column = Some(column.to_string());
+        self
+    }
+
+    pub fn edge_columns(mut self, source: &str, target: &str) -> Self {
+        self.source_column = source.to_string();
+        self.target_column = target.to_string();
+        self
+    }
+
+    // 型を宣言しない列は値から推定する
+    pub fn column(mut self, column: &str, value_type: ValueType) -> Self {
+        self.column_types.insert(column.to_string(), value_type);
+        self
+    }
+
+    pub fn entity_id(&self, external_id: &str) -> Option<Uuid> {
+        self.ids.get(external_id).copied()
+    }
+
+    pub fn import_nodes<R: Read>(&mut self, model: &Model, reader: R) -> io::Result<ImportReport> {
+        let rows = read_csv(reader)?;
+        let mut report = ImportReport::default();
+        let Some((_, header)) = rows.first() else {
+            return Ok(report);
+        };
+        let column = |name: &str| header.iter().position(|h| h == name);
+        let Some(id_index) = column(&self.id_column) else {
+            report.errors.push(ImportError { line: 1, message: format!("missing id column '{}'", self.id_column) });
+            return Ok(report);
+        };
+        if let Some(error) = self.unsupported_column(header) {
+            report.errors.push(error);
+            return Ok(report);
+        }
+        let name_index = self.name_column.as_deref().and_then(column);
+        let type_index = self.type_column.as_deref().and_then(column);
+        let state_columns: Vec<usize> = (0..header.len())
+            .filter(|i| *i != id_index && Some(*i) != name_index && Some(*i) != type_index)
+            .collect();
+
+        for (line, row) in rows.iter().skip(1) {
+            if row.len() != header.len() {
+                report.errors.push(ImportError {
+                    line: *line,
+                    message: format!("expected {} columns, found {}", header.len(), row.len()),
+                });
+                continue;
+            }
+            let external_id = &row[id_index];
+            if self.ids.contains_key(external_id) {
+                report.errors.push(ImportError { line: *line, message: format!("duplicate id '{}'", external_id) });
+                continue;
+            }
+
+            let mut state = Vec::new();
+            let mut failed = false;
+            for &i in &state_columns {
+                match self.parse_cell(&header[i], &row[i]) {
+                    Ok(Some(value)) => state.push((header[i].clone(), value)),
+                    Ok(None) => {}
+                    Err(message) => {
+                        report.errors.push(ImportError { line: *line, message });
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                continue;
+            }
+
+            let name = name_index.map(|i| row[i].clone()).unwrap_or_else(|| external_id.clone());
+            let entity_type = match type_index.map(|i| self.parse_type(model, &row[i])) {
+                Some(Ok(entity_type)) => entity_type,
+                Some(Err(message)) => {
+                    report.errors.push(ImportError { line: *line, message });
+                    continue;
+                }
+                None => self.entity_type.clone(),
+            };
+            let entity = match model.create_entity_with_state(name, entity_type, state.into_iter().collect()) {
+                Ok(entity) => entity,
+                Err(e) => {
+                    report.errors.push(ImportError { line: *line, message: e.to_string() });
+                    continue;
+                }
+            };
+            self.ids.insert(external_id.clone(), entity.id);
+            report.entities.push(entity.id);
+        }
+        Ok(report)
+    }
+
+    // 追加の列は関係のメタデータとして保存する
+    pub fn import_edges<R: Read>(&mut self, model: &Model, relation_name: &str, reader: R) -> io::Result<ImportReport> {
+        let rows = read_csv(reader)?;
+        let mut report = ImportReport::default();
+        let Some((_, header)) = rows.first() else {
+            return Ok(report);
+        };
+        let column = |name: &str| header.iter().position(|h| h == name);
+        let (Some(source_index), Some(target_index)) = (column(&self.source_column), column(&self.target_column)) else {
+            report.errors.push(ImportError {
+                line: 1,
+                message: format!("missing '{}' or '{}' column", self.source_column, self.target_column),
+            });
+            return Ok(report);
+        };
+        if let Some(error) = self.unsupported_column(header) {
+            report.errors.push(error);
+            return Ok(report);
+        }
+        let meta_columns: Vec<usize> = (0..header.len())
+            .filter(|i| *i != source_index && *i != target_index)
+            .collect();
+
+        for (line, row) in rows.iter().skip(1) {
+            if row.len() != header.len() {
+                report.errors.push(ImportError {
+                    line: *line,
+                    message: format!("expected {} columns, found {}", header.len(), row.len()),
+                });
+                continue;
+            }
+            let (source, target) = match (self.resolve(model, &row[source_index]), self.resolve(model, &row[target_index])) {
+                (Ok(source), Ok(target)) => (source, target),
+                (Err(message), _) | (_, Err(message)) => {
+                    report.errors.push(ImportError { line: *line, message });
+                    continue;
+                }
+            };
+
+            let mut metadata = Vec::new();
+            let mut failed = false;
+            for &i in &meta_columns {
+                match self.parse_cell(&header[i], &row[i]) {
+                    Ok(Some(value)) => metadata.push((header[i].clone(), value)),
+                    Ok(None) => {}
+                    Err(message) => {
+                        report.errors.push(ImportError { line: *line, message });
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                continue;
+            }
+
+            match model.add_relation(relation_name.to_string(), &source, &target) {
+                Ok(relation) => {
+                    for (key, value) in metadata {
+                        relation.add_metadata(key, value);
+                    }
+                    report.relations.push(relation.id);
+                }
+                Err(e) => report.errors.push(ImportError { line: *line, message: e.to_string() }),
+            }
+        }
+        Ok(report)
+    }
+
+    fn resolve(&self, model: &Model, reference: &str) -> Result<Uuid, String> {
+        if let Some(id) = self.ids.get(reference) {
+            return Ok(*id);
+        }
+        match model.get_entities_by_name(reference).as_slice() {
+            [entity] => Ok(entity.id),
+            [] => Err(format!("unknown entity '{}'", reference)),
+            _ => Err(format!("ambiguous entity name '{}'", reference)),
+        }
+    }
+
+    // 空のセルは既定の型とする。組み込みの型名と Custom(名前) の形式のほかは、
+    // 既定の型かモデルが既に知っている Custom 型の名前だけを受け付ける
+    fn parse_type(&self, model: &Model, cell: &str) -> Result<EntityType, String> {
+        if cell.is_empty() {
+            return Ok(self.entity_type.clone());
+        }
+        let entity_type: EntityType = cell.parse().unwrap_or_else(|never| match never {});
+        let explicit = !matches!(entity_type, EntityType::Custom(_)) || cell.starts_with("Custom(");
+        if explicit
+            || entity_type == self.entity_type
+            || model.get_type_registry().contains(&entity_type)
+            || model.get_all_entity_types().contains(&entity_type)
+        {
+            Ok(entity_type)
+        } else {
+            Err(format!("unknown entity type '{}'", cell))
+        }
+    }
+
+    // 配列と Map の列は文字列から読み込めないため、読み込みを始める前に拒否する
+    fn unsupported_column(&self, header: &[String]) -> Option<ImportError> {
+        header.iter().find_map(|name| match self.column_types.get(name) {
+            Some(value_type @ (ValueType::Array | ValueType::Map)) => Some(ImportError {
+                line: 1,
+                message: format!("column '{}': {:?} columns cannot be imported from CSV", name, value_type),
+            }),
+            _ => None,
+        })
+    }
+
+    fn parse_cell(&self, column: &str, cell: &str) -> Result<Option<Value>, String> {
+        if cell.is_empty() {
+            return Ok(None);
+        }
+        match self.column_types.get(column) {
+            Some(value_type) => value_type
+                .parse(cell)
+                .map(Some)
+                .ok_or_else(|| format!("column '{}': cannot parse '{}' as {:?}", column, cell, value_type)),
+            None => Ok(Some(ValueType::infer(cell))),
+        }
+    }
+}
+
+// 引用符で囲まれたフィールド内の区切り文字と改行に対応した CSV の読み込み
+fn read_csv<R: Read>(mut reader: R) -> io::Result<Vec<(usize, Vec<String>)>> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => row.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut field));
+                if !(row.len() == 1 && row[0].is_empty()) {
+                    rows.push((row_line, std::mem::take(&mut row)));
+                } else {
+                    row.clear();
+                }
+                line += 1;
+                row_line = line;
+            }
+            '\n' => {
+                field.push(c);
+                line += 1;
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push((row_line, row));
+    }
+    for (_, row) in rows.iter_mut() {
+        for field in row.iter_mut() {
+            *field = field.trim().to_string();
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RelationType;
+
+    const NODES: &str = "id,name,type,age,score\n\
+        1,alice,,30,1.5\n\
+        2,bob,Spot,x,2\n\
+        3,carol,Agnet,40,\n\
+        4,\"dave, jr\",Custom(Student),50,3\n";
+
+    #[test]
+    fn imports_nodes_with_inferred_state() {
+        let model = Model::new();
+        let mut importer = CsvImporter::new(EntityType::Agent).type_column("type");
+        let report = importer.import_nodes(&model, NODES.as_bytes()).unwrap();
+        assert_eq!(report.entities.len(), 3);
+
+        let alice = model.get_entity(&importer.entity_id("1").unwrap()).unwrap();
+        assert_eq!(alice.entity_type, EntityType::Agent);
+        assert_eq!(alice.get_state().borrow().get("age"), Some(&Value::Integer(30)));
+        let bob = model.get_entity(&importer.entity_id("2").unwrap()).unwrap();
+        assert_eq!(bob.entity_type, EntityType::Spot);
+        assert_eq!(bob.get_state().borrow().get("age"), Some(&Value::String("x".to_string())));
+        let dave = model.get_entity(&importer.entity_id("4").unwrap()).unwrap();
+        assert_eq!(dave.name, "dave, jr");
+        assert_eq!(dave.entity_type, EntityType::Custom("Student".to_string()));
+
+        assert_eq!(report.errors, vec![ImportError { line: 4, message: "unknown entity type 'Agnet'".to_string() }]);
+    }
+
+    #[test]
+    fn declared_column_types_are_checked() {
+        let model = Model::new();
+        let mut importer = CsvImporter::new(EntityType::Agent)
+            .type_column("type")
+            .column("age", ValueType::Integer)
+            .column("score", ValueType::Float64);
+        let report = importer.import_nodes(&model, NODES.as_bytes()).unwrap();
+        assert_eq!(report.entities.len(), 2);
+        assert_eq!(report.errors.len(), 2);
+        assert_eq!(report.errors[0].line, 3);
+        let alice = model.get_entity(&importer.entity_id("1").unwrap()).unwrap();
+        assert_eq!(alice.get_state().borrow().get("score"), Some(&Value::Float64(1.5)));
+    }
+
+    #[test]
+    fn registered_custom_types_may_be_bare_names() {
+        let model = Model::new();
+        model.register_entity_type(EntityType::Custom("Student".to_string()), EntityType::Agent).unwrap();
+        let mut importer = CsvImporter::new(EntityType::Agent).type_column("type");
+        let report = importer.import_nodes(&model, "id,type\n1,Student\n2,Teacher\n".as_bytes()).unwrap();
+        assert_eq!(report.entities.len(), 1);
+        assert_eq!(report.errors[0].line, 3);
+    }
+
+    #[test]
+    fn array_columns_are_rejected_up_front() {
+        let model = Model::new();
+        let mut importer = CsvImporter::new(EntityType::Agent).column("tags", ValueType::Array);
+        let report = importer.import_nodes(&model, "id,tags\n1,a\n".as_bytes()).unwrap();
+        assert!(report.entities.is_empty());
+        assert_eq!(report.errors[0].line, 1);
+        assert!(model.get_all_entities().is_empty());
+    }
+
+    #[test]
+    fn imports_edges_with_metadata() {
+        let model = Model::new();
+        model.define_relationship("knows".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        let mut importer = CsvImporter::new(EntityType::Agent);
+        importer.import_nodes(&model, "id,name\n1,a\n2,b\n".as_bytes()).unwrap();
+        let edges = "source,target,weight\n1,2,0.5\n2,b,1\n1,9,1\n1,2\n";
+        let report = importer.import_edges(&model, "knows", edges.as_bytes()).unwrap();
+        assert_eq!(report.relations.len(), 2);
+        let relation = model.get_relation(&report.relations[0]).unwrap();
+        assert_eq!(relation.get_meta().borrow().get("weight"), Some(&Value::Float64(0.5)));
+        let lines: Vec<usize> = report.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![4, 5]);
+    }
+
+    #[test]
+    fn missing_id_column() {
+        let model = Model::new();
+        let report = CsvImporter::new(EntityType::Agent).import_nodes(&model, "key\n1\n".as_bytes()).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.errors[0].line, 1);
+    }
+
+    #[test]
+    fn quoted_fields_span_lines() {
+        let rows = read_csv("a,b\n\"x\ny\",\"q\"\"\"\n\n3,4".as_bytes()).unwrap();
+        assert_eq!(rows, vec![
+            (1, vec!["a".to_string(), "b".to_string()]),
+            (2, vec!["x\ny".to_string(), "q\"".to_string()]),
+            (5, vec!["3".to_string(), "4".to_string()]),
+        ]);
+    }
+}
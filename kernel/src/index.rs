@@ -0,0 +1,233 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use crate::entity::Entity;
+use crate::types::EntityType;
+use crate::variable::Value;
+
+#[derive(Debug, Default)]
+pub(crate) struct EntityIndex {
+    by_type: HashMap<EntityType, HashSet<Uuid>>,
+    by_name: HashMap<String, HashSet<Uuid>>,
+    by_state: HashMap<String, StateIndex>,
+}
+
+// 値からエンティティへの対応と、索引し直すときに古い値を外すための逆引き
+#[derive(Debug, Default)]
+struct StateIndex {
+    by_value: HashMap<Value, HashSet<Uuid>>,
+    values: HashMap<Uuid, Value>,
+}
+
+impl StateIndex {
+    fn set(&mut self, id: Uuid, value: Option<&Value>) {
+        if self.values.get(&id) == value {
+            return;
+        }
+        if let Some(old) = self.values.remove(&id) {
+            remove_from(&mut self.by_value, &old, id);
+        }
+        if let Some(value) = value {
+            self.by_value.entry(value.clone()).or_default().insert(id);
+            self.values.insert(id, value.clone());
+        }
+    }
+}
+
+impl EntityIndex {
+    pub fn insert(&mut self, entity: &Entity) {
+        self.by_type.entry(entity.entity_type.clone()).or_default().insert(entity.id);
+        self.by_name.entry(entity.name.clone()).or_default().insert(entity.id);
+
+        self.reindex_state(entity);
+    }
+
+    pub fn remove(&mut self, entity: &Entity) {
+        remove_from(&mut self.by_type, &entity.entity_type, entity.id);
+        remove_from(&mut self.by_name, &entity.name, entity.id);
+        for index in self.by_state.values_mut() {
+            index.set(entity.id, None);
+        }
+    }
+
+    // エンティティの現在の状態で状態インデックスを更新する
+    pub fn reindex_state(&mut self, entity: &Entity) {
+        let state = entity.state.borrow();
+        for (key, index) in self.by_state.iter_mut() {
+            index.set(entity.id, state.get(key));
+        }
+    }
+
+    pub fn add_state_index<'a, I>(&mut self, key: &str, entities: I)
+    where
+        I: IntoIterator<Item = &'a Entity>,
+    {
+        let mut index = StateIndex::default();
+        for entity in entities {
+            index.set(entity.id, entity.state.borrow().get(key));
+        }
+        self.by_state.insert(key.to_string(), index);
+    }
+
+    pub fn remove_state_index(&mut self, key: &str) {
+        self.by_state.remove(key);
+    }
+
+    pub fn has_state_index(&self, key: &str) -> bool {
+        self.by_state.contains_key(key)
+    }
+
+    pub fn state_index_keys(&self) -> Vec<String> {
+        self.by_state.keys().cloned().collect()
+    }
+
+    pub fn ids_by_type(&self, entity_type: &EntityType) -> Vec<Uuid> {
+        self.by_type.get(entity_type).map(|ids| ids.iter().copied().collect()).unwrap_or_default()
+    }
+
+    pub fn ids_by_name(&self, name: &str) -> Vec<Uuid> {
+        self.by_name.get(name).map(|ids| ids.iter().copied().collect()).unwrap_or_default()
+    }
+
+    pub fn ids_by_name_prefix(&self, prefix: &str) -> Vec<Uuid> {
+        self.by_name
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect()
+    }
+
+    pub fn ids_by_state(&self, key: &str, value: &Value) -> Option<Vec<Uuid>> {
+        self.by_state.get(key).map(|index| {
+            index.by_value.get(value)
+                .map(|ids| ids.iter().copied().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn entity_types(&self) -> Vec<EntityType> {
+        self.by_type.keys().cloned().collect()
+    }
+}
+
+// EntityRef の参照先から、それを状態・パラメータに持つエンティティやメタデータに持つ関係への逆引き
+#[derive(Debug, Default)]
+pub(crate) struct RefIndex {
+    holders: HashMap<Uuid, HashSet<Uuid>>,
+    targets: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl RefIndex {
+    pub fn set<'a, I>(&mut self, holder: Uuid, values: I)
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        let mut targets = HashSet::new();
+        for value in values {
+            value.collect_entity_refs(&mut targets);
+        }
+        for target in self.targets.remove(&holder).unwrap_or_default() {
+            remove_from(&mut self.holders, &target, holder);
+        }
+        for target in &targets {
+            self.holders.entry(*target).or_default().insert(holder);
+        }
+        if !targets.is_empty() {
+            self.targets.insert(holder, targets);
+        }
+    }
+
+    pub fn remove(&mut self, holder: Uuid) {
+        self.set(holder, std::iter::empty());
+    }
+
+    pub fn holders_of(&self, target: &Uuid) -> Vec<Uuid> {
+        self.holders.get(target).map(|ids| ids.iter().copied().collect()).unwrap_or_default()
+    }
+}
+
+fn remove_from<K>(map: &mut HashMap<K, HashSet<Uuid>>, key: &K, id: Uuid)
+where
+    K: std::hash::Hash + Eq,
+{
+    if let Some(ids) = map.get_mut(key) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Model;
+    use crate::query::EntityQuery;
+    use crate::result::ExecutionResult;
+    use crate::types::EntityType;
+    use crate::variable::Value;
+
+    fn names(model: &Model, key: &str, value: Value) -> Vec<String> {
+        let mut names: Vec<String> = model.get_entities_by_state(key, &value).iter().map(|e| e.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn type_and_name_lookups() {
+        let model = Model::new();
+        model.create_entity("a1".to_string(), EntityType::Agent);
+        model.create_entity("a2".to_string(), EntityType::Agent);
+        let spot = model.create_entity("s1".to_string(), EntityType::Spot);
+        assert_eq!(model.get_entities_by_type(&EntityType::Agent).len(), 2);
+        assert_eq!(model.get_entities_by_name("s1")[0].id, spot.id);
+        assert_eq!(model.get_entities_by_name_prefix("a").len(), 2);
+
+        model.apply_results(vec![ExecutionResult::DeleteEntity(spot.id)]);
+        assert!(model.get_entities_by_name("s1").is_empty());
+        assert!(model.get_entities_by_type(&EntityType::Spot).is_empty());
+    }
+
+    #[test]
+    fn state_index_follows_direct_writes() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        a.get_state().borrow_mut().set("status", Value::String("S".to_string()));
+        model.create_state_index("status");
+        assert_eq!(names(&model, "status", Value::String("S".to_string())), vec!["a"]);
+
+        // モデルを通さない書き換え
+        a.get_state().borrow_mut().set("status", Value::String("I".to_string()));
+        b.get_state().borrow_mut().set("status", Value::String("S".to_string()));
+        assert_eq!(names(&model, "status", Value::String("S".to_string())), vec!["b"]);
+        assert_eq!(names(&model, "status", Value::String("I".to_string())), vec!["a"]);
+
+        b.get_state().borrow_mut().remove("status");
+        assert!(names(&model, "status", Value::String("S".to_string())).is_empty());
+
+        let query = EntityQuery::new().state_equals("status", Value::String("I".to_string()));
+        assert_eq!(model.query(&query).len(), 1);
+    }
+
+    #[test]
+    fn state_index_follows_results_and_deletion() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        model.create_state_index("age");
+        model.apply_results(vec![ExecutionResult::UpdateEntityState(a.id, "age".into(), Value::Integer(3))]);
+        assert_eq!(names(&model, "age", Value::Integer(3)), vec!["a"]);
+
+        model.apply_results(vec![ExecutionResult::DeleteEntity(a.id)]);
+        assert!(names(&model, "age", Value::Integer(3)).is_empty());
+    }
+
+    #[test]
+    fn cloned_state_does_not_touch_the_index() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        a.get_state().borrow_mut().set("age", Value::Integer(1));
+        model.create_state_index("age");
+        let mut copy = a.get_state().borrow().clone();
+        copy.set("age", Value::Integer(2));
+        assert_eq!(names(&model, "age", Value::Integer(1)), vec!["a"]);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Neighborhood {
+    Moore,
+    VonNeumann,
+}
+
+impl Neighborhood {
+    fn offsets(&self) -> &'static [(isize, isize)] {
+        match self {
+            Neighborhood::Moore => &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)],
+            Neighborhood::VonNeumann => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+        }
+    }
+}
+
+// W×H の Spot を行優先で保持し、座標から O(1) で引けるようにする
+#[derive(Debug, Clone)]
+pub struct Lattice {
+    pub relation_name: String,
+    pub width: usize,
+    pub height: usize,
+    pub neighborhood: Neighborhood,
+    pub torus: bool,
+    cells: Vec<Uuid>,
+    positions: HashMap<Uuid, (usize, usize)>,
+}
+
+impl Lattice {
+    pub(crate) fn new(
+        relation_name: String,
+        width: usize,
+        height: usize,
+        neighborhood: Neighborhood,
+        torus: bool,
+        cells: Vec<Uuid>,
+    ) -> Self {
+        let positions = cells
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, (i % width, i / width)))
+            .collect();
+        Self { relation_name, width, height, neighborhood, torus, cells, positions }
+    }
+
+    pub fn spot_at(&self, x: usize, y: usize) -> Option<Uuid> {
+        if x < self.width && y < self.height {
+            Some(self.cells[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    pub fn position_of(&self, id: &Uuid) -> Option<(usize, usize)> {
+        self.positions.get(id).copied()
+    }
+
+    pub fn spots(&self) -> &[Uuid] {
+        &self.cells
+    }
+
+    // 関係をたどらずに座標計算で近傍を求める
+    pub fn neighbor_positions(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let mut positions = Vec::new();
+        for (dx, dy) in self.neighborhood.offsets() {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            let (w, h) = (self.width as isize, self.height as isize);
+            let position = if self.torus {
+                Some((nx.rem_euclid(w) as usize, ny.rem_euclid(h) as usize))
+            } else if (0..w).contains(&nx) && (0..h).contains(&ny) {
+                Some((nx as usize, ny as usize))
+            } else {
+                None
+            };
+            if let Some(position) = position {
+                if position != (x, y) && !positions.contains(&position) {
+                    positions.push(position);
+                }
+            }
+        }
+        positions
+    }
+
+    pub fn neighbors(&self, id: &Uuid) -> Vec<Uuid> {
+        match self.position_of(id) {
+            Some((x, y)) => self.neighbor_positions(x, y)
+                .into_iter()
+                .filter_map(|(nx, ny)| self.spot_at(nx, ny))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Model, ModelError};
+    use crate::types::{EntityType, RelationType};
+    use crate::variable::Value;
+
+    #[test]
+    fn neighbor_positions_at_the_border() {
+        let lattice = Lattice::new("grid".to_string(), 3, 3, Neighborhood::Moore, false, Vec::new());
+        assert_eq!(lattice.neighbor_positions(0, 0).len(), 3);
+        assert_eq!(lattice.neighbor_positions(1, 1).len(), 8);
+
+        let von_neumann = Lattice::new("grid".to_string(), 3, 3, Neighborhood::VonNeumann, false, Vec::new());
+        assert_eq!(von_neumann.neighbor_positions(0, 0), vec![(1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn torus_wraps_and_deduplicates() {
+        let lattice = Lattice::new("grid".to_string(), 3, 3, Neighborhood::Moore, true, Vec::new());
+        assert_eq!(lattice.neighbor_positions(0, 0).len(), 8);
+        assert!(lattice.neighbor_positions(0, 0).contains(&(2, 2)));
+
+        // 幅 2 では左右の近傍が同じ位置になる
+        let narrow = Lattice::new("grid".to_string(), 2, 1, Neighborhood::VonNeumann, true, Vec::new());
+        assert_eq!(narrow.neighbor_positions(0, 0), vec![(1, 0)]);
+    }
+
+    #[test]
+    fn create_lattice_links_neighbouring_spots() {
+        let model = Model::new();
+        let lattice = model.create_lattice("grid", 3, 2, Neighborhood::VonNeumann, false).unwrap();
+        assert_eq!(lattice.spots().len(), 6);
+        // 横 2×2 本と縦 3 本
+        assert_eq!(model.graph(&["grid"]).edges().len(), 7);
+
+        let corner = model.get_entity(&lattice.spot_at(2, 1).unwrap()).unwrap();
+        assert_eq!(corner.get_state().borrow().get("x"), Some(&Value::Integer(2)));
+        assert_eq!(corner.get_relations("grid").len(), 2);
+        assert_eq!(lattice.neighbors(&corner.id).len(), 2);
+        assert!(model.get_lattice("grid").is_some());
+        assert_eq!(lattice.spot_at(3, 0), None);
+    }
+
+    #[test]
+    fn invalid_definition_leaves_the_model_unchanged() {
+        let model = Model::new();
+        model.define_relationship("grid".to_string(), EntityType::Spot, EntityType::Spot, RelationType::OneToOne).unwrap();
+        let result = model.create_lattice("grid", 3, 3, Neighborhood::Moore, false);
+        assert!(matches!(result, Err(ModelError::InvalidRelationType { .. })));
+        assert!(model.get_all_entities().is_empty());
+        assert!(model.get_lattice("grid").is_none());
+
+        model.define_relationship("roads".to_string(), EntityType::Agent, EntityType::Spot, RelationType::ManyToMany).unwrap();
+        assert!(matches!(model.create_lattice("roads", 2, 2, Neighborhood::Moore, false), Err(ModelError::InvalidRelationEntityTypes)));
+        assert!(model.get_all_entities().is_empty());
+    }
+}
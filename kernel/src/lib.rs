@@ -1,19 +1,71 @@
+// 派生コードは ::kernel を参照するため、クレート内のテストからも同じパスで使えるようにする
+#[cfg(test)]
+extern crate self as kernel;
+
 mod entity;
+mod symbol;
 mod variable;
+mod value;
+mod state;
+mod schema;
 mod relation;
 mod function;
 mod process;
+mod behavior;
+mod system;
+mod columnar;
+#[cfg(feature = "parallel")]
+mod parallel;
 mod model;
 mod types;
 mod context;
 mod result;
+mod recorder;
+mod aggregate;
+mod query;
+mod index;
+mod graph;
+mod network;
+mod export;
+mod import;
+mod metrics;
+mod lattice;
+mod space;
+mod location;
+mod membership;
+mod multiplex;
+mod schedule;
 
 pub use context::{ReadOnlyEntity, ReadOnlyFunction, ReadOnlyRelation, ReadOnlyModel, ExecutionContext};
 pub use result::{ExecutionResult, EntityCreationInfo, RelationCreationInfo, FunctionCreationInfo, ProcessCreationInfo};
 pub use entity::Entity;
-pub use variable::{Variable, Value};
+pub use symbol::{Symbol, AsSymbol};
+pub use variable::{Variable, Value, ValueType};
+pub use value::ValueError;
+pub use schema::{StateSchema, FieldSchema, SchemaViolation, ViolationKind};
+pub use state::{EntityState, StateField, read_field};
+pub use kernel_derive::EntityState;
 pub use relation::{Relation, RelationshipDefinition, RelationshipRegistry};
 pub use function::Function;
-pub use process::{Process, Condition, AlwaysTrueCondition};
-pub use model::Model;
-pub use types::{EntityType, RelationType};
\ No newline at end of file
+pub use process::{Process, Condition, AlwaysTrueCondition, Action, ResultAction, ResultCondition};
+pub use behavior::Behavior;
+pub use system::{System, SystemContext};
+pub use columnar::{ColumnarModel, Column, EntityHandle};
+#[cfg(feature = "parallel")]
+pub use parallel::{ParallelModel, ParallelContext, ParallelAction, SharedEntity, replicate};
+pub use model::{Model, ModelError, RejectedResult};
+pub use network::{NetworkGenerator, NetworkReport, RejectedEdge};
+pub use lattice::{Lattice, Neighborhood};
+pub use space::{Space, Position};
+pub use location::{LOCATION_RELATION, CAPACITY_KEY};
+pub use schedule::{Clock, TimeSlot, Timetable};
+pub use multiplex::{ContextDefinition, ContextSchedule, scoped_key};
+pub use membership::{AGENT_MEMBERSHIP, SPOT_MEMBERSHIP, SetAggregate, membership_relation, member_type};
+pub use metrics::{NodeMetric, NetworkMetric, metric_updates};
+pub use import::{CsvImporter, ImportReport, ImportError};
+pub use export::{GraphSnapshot, NodeRecord, EdgeRecord, DynamicGexf};
+pub use graph::{Graph, Direction, Edge, Bfs, Dfs};
+pub use query::{EntityQuery, SortOrder};
+pub use aggregate::{Aggregation, EntityPredicate, Summary, Histogram};
+pub use recorder::{Observer, Observable, ObservableFn, Aggregate, Recorder, Sink, CsvSink, JsonLinesSink, MemorySink, Record, TimeSeries};
+pub use types::{EntityType, RelationType, TypeRegistry};
\ No newline at end of file
@@ -0,0 +1,195 @@
+use uuid::Uuid;
+use crate::aggregate::numeric_value;
+use crate::context::{ExecutionContext, ReadOnlyModel};
+use crate::graph::Direction;
+
+// Agent→Spot (ManyToOne) の組み込み関係と、Spot の収容人数を表す状態キー
+pub const LOCATION_RELATION: &str = "location";
+pub const CAPACITY_KEY: &str = "capacity";
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn location_of(&self, agent_id: &Uuid) -> Option<Uuid> {
+        let agent = self.get_entity(agent_id)?;
+        agent.get_relations(LOCATION_RELATION)
+            .into_iter()
+            .filter(|r| r.get_entity1().map(|e| e.get_id()) == Some(*agent_id))
+            .find_map(|r| r.get_entity2().map(|e| e.get_id()))
+    }
+
+    pub fn agents_on(&self, spot_id: &Uuid) -> Vec<Uuid> {
+        let Some(spot) = self.get_entity(spot_id) else {
+            return Vec::new();
+        };
+        spot.get_relations(LOCATION_RELATION)
+            .into_iter()
+            .filter(|r| r.get_entity2().map(|e| e.get_id()) == Some(*spot_id))
+            .filter_map(|r| r.get_entity1().map(|e| e.get_id()))
+            .collect()
+    }
+
+    // 格子があれば座標計算で、なければ relation_name の関係をたどって隣接 Spot を求める
+    pub fn neighboring_spots(&self, spot_id: &Uuid, relation_name: &str) -> Vec<Uuid> {
+        match self.get_lattice(relation_name) {
+            Some(lattice) => lattice.neighbors(spot_id),
+            None => self.graph(&[relation_name]).direction(Direction::Both).neighbors(spot_id),
+        }
+    }
+
+    pub fn agents_on_neighbors(&self, spot_id: &Uuid, relation_name: &str) -> Vec<Uuid> {
+        self.neighboring_spots(spot_id, relation_name)
+            .iter()
+            .flat_map(|neighbor| self.agents_on(neighbor))
+            .collect()
+    }
+
+    pub fn capacity_of(&self, spot_id: &Uuid) -> Option<usize> {
+        let spot = self.get_entity(spot_id)?;
+        let capacity = spot.get_state().get(CAPACITY_KEY).and_then(numeric_value)?;
+        Some(capacity.max(0.0) as usize)
+    }
+
+    pub fn has_room(&self, spot_id: &Uuid) -> bool {
+        self.capacity_of(spot_id).is_none_or(|capacity| self.agents_on(spot_id).len() < capacity)
+    }
+}
+
+impl ExecutionContext<'_> {
+    pub fn location(&self) -> Option<Uuid> {
+        self.model.location_of(&self.owner_entity.get_id())
+    }
+
+    // 同じ Spot にいる他のエージェント
+    pub fn co_located(&self) -> Vec<Uuid> {
+        let id = self.owner_entity.get_id();
+        match self.location() {
+            Some(spot) => self.model.agents_on(&spot).into_iter().filter(|other| *other != id).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn neighboring_agents(&self, relation_name: &str) -> Vec<Uuid> {
+        match self.location() {
+            Some(spot) => self.model.agents_on_neighbors(&spot, relation_name),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::*;
+    use crate::function::Function;
+    use crate::lattice::Neighborhood;
+    use crate::model::{Model, ModelError};
+    use crate::result::ExecutionResult;
+    use crate::types::EntityType;
+    use crate::variable::Value;
+
+    #[test]
+    fn move_entity_replaces_the_previous_location() {
+        let model = Model::new();
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        let home = model.create_entity("home".to_string(), EntityType::Spot);
+        let work = model.create_entity("work".to_string(), EntityType::Spot);
+
+        let first = model.move_entity(&agent.id, &home.id).unwrap();
+        // 同じ Spot への移動は既存の関係を返す
+        assert_eq!(model.move_entity(&agent.id, &home.id).unwrap().id, first.id);
+        assert_eq!(agent.get_relations(LOCATION_RELATION).len(), 1);
+
+        model.move_entity(&agent.id, &work.id).unwrap();
+        assert_eq!(model.location_of(&agent.id).map(|e| e.id), Some(work.id));
+        assert!(model.agents_on(&home.id).is_empty());
+        assert_eq!(model.agents_on(&work.id).len(), 1);
+    }
+
+    #[test]
+    fn move_entity_rejects_wrong_types_and_full_spots() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        spot.get_state().borrow_mut().set(CAPACITY_KEY, Value::Integer(1));
+
+        assert!(matches!(model.move_entity(&spot.id, &a.id), Err(ModelError::InvalidRelationEntityTypes)));
+        let missing = Uuid::new_v4();
+        assert!(matches!(model.move_entity(&a.id, &missing), Err(ModelError::EntityNotFound(id)) if id == missing));
+
+        model.move_entity(&a.id, &spot.id).unwrap();
+        let view: &dyn ReadOnlyModel = &model;
+        assert_eq!(view.capacity_of(&spot.id), Some(1));
+        assert!(!view.has_room(&spot.id));
+        assert!(matches!(model.move_entity(&b.id, &spot.id), Err(ModelError::SpotFull(id)) if id == spot.id));
+        assert!(model.location_of(&b.id).is_none());
+    }
+
+    #[test]
+    fn rejected_moves_are_recorded() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        spot.get_state().borrow_mut().set(CAPACITY_KEY, Value::Integer(1));
+
+        model.apply_results(vec![
+            ExecutionResult::MoveEntity(a.id, spot.id),
+            ExecutionResult::MoveEntity(b.id, spot.id),
+        ]);
+        assert_eq!(model.location_of(&a.id).map(|e| e.id), Some(spot.id));
+        assert!(model.location_of(&b.id).is_none());
+        let rejected = model.take_rejected_results();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].step, model.current_step());
+        assert_eq!(rejected[0].kind, "MoveEntity");
+        assert_eq!(rejected[0].error, ModelError::SpotFull(spot.id));
+        assert!(model.rejected_results().is_empty());
+    }
+
+    #[test]
+    fn neighbors_through_lattice_or_relation() {
+        let model = Model::new();
+        let lattice = model.create_lattice("grid", 3, 1, Neighborhood::VonNeumann, false).unwrap();
+        let left = lattice.spot_at(0, 0).unwrap();
+        let middle = lattice.spot_at(1, 0).unwrap();
+        let right = lattice.spot_at(2, 0).unwrap();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        let c = model.create_entity("c".to_string(), EntityType::Agent);
+        model.move_entity(&a.id, &middle).unwrap();
+        model.move_entity(&b.id, &middle).unwrap();
+        model.move_entity(&c.id, &right).unwrap();
+
+        let view: &dyn ReadOnlyModel = &model;
+        let mut neighbors = view.neighboring_spots(&middle, "grid");
+        neighbors.sort();
+        let mut expected = vec![left, right];
+        expected.sort();
+        assert_eq!(neighbors, expected);
+        assert_eq!(view.agents_on_neighbors(&middle, "grid"), vec![c.id]);
+
+        // 格子がない関係名では関係をたどる
+        assert_eq!(view.neighboring_spots(&middle, "roads"), Vec::<Uuid>::new());
+    }
+
+    #[test]
+    fn execution_context_helpers() {
+        let model = Model::new();
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        model.move_entity(&a.id, &spot.id).unwrap();
+        model.move_entity(&b.id, &spot.id).unwrap();
+
+        let function = Function::new("f".to_string(), Rc::downgrade(&a));
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*a, model: &model };
+        assert_eq!(context.location(), Some(spot.id));
+        assert_eq!(context.co_located(), vec![b.id]);
+
+        let loner = model.create_entity("c".to_string(), EntityType::Agent);
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*loner, model: &model };
+        assert_eq!(context.location(), None);
+        assert!(context.co_located().is_empty());
+        assert!(context.neighboring_agents("grid").is_empty());
+    }
+}
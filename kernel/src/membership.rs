@@ -0,0 +1,221 @@
+use uuid::Uuid;
+use crate::aggregate::summarize;
+use crate::context::{ExecutionContext, ReadOnlyEntity, ReadOnlyModel};
+use crate::recorder::Aggregate;
+use crate::result::ExecutionResult;
+use crate::types::EntityType;
+use crate::variable::Value;
+
+// メンバー→集合 (ManyToMany) の組み込み関係。Agent は AgentSet に、Spot は SpotSet に所属する
+pub const AGENT_MEMBERSHIP: &str = "member_of";
+pub const SPOT_MEMBERSHIP: &str = "spot_member_of";
+
+pub fn membership_relation(set_type: &EntityType) -> Option<&'static str> {
+    match set_type {
+        EntityType::AgentSet => Some(AGENT_MEMBERSHIP),
+        EntityType::SpotSet => Some(SPOT_MEMBERSHIP),
+        _ => None,
+    }
+}
+
+pub fn member_type(set_type: &EntityType) -> Option<EntityType> {
+    match set_type {
+        EntityType::AgentSet => Some(EntityType::Agent),
+        EntityType::SpotSet => Some(EntityType::Spot),
+        _ => None,
+    }
+}
+
+// 集合の状態 key にメンバーの source_key の集計値を書き込む。source_key が None ならメンバー数
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetAggregate {
+    pub key: String,
+    pub source_key: Option<String>,
+    pub aggregate: Aggregate,
+}
+
+impl SetAggregate {
+    pub fn compute(&self, model: &dyn ReadOnlyModel, set_id: &Uuid) -> Value {
+        let members = model.members_of(set_id);
+        match &self.source_key {
+            None => Value::Integer(members.len() as i32),
+            Some(source_key) => {
+                let values: Vec<Value> = members
+                    .iter()
+                    .filter_map(|id| model.get_entity(id))
+                    .filter_map(|member| member.get_state().get(source_key).cloned())
+                    .collect();
+                self.aggregate.value_of(&summarize(&values))
+            }
+        }
+    }
+}
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn members_of(&self, set_id: &Uuid) -> Vec<Uuid> {
+        let Some(set) = self.get_entity(set_id) else {
+            return Vec::new();
+        };
+        let Some(relation_name) = membership_relation(&self.get_base_type(set.get_entity_type())) else {
+            return Vec::new();
+        };
+        set.get_relations(relation_name)
+            .into_iter()
+            .filter(|r| r.get_entity2().map(|e| e.get_id()) == Some(*set_id))
+            .filter_map(|r| r.get_entity1().map(|e| e.get_id()))
+            .collect()
+    }
+
+    pub fn sets_of(&self, member_id: &Uuid) -> Vec<Uuid> {
+        let Some(member) = self.get_entity(member_id) else {
+            return Vec::new();
+        };
+        let relation_name = match self.get_base_type(member.get_entity_type()) {
+            EntityType::Agent => AGENT_MEMBERSHIP,
+            EntityType::Spot => SPOT_MEMBERSHIP,
+            _ => return Vec::new(),
+        };
+        member.get_relations(relation_name)
+            .into_iter()
+            .filter(|r| r.get_entity1().map(|e| e.get_id()) == Some(*member_id))
+            .filter_map(|r| r.get_entity2().map(|e| e.get_id()))
+            .collect()
+    }
+
+    pub fn is_member(&self, member_id: &Uuid, set_id: &Uuid) -> bool {
+        self.sets_of(member_id).contains(set_id)
+    }
+}
+
+impl ExecutionContext<'_> {
+    pub fn members(&self) -> Vec<Uuid> {
+        self.model.members_of(&self.owner_entity.get_id())
+    }
+
+    pub fn sets(&self) -> Vec<Uuid> {
+        self.model.sets_of(&self.owner_entity.get_id())
+    }
+
+    // 集合レベルの関数から、各メンバーに対する結果をまとめて生成する
+    pub fn for_each_member<F>(&self, mut f: F) -> Vec<ExecutionResult>
+    where
+        F: FnMut(&dyn ReadOnlyEntity) -> Vec<ExecutionResult>,
+    {
+        self.members()
+            .iter()
+            .filter_map(|id| self.model.get_entity(id))
+            .flat_map(|member| f(&*member))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::*;
+    use crate::function::Function;
+    use crate::model::{Model, ModelError};
+    use crate::types::RelationType;
+
+    #[test]
+    fn join_and_leave_sets() {
+        let model = Model::new();
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        let set = model.create_entity("club".to_string(), EntityType::AgentSet);
+
+        let first = model.join_set(&agent.id, &set.id).unwrap();
+        assert_eq!(model.join_set(&agent.id, &set.id).unwrap().id, first.id);
+        assert_eq!(model.members_of(&set.id).len(), 1);
+        // 自動で定義された関係は ManyToMany なので複数の集合に所属できる
+        let other = model.create_entity("team".to_string(), EntityType::AgentSet);
+        let relation = model.join_set(&agent.id, &other.id).unwrap();
+        assert_eq!(relation.relation_type, RelationType::ManyToMany);
+        assert_eq!(model.sets_of(&agent.id).len(), 2);
+
+        model.leave_set(&agent.id, &set.id).unwrap();
+        assert!(model.members_of(&set.id).is_empty());
+        assert_eq!(model.sets_of(&agent.id).len(), 1);
+        assert!(!(&model as &dyn ReadOnlyModel).is_member(&agent.id, &set.id));
+    }
+
+    #[test]
+    fn join_set_checks_member_and_set_types() {
+        let model = Model::new();
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        let agents = model.create_entity("agents".to_string(), EntityType::AgentSet);
+        let spots = model.create_entity("spots".to_string(), EntityType::SpotSet);
+
+        assert!(matches!(model.join_set(&spot.id, &agents.id), Err(ModelError::InvalidRelationEntityTypes)));
+        assert!(matches!(model.join_set(&agent.id, &spot.id), Err(ModelError::InvalidRelationEntityTypes)));
+        assert!(matches!(model.leave_set(&agent.id, &spot.id), Err(ModelError::InvalidRelationEntityTypes)));
+        model.join_set(&spot.id, &spots.id).unwrap();
+        assert_eq!((&model as &dyn ReadOnlyModel).sets_of(&spot.id), vec![spots.id]);
+        assert!(model.members_of(&agents.id).is_empty());
+    }
+
+    #[test]
+    fn rejected_membership_results_are_recorded() {
+        let model = Model::new();
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        let agents = model.create_entity("agents".to_string(), EntityType::AgentSet);
+        let missing = Uuid::new_v4();
+
+        model.apply_results(vec![
+            ExecutionResult::JoinSet(agent.id, agents.id),
+            ExecutionResult::JoinSet(spot.id, agents.id),
+            ExecutionResult::LeaveSet(agent.id, missing),
+        ]);
+        assert_eq!(model.members_of(&agents.id).len(), 1);
+        let rejected: Vec<(&str, ModelError)> = model.take_rejected_results().into_iter().map(|r| (r.kind, r.error)).collect();
+        assert_eq!(rejected, vec![
+            ("JoinSet", ModelError::InvalidRelationEntityTypes),
+            ("LeaveSet", ModelError::EntityNotFound(missing)),
+        ]);
+    }
+
+    #[test]
+    fn set_aggregates_follow_membership() {
+        let model = Model::new();
+        let set = model.create_entity("club".to_string(), EntityType::AgentSet);
+        for (name, age) in [("a", 10), ("b", 30)] {
+            let agent = model.create_entity(name.to_string(), EntityType::Agent);
+            agent.get_state().borrow_mut().set("age", Value::Integer(age));
+            model.join_set(&agent.id, &set.id).unwrap();
+        }
+        model.add_set_aggregate("size", None, Aggregate::Count);
+        model.add_set_aggregate("mean_age", Some("age"), Aggregate::Mean);
+        assert_eq!(set.get_state().borrow().get("size"), Some(&Value::Integer(2)));
+        assert_eq!(set.get_state().borrow().get("mean_age"), Some(&Value::Float64(20.0)));
+
+        // メンバーがいなければ平均は Null
+        let empty = model.create_entity("empty".to_string(), EntityType::AgentSet);
+        model.refresh_set_aggregates();
+        assert_eq!(empty.get_state().borrow().get("size"), Some(&Value::Integer(0)));
+        assert_eq!(empty.get_state().borrow().get("mean_age"), Some(&Value::Null));
+    }
+
+    #[test]
+    fn for_each_member_collects_results() {
+        let model = Model::new();
+        let set = model.create_entity("club".to_string(), EntityType::AgentSet);
+        let members: Vec<_> = (0..3)
+            .map(|i| {
+                let agent = model.create_entity(format!("a{}", i), EntityType::Agent);
+                model.join_set(&agent.id, &set.id).unwrap();
+                agent.id
+            })
+            .collect();
+
+        let function = Function::new("f".to_string(), Rc::downgrade(&set));
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*set, model: &model };
+        let results = context.for_each_member(|member| vec![ExecutionResult::DeleteEntity(member.get_id())]);
+        assert_eq!(results.len(), 3);
+        let mut visited = context.members();
+        visited.sort();
+        let mut expected = members;
+        expected.sort();
+        assert_eq!(visited, expected);
+    }
+}
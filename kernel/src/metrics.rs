@@ -0,0 +1,476 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use uuid::Uuid;
+use crate::aggregate::{category_label, numeric_value};
+use crate::graph::{Direction, Graph};
+use crate::result::ExecutionResult;
+use crate::symbol::Symbol;
+use crate::variable::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeMetric {
+    Degree,
+    Clustering,
+    Betweenness,
+    Closeness,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetworkMetric {
+    NodeCount,
+    EdgeCount,
+    AverageDegree,
+    AverageClustering,
+    ComponentCount,
+    LargestComponentSize,
+    AveragePathLength,
+    DegreeAssortativity,
+    Assortativity(String),
+}
+
+// ノードを添字で表した隣接リスト
+struct Adjacency {
+    nodes: Vec<Uuid>,
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl Adjacency {
+    fn undirected(&self) -> Vec<HashSet<usize>> {
+        let mut sets: Vec<HashSet<usize>> = vec![HashSet::new(); self.nodes.len()];
+        for (u, neighbors) in self.neighbors.iter().enumerate() {
+            for &v in neighbors {
+                if u != v {
+                    sets[u].insert(v);
+                    sets[v].insert(u);
+                }
+            }
+        }
+        sets
+    }
+
+    fn reversed(&self) -> Adjacency {
+        let mut neighbors: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for (u, targets) in self.neighbors.iter().enumerate() {
+            for &v in targets {
+                neighbors[v].push(u);
+            }
+        }
+        Adjacency { nodes: self.nodes.clone(), neighbors }
+    }
+
+    fn distances_from(&self, source: usize) -> Vec<Option<usize>> {
+        let mut distances = vec![None; self.nodes.len()];
+        distances[source] = Some(0);
+        let mut queue = VecDeque::from([source]);
+        while let Some(u) = queue.pop_front() {
+            let d = distances[u].unwrap();
+            for &v in &self.neighbors[u] {
+                if distances[v].is_none() {
+                    distances[v] = Some(d + 1);
+                    queue.push_back(v);
+                }
+            }
+        }
+        distances
+    }
+}
+
+impl Graph<'_> {
+    fn adjacency(&self) -> Adjacency {
+        let nodes = self.nodes();
+        let positions: HashMap<Uuid, usize> = nodes.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let mut sets: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+        let mut push = |from: usize, to: usize| {
+            if !sets[from].contains(&to) {
+                sets[from].push(to);
+            }
+        };
+        for edge in self.edges() {
+            let (s, t) = (positions[&edge.source], positions[&edge.target]);
+            match self.get_direction() {
+                Direction::Outgoing => push(s, t),
+                Direction::Incoming => push(t, s),
+                Direction::Both => {
+                    push(s, t);
+                    push(t, s);
+                }
+            }
+        }
+        Adjacency { nodes, neighbors: sets }
+    }
+
+    pub fn degrees(&self) -> HashMap<Uuid, usize> {
+        let adjacency = self.adjacency();
+        adjacency.nodes.iter().zip(&adjacency.neighbors).map(|(id, n)| (*id, n.len())).collect()
+    }
+
+    pub fn degree_distribution(&self) -> BTreeMap<usize, usize> {
+        let mut distribution = BTreeMap::new();
+        for degree in self.degrees().values() {
+            *distribution.entry(*degree).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+    // 局所クラスタ係数。向きは無視する
+    pub fn clustering(&self) -> HashMap<Uuid, f64> {
+        let adjacency = self.adjacency();
+        let sets = adjacency.undirected();
+        adjacency.nodes
+            .iter()
+            .enumerate()
+            .map(|(u, id)| {
+                let neighbors: Vec<usize> = sets[u].iter().copied().collect();
+                let k = neighbors.len();
+                if k < 2 {
+                    return (*id, 0.0);
+                }
+                let mut links = 0;
+                for (i, &a) in neighbors.iter().enumerate() {
+                    for &b in &neighbors[i + 1..] {
+                        if sets[a].contains(&b) {
+                            links += 1;
+                        }
+                    }
+                }
+                (*id, 2.0 * links as f64 / (k * (k - 1)) as f64)
+            })
+            .collect()
+    }
+
+    // ノードがなければ None
+    pub fn average_clustering(&self) -> Option<f64> {
+        mean(self.clustering().values().copied())
+    }
+
+    // 弱連結成分を大きい順に返す
+    pub fn connected_components(&self) -> Vec<Vec<Uuid>> {
+        let adjacency = self.adjacency();
+        let sets = adjacency.undirected();
+        let mut visited = vec![false; adjacency.nodes.len()];
+        let mut components = Vec::new();
+        for start in 0..adjacency.nodes.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut component = Vec::new();
+            let mut stack = vec![start];
+            while let Some(u) = stack.pop() {
+                component.push(adjacency.nodes[u]);
+                for &v in &sets[u] {
+                    if !visited[v] {
+                        visited[v] = true;
+                        stack.push(v);
+                    }
+                }
+            }
+            components.push(component);
+        }
+        components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        components
+    }
+
+    // Brandes のアルゴリズム。正規化は NetworkX と同じ
+    pub fn betweenness_centrality(&self) -> HashMap<Uuid, f64> {
+        let adjacency = self.adjacency();
+        let n = adjacency.nodes.len();
+        let mut centrality = vec![0.0; n];
+        for s in 0..n {
+            let mut stack = Vec::new();
+            let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); n];
+            let mut sigma = vec![0.0; n];
+            let mut distance: Vec<Option<usize>> = vec![None; n];
+            sigma[s] = 1.0;
+            distance[s] = Some(0);
+            let mut queue = VecDeque::from([s]);
+            while let Some(v) = queue.pop_front() {
+                stack.push(v);
+                let dv = distance[v].unwrap();
+                for &w in &adjacency.neighbors[v] {
+                    if distance[w].is_none() {
+                        distance[w] = Some(dv + 1);
+                        queue.push_back(w);
+                    }
+                    if distance[w] == Some(dv + 1) {
+                        sigma[w] += sigma[v];
+                        predecessors[w].push(v);
+                    }
+                }
+            }
+            let mut delta = vec![0.0; n];
+            while let Some(w) = stack.pop() {
+                for &v in &predecessors[w] {
+                    delta[v] += sigma[v] / sigma[w] * (1.0 + delta[w]);
+                }
+                if w != s {
+                    centrality[w] += delta[w];
+                }
+            }
+        }
+        let scale = if n > 2 { 1.0 / ((n - 1) * (n - 2)) as f64 } else { 1.0 };
+        adjacency.nodes.iter().zip(centrality).map(|(id, c)| (*id, c * scale)).collect()
+    }
+
+    // 到達可能なノードの割合で補正した近接中心性 (Wasserman-Faust)。
+    // 有向の場合は NetworkX と同じく、他のノードからそのノードへの距離 (入方向) を使う。
+    // 出方向の距離で求めるには Direction::Incoming のグラフで呼び出す
+    pub fn closeness_centrality(&self) -> HashMap<Uuid, f64> {
+        let adjacency = self.adjacency().reversed();
+        let n = adjacency.nodes.len();
+        (0..n)
+            .map(|u| {
+                let distances: Vec<usize> = adjacency.distances_from(u).into_iter().flatten().collect();
+                let reachable = distances.len() - 1;
+                let total: usize = distances.iter().sum();
+                let closeness = if total > 0 && n > 1 {
+                    (reachable as f64 / total as f64) * (reachable as f64 / (n - 1) as f64)
+                } else {
+                    0.0
+                };
+                (adjacency.nodes[u], closeness)
+            })
+            .collect()
+    }
+
+    // 到達可能なノード対についての平均最短経路長
+    pub fn average_path_length(&self) -> Option<f64> {
+        let adjacency = self.adjacency();
+        let mut total = 0usize;
+        let mut pairs = 0usize;
+        for u in 0..adjacency.nodes.len() {
+            for d in adjacency.distances_from(u).into_iter().flatten().filter(|d| *d > 0) {
+                total += d;
+                pairs += 1;
+            }
+        }
+        (pairs > 0).then(|| total as f64 / pairs as f64)
+    }
+
+    pub fn degree_assortativity(&self) -> Option<f64> {
+        let degrees = self.degrees();
+        let pairs: Vec<(f64, f64)> = self.undirected_pairs()
+            .into_iter()
+            .map(|(u, v)| (degrees[&u] as f64, degrees[&v] as f64))
+            .collect();
+        pearson(&pairs)
+    }
+
+    // 数値の状態は相関係数、カテゴリ値は Newman の assortativity 係数を返す
+    pub fn assortativity(&self, key: &str) -> Option<f64> {
+        let model = self.model();
+        let value_of = |id: &Uuid| model.get_entity(id).and_then(|e| e.get_state().get(key).cloned());
+        let pairs: Vec<(Value, Value)> = self.undirected_pairs()
+            .into_iter()
+            .filter_map(|(u, v)| Some((value_of(&u)?, value_of(&v)?)))
+            .collect();
+        if pairs.is_empty() {
+            return None;
+        }
+        let numeric: Option<Vec<(f64, f64)>> = pairs
+            .iter()
+            .map(|(a, b)| Some((numeric_value(a)?, numeric_value(b)?)))
+            .collect();
+        match numeric {
+            Some(numeric) if !pairs.iter().any(|(a, _)| matches!(a, Value::Boolean(_))) => pearson(&numeric),
+            _ => {
+                let labels: Vec<(String, String)> = pairs
+                    .iter()
+                    .map(|(a, b)| (category_label(a), category_label(b)))
+                    .collect();
+                categorical_assortativity(&labels)
+            }
+        }
+    }
+
+    pub fn node_metric(&self, metric: NodeMetric) -> HashMap<Uuid, f64> {
+        match metric {
+            NodeMetric::Degree => self.degrees().into_iter().map(|(id, d)| (id, d as f64)).collect(),
+            NodeMetric::Clustering => self.clustering(),
+            NodeMetric::Betweenness => self.betweenness_centrality(),
+            NodeMetric::Closeness => self.closeness_centrality(),
+        }
+    }
+
+    pub fn network_metric(&self, metric: &NetworkMetric) -> Value {
+        let float = |x: Option<f64>| x.map_or(Value::Null, Value::Float64);
+        match metric {
+            NetworkMetric::NodeCount => Value::Integer(self.nodes().len() as i32),
+            NetworkMetric::EdgeCount => Value::Integer(self.edges().len() as i32),
+            NetworkMetric::AverageDegree => float(mean(self.degrees().values().map(|d| *d as f64))),
+            NetworkMetric::AverageClustering => float(self.average_clustering()),
+            NetworkMetric::ComponentCount => Value::Integer(self.connected_components().len() as i32),
+            NetworkMetric::LargestComponentSize => {
+                Value::Integer(self.connected_components().first().map_or(0, |c| c.len()) as i32)
+            }
+            NetworkMetric::AveragePathLength => float(self.average_path_length()),
+            NetworkMetric::DegreeAssortativity => float(self.degree_assortativity()),
+            NetworkMetric::Assortativity(key) => float(self.assortativity(key)),
+        }
+    }
+
+    // 各辺を両方向の組として数える
+    fn undirected_pairs(&self) -> Vec<(Uuid, Uuid)> {
+        self.edges()
+            .into_iter()
+            .filter(|e| e.source != e.target)
+            .flat_map(|e| [(e.source, e.target), (e.target, e.source)])
+            .collect()
+    }
+}
+
+// 指標の値をエンティティの状態に書き込む結果を作る
+pub fn metric_updates(key: &str, values: &HashMap<Uuid, f64>) -> Vec<ExecutionResult> {
+    let key = Symbol::new(key);
+    values
+        .iter()
+        .map(|(id, value)| ExecutionResult::UpdateEntityState(*id, key, Value::Float64(*value)))
+        .collect()
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0usize), |(s, c), x| (s + x, c + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+fn pearson(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.is_empty() {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+    let cov: f64 = pairs.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let var_x: f64 = pairs.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let var_y: f64 = pairs.iter().map(|(_, y)| (y - mean_y).powi(2)).sum();
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+fn categorical_assortativity(pairs: &[(String, String)]) -> Option<f64> {
+    let total = pairs.len() as f64;
+    let mut same = 0.0;
+    let mut a: HashMap<&str, f64> = HashMap::new();
+    let mut b: HashMap<&str, f64> = HashMap::new();
+    for (x, y) in pairs {
+        if x == y {
+            same += 1.0;
+        }
+        *a.entry(x).or_insert(0.0) += 1.0;
+        *b.entry(y).or_insert(0.0) += 1.0;
+    }
+    let expected: f64 = a.iter().map(|(k, v)| (v / total) * (b.get(k).copied().unwrap_or(0.0) / total)).sum();
+    if expected >= 1.0 {
+        return None;
+    }
+    Some((same / total - expected) / (1.0 - expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::types::{EntityType, RelationType};
+
+    fn model(edges: &[(usize, usize)], n: usize) -> (Model, Vec<Uuid>) {
+        let model = Model::new();
+        model.define_relationship("link".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        let ids: Vec<Uuid> = (0..n).map(|i| model.create_entity(format!("n{}", i), EntityType::Agent).id).collect();
+        for (i, j) in edges {
+            model.add_relation("link".to_string(), &ids[*i], &ids[*j]).unwrap();
+        }
+        (model, ids)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn degree_and_clustering_of_a_triangle_with_tail() {
+        let (model, ids) = model(&[(0, 1), (1, 2), (2, 0), (2, 3)], 4);
+        let graph = model.graph(&["link"]);
+        assert_eq!(graph.degrees()[&ids[2]], 3);
+        assert_eq!(graph.degree_distribution().get(&2), Some(&2));
+        let clustering = graph.clustering();
+        assert!(close(clustering[&ids[0]], 1.0));
+        assert!(close(clustering[&ids[2]], 1.0 / 3.0));
+        assert!(close(clustering[&ids[3]], 0.0));
+    }
+
+    #[test]
+    fn betweenness_matches_networkx_normalisation() {
+        let (model, ids) = model(&[(0, 1), (1, 2)], 3);
+        let betweenness = model.graph(&["link"]).betweenness_centrality();
+        assert!(close(betweenness[&ids[1]], 1.0));
+        assert!(close(betweenness[&ids[0]], 0.0));
+    }
+
+    #[test]
+    fn directed_closeness_uses_incoming_distances() {
+        let (model, ids) = model(&[(0, 1), (1, 2)], 3);
+        let closeness = model.graph(&["link"]).direction(Direction::Outgoing).closeness_centrality();
+        assert!(close(closeness[&ids[0]], 0.0));
+        assert!(close(closeness[&ids[1]], 0.5));
+        assert!(close(closeness[&ids[2]], 2.0 / 3.0));
+
+        let outward = model.graph(&["link"]).direction(Direction::Incoming).closeness_centrality();
+        assert!(close(outward[&ids[0]], 2.0 / 3.0));
+        assert!(close(outward[&ids[2]], 0.0));
+
+        let undirected = model.graph(&["link"]).closeness_centrality();
+        assert!(close(undirected[&ids[1]], 1.0));
+    }
+
+    #[test]
+    fn components_and_path_length() {
+        let (model, ids) = model(&[(0, 1), (1, 2), (3, 4)], 5);
+        let graph = model.graph(&["link"]);
+        let components = graph.connected_components();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].len(), 3);
+        assert!(components[1].contains(&ids[3]));
+        // 0-1, 1-2, 0-2, 3-4 を両方向に数える
+        assert!(close(graph.average_path_length().unwrap(), (1.0 + 1.0 + 2.0 + 1.0) / 4.0));
+        assert_eq!(graph.network_metric(&NetworkMetric::LargestComponentSize), Value::Integer(3));
+    }
+
+    #[test]
+    fn assortativity_numeric_and_categorical() {
+        let (model, ids) = model(&[(0, 1), (2, 3)], 4);
+        for (i, (age, group)) in [(10, "a"), (10, "a"), (50, "b"), (50, "b")].iter().enumerate() {
+            let entity = model.get_entity(&ids[i]).unwrap();
+            entity.get_state().borrow_mut().set("age", Value::Integer(*age));
+            entity.get_state().borrow_mut().set("group", Value::String(group.to_string()));
+        }
+        let graph = model.graph(&["link"]);
+        assert!(close(graph.assortativity("age").unwrap(), 1.0));
+        assert!(close(graph.assortativity("group").unwrap(), 1.0));
+        assert_eq!(graph.assortativity("missing"), None);
+        assert_eq!(graph.degree_assortativity(), None);
+    }
+
+    #[test]
+    fn averages_of_an_empty_graph_are_null() {
+        let (empty, _) = model(&[], 0);
+        let graph = empty.graph(&["link"]);
+        assert_eq!(graph.average_clustering(), None);
+        assert_eq!(graph.network_metric(&NetworkMetric::AverageDegree), Value::Null);
+        assert_eq!(graph.network_metric(&NetworkMetric::AverageClustering), Value::Null);
+        assert_eq!(graph.network_metric(&NetworkMetric::NodeCount), Value::Integer(0));
+
+        let (pair, _) = model(&[(0, 1)], 2);
+        let graph = pair.graph(&["link"]);
+        assert_eq!(graph.network_metric(&NetworkMetric::AverageDegree), Value::Float64(1.0));
+        assert_eq!(graph.average_clustering(), Some(0.0));
+    }
+
+    #[test]
+    fn metric_updates_write_float64_state() {
+        let id = Uuid::new_v4();
+        let updates = metric_updates("degree", &HashMap::from([(id, 2.0)]));
+        assert!(matches!(&updates[0], ExecutionResult::UpdateEntityState(target, key, Value::Float64(v))
+            if *target == id && *key == "degree" && *v == 2.0));
+    }
+}
@@ -1,31 +1,139 @@
 use std::collections::HashMap;
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::fmt;
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, Ref, RefCell};
 use uuid::Uuid;
 use crate::entity::Entity;
 use crate::relation::{Relation, RelationshipDefinition, RelationshipRegistry};
-use crate::types::{EntityType, RelationType};
+use crate::types::{EntityType, RelationType, TypeRegistry};
+use crate::behavior::Behavior;
+use crate::symbol::Symbol;
+use crate::system::System;
 use crate::context::{ExecutionContext, ReadOnlyRelation, ReadOnlyModel, ReadOnlyEntity};
 use crate::result::{ExecutionResult, EntityCreationInfo, RelationCreationInfo, FunctionCreationInfo, ProcessCreationInfo};
 use crate::process::{Process, Condition};
 use crate::function::Function;
-use crate::variable::Value;
+use crate::value::ValueError;
+use crate::variable::{SharedStateLog, StateWatch, Value};
+use crate::recorder::Observer;
+use crate::aggregate::Aggregation;
+use crate::query::EntityQuery;
+use crate::index::{EntityIndex, RefIndex};
+use crate::graph::Graph;
+use crate::network::{NetworkGenerator, NetworkReport, RejectedEdge};
+use crate::metrics::metric_updates;
+use crate::lattice::{Lattice, Neighborhood};
+use crate::space::{Position, Space};
+use crate::location::LOCATION_RELATION;
+use crate::membership::{membership_relation, member_type, SetAggregate};
+use crate::recorder::Aggregate;
+use crate::multiplex::{ContextDefinition, ContextSchedule};
+use crate::schedule::{Clock, Timetable};
+use crate::schema::{SchemaViolation, StateSchema, ViolationKind};
+use chrono::NaiveDateTime;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ModelError {
     EntityNotFound(Uuid),
+    EntityNameNotFound(String),
     RelationNotFound(Uuid),
     RelationAlreadyExists(String),
     InvalidRelationType { name: String, relation_type: RelationType },
     UndefinedRelation(String),
+    MissingRelationTarget(String),
     InvalidRelationEntityTypes,
+    InvalidGeneratorParameter(String),
+    SpaceNotEnabled,
+    InvalidSpace(String),
+    InvalidSchedule(String),
+    SpotFull(Uuid),
+    UndefinedContext(String),
+    SchemaViolation(SchemaViolation),
+    InvalidTypeHierarchy(String),
+    InvalidValue(ValueError),
+    UnsupportedResult(&'static str),
 }
 
+// 適用できなかった結果。時間割による移動なども、行おうとした操作の種類で記録する
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedResult {
+    pub step: u64,
+    pub kind: &'static str,
+    pub error: ModelError,
+}
+
+impl fmt::Display for RejectedResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}: {} rejected: {}", self.step, self.kind, self.error)
+    }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::EntityNotFound(id) => write!(f, "entity {} not found", id),
+            ModelError::EntityNameNotFound(name) => write!(f, "no entity named '{}'", name),
+            ModelError::RelationNotFound(id) => write!(f, "relation {} not found", id),
+            ModelError::RelationAlreadyExists(name) => write!(f, "relation '{}' already exists", name),
+            ModelError::InvalidRelationType { name, relation_type } => {
+                write!(f, "relation '{}' would violate {} cardinality", name, relation_type)
+            }
+            ModelError::UndefinedRelation(name) => write!(f, "relationship '{}' is not defined", name),
+            ModelError::MissingRelationTarget(name) => write!(f, "relation '{}' has no target entity", name),
+            ModelError::InvalidRelationEntityTypes => write!(f, "entity types do not match the relationship definition"),
+            ModelError::InvalidGeneratorParameter(message) => write!(f, "invalid generator parameter: {}", message),
+            ModelError::SpaceNotEnabled => write!(f, "continuous space is not enabled"),
+            ModelError::InvalidSpace(message) => write!(f, "invalid space: {}", message),
+            ModelError::InvalidSchedule(message) => write!(f, "invalid schedule: {}", message),
+            ModelError::SpotFull(id) => write!(f, "spot {} is at capacity", id),
+            ModelError::UndefinedContext(name) => write!(f, "context '{}' is not defined", name),
+            ModelError::SchemaViolation(violation) => write!(f, "schema violation: {}", violation),
+            ModelError::InvalidTypeHierarchy(message) => write!(f, "invalid type hierarchy: {}", message),
+            ModelError::InvalidValue(error) => write!(f, "invalid value: {}", error),
+            ModelError::UnsupportedResult(kind) => write!(f, "{} results are not supported by this model", kind),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
 pub struct Model {
     entities: RefCell<HashMap<Uuid, Rc<Entity>>>,
     relations: RefCell<HashMap<Uuid, Rc<Relation>>>,
     relationship_registry: RefCell<RelationshipRegistry>,
+    index: RefCell<EntityIndex>,
+    state_log: SharedStateLog,
+    entity_version: Cell<u64>,
+    entity_refs: RefCell<RefIndex>,
+    lattices: RefCell<HashMap<String, Rc<Lattice>>>,
+    space: RefCell<Option<Space>>,
+    set_aggregates: RefCell<Vec<SetAggregate>>,
+    contexts: RefCell<HashMap<String, ContextDefinition>>,
+    model_context: RefCell<Option<String>>,
+    entity_contexts: RefCell<HashMap<Uuid, String>>,
+    context_schedules: RefCell<Vec<(Option<Uuid>, ContextSchedule)>>,
+    clock: Cell<Option<Clock>>,
+    timetables: RefCell<HashMap<Uuid, Timetable>>,
+    type_registry: RefCell<TypeRegistry>,
+    behaviors: RefCell<HashMap<EntityType, Vec<Rc<Behavior>>>>,
+    inherited_functions: RefCell<HashMap<Uuid, Vec<Weak<Function>>>>,
+    schemas: RefCell<HashMap<EntityType, Rc<StateSchema>>>,
+    resolved_schemas: RefCell<HashMap<EntityType, Option<Rc<StateSchema>>>>,
+    violations: RefCell<Vec<SchemaViolation>>,
+    rejected: RefCell<Vec<RejectedResult>>,
     processes: RefCell<Vec<Rc<Process>>>,
+    stale_processes: Cell<bool>,
+    systems: RefCell<Vec<Rc<System>>>,
+    observers: RefCell<Vec<Box<dyn Observer>>>,
+    step: Cell<u64>,
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Model {
@@ -34,16 +142,78 @@ impl Model {
             entities: RefCell::new(HashMap::new()),
             relations: RefCell::new(HashMap::new()),
             relationship_registry: RefCell::new(RelationshipRegistry::new()),
+            index: RefCell::new(EntityIndex::default()),
+            state_log: SharedStateLog::default(),
+            entity_version: Cell::new(0),
+            entity_refs: RefCell::new(RefIndex::default()),
+            lattices: RefCell::new(HashMap::new()),
+            space: RefCell::new(None),
+            set_aggregates: RefCell::new(Vec::new()),
+            contexts: RefCell::new(HashMap::new()),
+            model_context: RefCell::new(None),
+            entity_contexts: RefCell::new(HashMap::new()),
+            context_schedules: RefCell::new(Vec::new()),
+            clock: Cell::new(None),
+            timetables: RefCell::new(HashMap::new()),
+            type_registry: RefCell::new(TypeRegistry::new()),
+            behaviors: RefCell::new(HashMap::new()),
+            inherited_functions: RefCell::new(HashMap::new()),
+            schemas: RefCell::new(HashMap::new()),
+            resolved_schemas: RefCell::new(HashMap::new()),
+            violations: RefCell::new(Vec::new()),
+            rejected: RefCell::new(Vec::new()),
             processes: RefCell::new(Vec::new()),
+            stale_processes: Cell::new(false),
+            systems: RefCell::new(Vec::new()),
+            observers: RefCell::new(Vec::new()),
+            step: Cell::new(0),
         }
     }
 
+    // 既定値のない必須キーは違反として記録する。作成を拒否するには create_entity_with_state を使う
     pub fn create_entity(&self, name: String, entity_type: EntityType) -> Rc<Entity> {
         let entity = Rc::new(Entity::new(name, entity_type));
+        self.fill_schema_defaults(&entity);
         self.entities.borrow_mut().insert(entity.id, entity.clone());
+        self.index.borrow_mut().insert(&entity);
+        self.entity_version.set(self.entity_version.get() + 1);
+        self.watch_state(&entity);
+        self.attach_behaviors(&entity);
         entity
     }
 
+    // 初期状態をスキーマで検証し、違反があればエンティティを作らない
+    pub fn create_entity_with_state(
+        &self,
+        name: String,
+        entity_type: EntityType,
+        initial_state: HashMap<String, Value>,
+    ) -> Result<Rc<Entity>, ModelError> {
+        let entity = Rc::new(Entity::new(name, entity_type));
+        if let Some(schema) = self.get_schema(&entity.entity_type) {
+            let mut keys: Vec<&String> = initial_state.keys().collect();
+            keys.sort();
+            for key in keys {
+                schema.check_value(key, &initial_state[key])
+                    .map_err(|kind| ModelError::SchemaViolation(self.violation(&entity, key, kind)))?;
+            }
+        }
+        for (key, value) in initial_state {
+            entity.get_state().borrow_mut().set(key, value);
+        }
+        if let Some(schema) = self.get_schema(&entity.entity_type) {
+            if let Some(key) = schema.fill_defaults(&mut entity.get_state().borrow_mut()).first() {
+                return Err(ModelError::SchemaViolation(self.violation(&entity, key, ViolationKind::MissingRequired)));
+            }
+        }
+        self.entities.borrow_mut().insert(entity.id, entity.clone());
+        self.index.borrow_mut().insert(&entity);
+        self.entity_version.set(self.entity_version.get() + 1);
+        self.watch_state(&entity);
+        self.attach_behaviors(&entity);
+        Ok(entity)
+    }
+
     pub fn get_entity(&self, id: &Uuid) -> Option<Rc<Entity>> {
         self.entities.borrow().get(id).cloned()
     }
@@ -52,43 +222,347 @@ impl Model {
         self.entities.borrow().values().cloned().collect()
     }
 
+    fn get_entities_by_ids(&self, ids: Vec<Uuid>) -> Vec<Rc<Entity>> {
+        let entities = self.entities.borrow();
+        ids.iter().filter_map(|id| entities.get(id).cloned()).collect()
+    }
+
+    // 派生型のエンティティも含める
     pub fn get_entities_by_type(&self, entity_type: &EntityType) -> Vec<Rc<Entity>> {
-        self.entities
-            .borrow()
-            .values()
-            .filter(|entity| entity.entity_type == *entity_type)
-            .cloned()
-            .collect()
+        let index = self.index.borrow();
+        let ids = self.get_subtypes(entity_type)
+            .iter()
+            .flat_map(|t| index.ids_by_type(t))
+            .collect();
+        drop(index);
+        self.get_entities_by_ids(ids)
+    }
+
+    pub fn register_entity_type(&self, entity_type: EntityType, parent: EntityType) -> Result<(), ModelError> {
+        self.type_registry.borrow_mut().register(entity_type, parent).map_err(ModelError::InvalidTypeHierarchy)?;
+        self.resolved_schemas.borrow_mut().clear();
+        self.entity_version.set(self.entity_version.get() + 1);
+        Ok(())
+    }
+
+    pub fn get_type_registry(&self) -> Ref<'_, TypeRegistry> {
+        self.type_registry.borrow()
+    }
+
+    pub fn is_subtype(&self, entity_type: &EntityType, ancestor: &EntityType) -> bool {
+        self.type_registry.borrow().is_subtype(entity_type, ancestor)
+    }
+
+    pub fn get_subtypes(&self, entity_type: &EntityType) -> Vec<EntityType> {
+        self.type_registry.borrow().subtypes(entity_type)
+    }
+
+    pub fn get_base_type(&self, entity_type: &EntityType) -> EntityType {
+        self.type_registry.borrow().base_type(entity_type)
+    }
+
+    // 型 (派生型を含む) の既存・新規エンティティに関数を付与する。
+    // 振る舞いから付与した関数は置き換えるが、インスタンスに直接追加した同名の関数は残す
+    pub fn define_behavior(&self, entity_type: EntityType, behavior: Behavior) {
+        let behavior = Rc::new(behavior);
+        {
+            let mut behaviors = self.behaviors.borrow_mut();
+            let defined = behaviors.entry(entity_type.clone()).or_default();
+            defined.retain(|b| b.name != behavior.name);
+            defined.push(Rc::clone(&behavior));
+        }
+        for entity in self.get_entities_by_type(&entity_type) {
+            let resolved = self.get_behaviors(&entity.entity_type)
+                .into_iter()
+                .find(|b| b.name == behavior.name);
+            if resolved.is_some_and(|b| Rc::ptr_eq(&b, &behavior)) {
+                self.attach_behavior(&entity, &behavior);
+            }
+        }
+        self.prune_processes();
+    }
+
+    // 既存のエンティティに付与済みの関数はそのまま残る
+    pub fn remove_behavior(&self, entity_type: &EntityType, name: &str) -> Option<Rc<Behavior>> {
+        let mut behaviors = self.behaviors.borrow_mut();
+        let defined = behaviors.get_mut(entity_type)?;
+        let index = defined.iter().position(|b| b.name == name)?;
+        Some(defined.remove(index))
+    }
+
+    // 型に適用される振る舞い。祖先と同名のものは派生型の定義が優先される
+    pub fn get_behaviors(&self, entity_type: &EntityType) -> Vec<Rc<Behavior>> {
+        let behaviors = self.behaviors.borrow();
+        let mut resolved: Vec<Rc<Behavior>> = Vec::new();
+        for t in self.type_registry.borrow().ancestors(entity_type) {
+            for behavior in behaviors.get(&t).into_iter().flatten() {
+                if !resolved.iter().any(|b| b.name == behavior.name) {
+                    resolved.push(Rc::clone(behavior));
+                }
+            }
+        }
+        resolved
+    }
+
+    fn attach_behaviors(&self, entity: &Rc<Entity>) {
+        for behavior in self.get_behaviors(&entity.entity_type) {
+            self.attach_behavior(entity, &behavior);
+        }
+    }
+
+    fn attach_behavior(&self, entity: &Rc<Entity>, behavior: &Behavior) {
+        if let Some(existing) = entity.get_function(&behavior.name) {
+            let inherited = self.inherited_functions.borrow()
+                .get(&entity.id)
+                .is_some_and(|functions| functions.iter().any(|f| f.as_ptr() == Rc::as_ptr(&existing)));
+            if !inherited {
+                return;
+            }
+        }
+        let function = Rc::new(Function::new(behavior.name.clone(), Rc::downgrade(entity)));
+        for (key, value) in &behavior.parameters {
+            function.get_parameter().borrow_mut().set(key.clone(), value.clone());
+        }
+        for (name, action, condition) in behavior.instantiate() {
+            let process = Rc::new(Process::new(name, Rc::downgrade(&function), action));
+            if let Some(condition) = condition {
+                process.set_condition(condition);
+            }
+            function.add_process(Rc::clone(&process));
+            self.add_process(process);
+        }
+        if behavior.active {
+            function.activate();
+        }
+        let mut inherited = self.inherited_functions.borrow_mut();
+        let functions = inherited.entry(entity.id).or_default();
+        functions.retain(|f| f.strong_count() > 0);
+        functions.push(Rc::downgrade(&function));
+        drop(inherited);
+        if entity.add_function(function).is_some() {
+            self.stale_processes.set(true);
+        }
+    }
+
+    // 置き換え・削除された関数のプロセスは、define_behavior や結果の適用の終わりにまとめて取り除く。
+    // 所有エンティティに今も登録されている関数のプロセスだけを残す
+    fn prune_processes(&self) {
+        if !self.stale_processes.replace(false) {
+            return;
+        }
+        self.processes.borrow_mut().retain(|process| {
+            let Some(function) = process.owner.upgrade() else {
+                return false;
+            };
+            function.owner.upgrade()
+                .and_then(|entity| entity.get_function(&function.name))
+                .is_some_and(|current| Rc::ptr_eq(&current, &function))
+        });
     }
 
     pub fn get_entities_by_name(&self, name: &str) -> Vec<Rc<Entity>> {
-        self.entities
-            .borrow()
-            .values()
-            .filter(|entity| entity.name == name)
-            .cloned()
-            .collect()
+        let ids = self.index.borrow().ids_by_name(name);
+        self.get_entities_by_ids(ids)
     }
 
     pub fn get_entities_by_name_prefix(&self, prefix: &str) -> Vec<Rc<Entity>> {
-        self.entities
-            .borrow()
-            .values()
-            .filter(|entity| entity.name.starts_with(prefix))
-            .cloned()
-            .collect()
+        let ids = self.index.borrow().ids_by_name_prefix(prefix);
+        self.get_entities_by_ids(ids)
     }
 
-    pub fn get_all_entity_types(&self) -> Vec<EntityType> {
-        self.entities
-            .borrow()
-            .values()
-            .map(|entity| entity.entity_type.clone())
-            .collect::<std::collections::HashSet<_>>()
+    // インデックスがあればそれを使い、なければ全件を走査する
+    pub fn get_entities_by_state(&self, key: &str, value: &Value) -> Vec<Rc<Entity>> {
+        self.sync_state_index();
+        let ids = self.index.borrow().ids_by_state(key, value);
+        match ids {
+            Some(ids) => self.get_entities_by_ids(ids),
+            None => self.entities
+                .borrow()
+                .values()
+                .filter(|entity| entity.state.borrow().get(key) == Some(value))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn create_state_index(&self, key: &str) {
+        self.sync_state_index();
+        let entities = self.entities.borrow();
+        self.index.borrow_mut().add_state_index(key, entities.values().map(|e| e.as_ref()));
+        self.state_log.set_indexed(true);
+    }
+
+    // 状態インデックスがなくなれば、参照に関わらない書き換えは記録しない
+    pub fn drop_state_index(&self, key: &str) {
+        let mut index = self.index.borrow_mut();
+        index.remove_state_index(key);
+        self.state_log.set_indexed(!index.state_index_keys().is_empty());
+    }
+
+    pub fn has_state_index(&self, key: &str) -> bool {
+        self.index.borrow().has_state_index(key)
+    }
+
+    // 状態・パラメータ・関係メタデータの書き換えは記録され、状態インデックスと EntityRef の逆引きを
+    // 引く前に反映される。rebuild_indexes は状態インデックス全体を作り直す
+    fn sync_state_index(&self) {
+        let dirty = self.state_log.take();
+        if dirty.is_empty() {
+            return;
+        }
+        let entities = self.entities.borrow();
+        let relations = self.relations.borrow();
+        let mut index = self.index.borrow_mut();
+        let mut refs = self.entity_refs.borrow_mut();
+        for id in dirty {
+            if let Some(entity) = entities.get(&id) {
+                entity.state.borrow_mut().mark_clean();
+                index.reindex_state(entity);
+                let functions = entity.get_all_functions();
+                for function in &functions {
+                    function.get_parameter().borrow_mut().mark_clean();
+                }
+                let state = entity.state.borrow();
+                let parameters: Vec<_> = functions.iter().map(|f| f.get_parameter().borrow()).collect();
+                let values = state.iter().chain(parameters.iter().flat_map(|p| p.iter())).map(|(_, v)| v);
+                refs.set(id, values);
+            } else if let Some(relation) = relations.get(&id) {
+                relation.meta.borrow_mut().mark_clean();
+                refs.set(id, relation.meta.borrow().iter().map(|(_, v)| v));
+            }
+        }
+    }
+
+    fn watch_state(&self, entity: &Entity) {
+        entity.state.borrow_mut().watch(StateWatch { log: SharedStateLog::clone(&self.state_log), id: entity.id });
+        for function in entity.get_all_functions() {
+            function.get_parameter().borrow_mut().share_watch(&entity.state.borrow());
+        }
+    }
+
+    fn watch_relation(&self, relation: &Relation) {
+        relation.meta.borrow_mut().watch(StateWatch { log: SharedStateLog::clone(&self.state_log), id: relation.id });
+    }
+
+    pub fn rebuild_indexes(&self) {
+        self.sync_state_index();
+        let keys = self.index.borrow().state_index_keys();
+        let mut index = EntityIndex::default();
+        let entities = self.entities.borrow();
+        for key in keys {
+            index.add_state_index(&key, std::iter::empty());
+        }
+        for entity in entities.values() {
+            index.insert(entity);
+        }
+        *self.index.borrow_mut() = index;
+    }
+
+    pub fn set_entity_state(&self, entity_id: &Uuid, key: impl Into<Symbol>, value: Value) -> Result<(), ModelError> {
+        let key = key.into();
+        let entity = self.get_entity(entity_id).ok_or(ModelError::EntityNotFound(*entity_id))?;
+        if let Some(schema) = self.get_schema(&entity.entity_type) {
+            schema.check_value(&key, &value)
+                .map_err(|kind| ModelError::SchemaViolation(self.violation(&entity, &key, kind)))?;
+        }
+        self.update_entity_state_internal(*entity_id, key, value);
+        Ok(())
+    }
+
+    pub fn define_schema(&self, entity_type: EntityType, schema: StateSchema) {
+        self.schemas.borrow_mut().insert(entity_type, Rc::new(schema));
+        self.resolved_schemas.borrow_mut().clear();
+    }
+
+    // 祖先の型のスキーマを継承する。同じキーは派生型の定義が優先される
+    pub fn get_schema(&self, entity_type: &EntityType) -> Option<Rc<StateSchema>> {
+        if let Some(resolved) = self.resolved_schemas.borrow().get(entity_type) {
+            return resolved.clone();
+        }
+        let schemas = self.schemas.borrow();
+        let mut chain = self.type_registry.borrow().ancestors(entity_type)
             .into_iter()
+            .filter_map(|t| schemas.get(&t).cloned())
+            .collect::<Vec<_>>();
+        let resolved = if chain.len() <= 1 {
+            chain.pop()
+        } else {
+            let mut merged = StateSchema::new();
+            for schema in chain.iter().rev() {
+                merged.fields.extend(schema.fields.iter().map(|(k, f)| (k.clone(), f.clone())));
+                merged.strict |= schema.strict;
+            }
+            Some(Rc::new(merged))
+        };
+        drop(schemas);
+        self.resolved_schemas.borrow_mut().insert(entity_type.clone(), resolved.clone());
+        resolved
+    }
+
+    pub fn schema_violations(&self) -> Vec<SchemaViolation> {
+        self.violations.borrow().clone()
+    }
+
+    pub fn take_schema_violations(&self) -> Vec<SchemaViolation> {
+        std::mem::take(&mut *self.violations.borrow_mut())
+    }
+
+    pub fn rejected_results(&self) -> Vec<RejectedResult> {
+        self.rejected.borrow().clone()
+    }
+
+    pub fn take_rejected_results(&self) -> Vec<RejectedResult> {
+        std::mem::take(&mut *self.rejected.borrow_mut())
+    }
+
+    fn record_rejection<T>(&self, kind: &'static str, result: Result<T, ModelError>) {
+        if let Err(error) = result {
+            self.rejected.borrow_mut().push(RejectedResult { step: self.step.get(), kind, error });
+        }
+    }
+
+    fn violation(&self, entity: &Entity, key: &str, kind: ViolationKind) -> SchemaViolation {
+        SchemaViolation {
+            step: self.step.get(),
+            entity_id: entity.id,
+            entity_type: entity.entity_type.clone(),
+            key: key.to_string(),
+            kind,
+        }
+    }
+
+    fn report_violation(&self, entity: &Entity, key: &str, kind: ViolationKind) {
+        let violation = self.violation(entity, key, kind);
+        self.violations.borrow_mut().push(violation);
+    }
+
+    fn fill_schema_defaults(&self, entity: &Entity) {
+        if let Some(schema) = self.get_schema(&entity.entity_type) {
+            let missing = schema.fill_defaults(&mut entity.get_state().borrow_mut());
+            for key in missing {
+                self.report_violation(entity, &key, ViolationKind::MissingRequired);
+            }
+        }
+    }
+
+    pub fn aggregate(&self, key: &str) -> Aggregation<'_> {
+        Aggregation::new(self, key)
+    }
+
+    // 候補の選び方は ReadOnlyModel のクエリと共通にし、一致したものを Entity として引き直す
+    pub fn query(&self, query: &EntityQuery) -> Vec<Rc<Entity>> {
+        let view: &dyn ReadOnlyModel = self;
+        let entities = self.entities.borrow();
+        view.query(query)
+            .iter()
+            .filter_map(|entity| entities.get(&entity.get_id()).cloned())
             .collect()
     }
 
+    pub fn get_all_entity_types(&self) -> Vec<EntityType> {
+        self.index.borrow().entity_types()
+    }
+
     pub fn define_relationship(
         &self,
         name: String,
@@ -121,41 +595,36 @@ impl Model {
         let entity2 = self.get_entity(entity2_id)
             .ok_or(ModelError::EntityNotFound(*entity2_id))?;
 
-        if entity1.entity_type != definition.source_type || 
-           entity2.entity_type != definition.target_type {
+        if !self.is_subtype(&entity1.entity_type, &definition.source_type) ||
+           !self.is_subtype(&entity2.entity_type, &definition.target_type) {
             return Err(ModelError::InvalidRelationEntityTypes);
         }
 
-        match definition.relation_type {
+        // OneToMany: 各ターゲットの入力は高々1本、ManyToOne: 各ソースの出力は高々1本
+        let violates = match definition.relation_type {
             RelationType::OneToOne => {
-                if !entity1.get_relations(&name).is_empty() || !entity2.get_relations(&name).is_empty() {
-                    return Err(ModelError::InvalidRelationType { name, relation_type: definition.relation_type });
-                }
-            },
-            RelationType::OneToMany => {
-                if !entity1.get_relations(&name).is_empty() {
-                    return Err(ModelError::InvalidRelationType { name, relation_type: definition.relation_type });
-                }
+                !entity1.get_outgoing_relations(&name).is_empty() || !entity2.get_incoming_relations(&name).is_empty()
             },
-            RelationType::ManyToOne => {
-                if !entity2.get_relations(&name).is_empty() {
-                    return Err(ModelError::InvalidRelationType { name, relation_type: definition.relation_type });
-                }
-            },
-            RelationType::ManyToMany => {} // No constraints
+            RelationType::OneToMany => !entity2.get_incoming_relations(&name).is_empty(),
+            RelationType::ManyToOne => !entity1.get_outgoing_relations(&name).is_empty(),
+            RelationType::ManyToMany => false, // No constraints
+        };
+        if violates {
+            return Err(ModelError::InvalidRelationType { name, relation_type: definition.relation_type });
         }
 
         let relation = Rc::new(Relation::new(
-            name.clone(),
+            name,
             definition.relation_type,
             Rc::downgrade(&entity1),
             Rc::downgrade(&entity2),
         ));
 
-        entity1.add_relation(name.clone(), Rc::downgrade(&relation));
-        entity2.add_relation(name, Rc::downgrade(&relation));
+        entity1.add_relation(relation.name, Rc::downgrade(&relation));
+        entity2.add_relation(relation.name, Rc::downgrade(&relation));
 
         self.relations.borrow_mut().insert(relation.id, relation.clone());
+        self.watch_relation(&relation);
 
         Ok(relation)
     }
@@ -163,17 +632,432 @@ impl Model {
     pub fn remove_relation(&self, relation_id: &Uuid) -> Result<(), ModelError> {
         let mut relations = self.relations.borrow_mut();
         let relation = relations.remove(relation_id).ok_or(ModelError::RelationNotFound(*relation_id))?;
+        self.entity_refs.borrow_mut().remove(relation.id);
 
         if let Some(entity1) = relation.entity1.upgrade() {
-            entity1.remove_relation(&relation.name, relation.id);
+            entity1.remove_relation(relation.name, relation.id);
         }
         if let Some(entity2) = relation.entity2.upgrade() {
-            entity2.remove_relation(&relation.name, relation.id);
+            entity2.remove_relation(relation.name, relation.id);
+        }
+
+        Ok(())
+    }
+
+    // 生成された辺のうち、型や多重度の制約に反するものは作成せず、理由とともに報告する
+    pub fn generate_network(
+        &self,
+        entity_ids: &[Uuid],
+        relation_name: &str,
+        generator: &NetworkGenerator,
+        seed: u64,
+    ) -> Result<NetworkReport, ModelError> {
+        let (source_type, target_type) = {
+            let registry = self.relationship_registry.borrow();
+            let definition = registry.get_definition(relation_name)
+                .ok_or(ModelError::UndefinedRelation(relation_name.to_string()))?;
+            (definition.source_type.clone(), definition.target_type.clone())
+        };
+        let entities = entity_ids
+            .iter()
+            .map(|id| self.get_entity(id).ok_or(ModelError::EntityNotFound(*id)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let edges = generator.edges(entities.len(), &mut rng)
+            .map_err(ModelError::InvalidGeneratorParameter)?;
+
+        let mut report = NetworkReport::default();
+        for (i, j) in edges {
+            let (a, b) = (&entities[i], &entities[j]);
+            let (source, target) = if self.is_subtype(&a.entity_type, &source_type) && self.is_subtype(&b.entity_type, &target_type) {
+                (a, b)
+            } else if self.is_subtype(&b.entity_type, &source_type) && self.is_subtype(&a.entity_type, &target_type) {
+                (b, a)
+            } else {
+                report.rejected.push(RejectedEdge { source: a.id, target: b.id, error: ModelError::InvalidRelationEntityTypes });
+                continue;
+            };
+            match self.add_relation(relation_name.to_string(), &source.id, &target.id) {
+                Ok(relation) => report.relations.push(relation),
+                Err(error) => report.rejected.push(RejectedEdge { source: source.id, target: target.id, error }),
+            }
+        }
+        Ok(report)
+    }
+
+    // 座標 (x, y) を状態に持つ Spot の格子を作り、隣接する Spot 同士を relation_name で結ぶ
+    pub fn create_lattice(
+        &self,
+        relation_name: &str,
+        width: usize,
+        height: usize,
+        neighborhood: Neighborhood,
+        torus: bool,
+    ) -> Result<Rc<Lattice>, ModelError> {
+        // 途中で失敗してモデルに格子の一部が残らないよう、関係の定義を先に検証する
+        let existing = self.relationship_registry.borrow().get_definition(relation_name).cloned();
+        match &existing {
+            Some(definition) => {
+                if definition.source_type != EntityType::Spot || definition.target_type != EntityType::Spot {
+                    return Err(ModelError::InvalidRelationEntityTypes);
+                }
+                if definition.relation_type != RelationType::ManyToMany {
+                    return Err(ModelError::InvalidRelationType {
+                        name: relation_name.to_string(),
+                        relation_type: definition.relation_type,
+                    });
+                }
+            }
+            None => {
+                self.relationship_registry.borrow_mut().add_definition(RelationshipDefinition {
+                    name: relation_name.to_string(),
+                    source_type: EntityType::Spot,
+                    target_type: EntityType::Spot,
+                    relation_type: RelationType::ManyToMany,
+                });
+            }
+        }
+
+        let mut cells = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let spot = self.create_entity(format!("{}({}, {})", relation_name, x, y), EntityType::Spot);
+                self.update_entity_state_internal(spot.id, "x".into(), Value::Integer(x as i32));
+                self.update_entity_state_internal(spot.id, "y".into(), Value::Integer(y as i32));
+                cells.push(spot.id);
+            }
+        }
+        let lattice = Rc::new(Lattice::new(relation_name.to_string(), width, height, neighborhood, torus, cells));
+
+        let linked = lattice.spots().iter().enumerate().try_for_each(|(i, id)| {
+            for neighbor in lattice.neighbors(id) {
+                let (nx, ny) = lattice.position_of(&neighbor).unwrap();
+                if ny * width + nx > i {
+                    self.add_relation(relation_name.to_string(), id, &neighbor)?;
+                }
+            }
+            Ok(())
+        });
+        // 失敗した場合は作成した Spot (とその関係) と、新しく作った定義を取り除く
+        if let Err(e) = linked {
+            for id in lattice.spots() {
+                self.delete_entity_internal(*id);
+            }
+            if existing.is_none() {
+                self.relationship_registry.borrow_mut().remove_definition(relation_name);
+            }
+            return Err(e);
+        }
+
+        self.lattices.borrow_mut().insert(relation_name.to_string(), Rc::clone(&lattice));
+        Ok(lattice)
+    }
+
+    pub fn get_lattice(&self, relation_name: &str) -> Option<Rc<Lattice>> {
+        self.lattices.borrow().get(relation_name).cloned()
+    }
+
+    // 連続空間を有効にする。既に有効な場合は登録済みの位置を新しいグリッドに移し替える
+    pub fn enable_space(&self, dimensions: usize, cell_size: f64) -> Result<(), ModelError> {
+        let mut space = Space::new(dimensions, cell_size).map_err(ModelError::InvalidSpace)?;
+        if let Some(old) = self.space.borrow().as_ref() {
+            for (id, position) in old.iter() {
+                space.set_position(id, position);
+            }
+        }
+        *self.space.borrow_mut() = Some(space);
+        Ok(())
+    }
+
+    pub fn get_space(&self) -> Option<Ref<'_, Space>> {
+        Ref::filter_map(self.space.borrow(), |space| space.as_ref()).ok()
+    }
+
+    pub fn set_position(&self, id: &Uuid, position: Position) -> Result<(), ModelError> {
+        if self.space.borrow().is_none() {
+            return Err(ModelError::SpaceNotEnabled);
+        }
+        if !self.entities.borrow().contains_key(id) {
+            return Err(ModelError::EntityNotFound(*id));
+        }
+        self.set_position_internal(*id, position);
+        Ok(())
+    }
+
+    pub fn remove_position(&self, id: &Uuid) -> Option<Position> {
+        self.space.borrow_mut().as_mut().and_then(|space| space.remove(id))
+    }
+
+    // 古い location 関係を外してから新しい関係を張る。検証に失敗した場合は何も変更しない
+    pub fn move_entity(&self, agent_id: &Uuid, spot_id: &Uuid) -> Result<Rc<Relation>, ModelError> {
+        let definition = self.location_definition();
+        let agent = self.get_entity(agent_id).ok_or(ModelError::EntityNotFound(*agent_id))?;
+        let spot = self.get_entity(spot_id).ok_or(ModelError::EntityNotFound(*spot_id))?;
+        if !self.is_subtype(&agent.entity_type, &definition.source_type) || !self.is_subtype(&spot.entity_type, &definition.target_type) {
+            return Err(ModelError::InvalidRelationEntityTypes);
+        }
+
+        let current = agent.get_outgoing_relations(LOCATION_RELATION);
+        if let Some(relation) = current.iter().find(|r| r.entity2.upgrade().map(|e| e.id) == Some(*spot_id)) {
+            return Ok(Rc::clone(relation));
+        }
+        if !(self as &dyn ReadOnlyModel).has_room(spot_id) {
+            return Err(ModelError::SpotFull(*spot_id));
+        }
+
+        for relation in current {
+            self.delete_relation_internal(relation.id);
+        }
+        self.add_relation(LOCATION_RELATION.to_string(), agent_id, spot_id)
+    }
+
+    fn location_definition(&self) -> RelationshipDefinition {
+        let existing = self.relationship_registry.borrow().get_definition(LOCATION_RELATION).cloned();
+        existing.unwrap_or_else(|| {
+            let definition = RelationshipDefinition {
+                name: LOCATION_RELATION.to_string(),
+                source_type: EntityType::Agent,
+                target_type: EntityType::Spot,
+                relation_type: RelationType::ManyToOne,
+            };
+            self.relationship_registry.borrow_mut().add_definition(definition.clone());
+            definition
+        })
+    }
+
+    pub fn location_of(&self, agent_id: &Uuid) -> Option<Rc<Entity>> {
+        (self as &dyn ReadOnlyModel).location_of(agent_id).and_then(|id| self.get_entity(&id))
+    }
+
+    pub fn agents_on(&self, spot_id: &Uuid) -> Vec<Rc<Entity>> {
+        let ids = (self as &dyn ReadOnlyModel).agents_on(spot_id);
+        self.get_entities_by_ids(ids)
+    }
+
+    pub fn join_set(&self, member_id: &Uuid, set_id: &Uuid) -> Result<Rc<Relation>, ModelError> {
+        let member = self.get_entity(member_id).ok_or(ModelError::EntityNotFound(*member_id))?;
+        let set = self.get_entity(set_id).ok_or(ModelError::EntityNotFound(*set_id))?;
+        let set_type = self.get_base_type(&set.entity_type);
+        let (Some(relation_name), Some(member_type)) = (membership_relation(&set_type), member_type(&set_type)) else {
+            return Err(ModelError::InvalidRelationEntityTypes);
+        };
+        if !self.is_subtype(&member.entity_type, &member_type) {
+            return Err(ModelError::InvalidRelationEntityTypes);
+        }
+
+        let existing = member.get_outgoing_relations(relation_name)
+            .into_iter()
+            .find(|r| r.entity2.upgrade().map(|e| e.id) == Some(*set_id));
+        if let Some(relation) = existing {
+            return Ok(relation);
+        }
+        if self.relationship_registry.borrow().get_definition(relation_name).is_none() {
+            self.relationship_registry.borrow_mut().add_definition(RelationshipDefinition {
+                name: relation_name.to_string(),
+                source_type: member_type,
+                target_type: set_type,
+                relation_type: RelationType::ManyToMany,
+            });
+        }
+        self.add_relation(relation_name.to_string(), member_id, set_id)
+    }
+
+    pub fn leave_set(&self, member_id: &Uuid, set_id: &Uuid) -> Result<(), ModelError> {
+        let member = self.get_entity(member_id).ok_or(ModelError::EntityNotFound(*member_id))?;
+        let set = self.get_entity(set_id).ok_or(ModelError::EntityNotFound(*set_id))?;
+        let relation_name = membership_relation(&self.get_base_type(&set.entity_type)).ok_or(ModelError::InvalidRelationEntityTypes)?;
+        for relation in member.get_outgoing_relations(relation_name) {
+            if relation.entity2.upgrade().map(|e| e.id) == Some(*set_id) {
+                self.delete_relation_internal(relation.id);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn members_of(&self, set_id: &Uuid) -> Vec<Rc<Entity>> {
+        let ids = (self as &dyn ReadOnlyModel).members_of(set_id);
+        self.get_entities_by_ids(ids)
+    }
+
+    pub fn sets_of(&self, member_id: &Uuid) -> Vec<Rc<Entity>> {
+        let ids = (self as &dyn ReadOnlyModel).sets_of(member_id);
+        self.get_entities_by_ids(ids)
+    }
+
+    // 各ステップの終わりに、すべての集合の状態 key をメンバーから再計算する
+    pub fn add_set_aggregate(&self, key: &str, source_key: Option<&str>, aggregate: Aggregate) {
+        self.set_aggregates.borrow_mut().push(SetAggregate {
+            key: key.to_string(),
+            source_key: source_key.map(|k| k.to_string()),
+            aggregate,
+        });
+        self.refresh_set_aggregates();
+    }
+
+    pub fn refresh_set_aggregates(&self) {
+        let aggregates = self.set_aggregates.borrow().clone();
+        if aggregates.is_empty() {
+            return;
+        }
+        let mut sets = self.get_entities_by_type(&EntityType::AgentSet);
+        sets.extend(self.get_entities_by_type(&EntityType::SpotSet));
+        for set in sets {
+            for aggregate in &aggregates {
+                let value = aggregate.compute(self, &set.id);
+                self.update_entity_state_internal(set.id, Symbol::new(&aggregate.key), value);
+            }
+        }
+    }
+
+    pub fn define_context(&self, definition: ContextDefinition) {
+        self.contexts.borrow_mut().insert(definition.name.clone(), definition);
+    }
+
+    pub fn get_context(&self, name: &str) -> Option<ContextDefinition> {
+        self.contexts.borrow().get(name).cloned()
+    }
+
+    pub fn context_graph(&self, name: &str) -> Option<Graph<'_>> {
+        (self as &dyn ReadOnlyModel).context_graph(name)
+    }
+
+    // エンティティ個別の指定がなければモデル全体のコンテキストに従う
+    pub fn active_context(&self, entity_id: &Uuid) -> Option<String> {
+        self.entity_contexts.borrow().get(entity_id).cloned()
+            .or_else(|| self.model_context.borrow().clone())
+    }
+
+    pub fn switch_context(&self, entity_id: &Uuid, context: &str) -> Result<(), ModelError> {
+        if !self.contexts.borrow().contains_key(context) {
+            return Err(ModelError::UndefinedContext(context.to_string()));
+        }
+        let entity = self.get_entity(entity_id).ok_or(ModelError::EntityNotFound(*entity_id))?;
+        self.entity_contexts.borrow_mut().insert(*entity_id, context.to_string());
+        self.apply_context_functions(&entity);
+        Ok(())
+    }
+
+    pub fn switch_model_context(&self, context: &str) -> Result<(), ModelError> {
+        if !self.contexts.borrow().contains_key(context) {
+            return Err(ModelError::UndefinedContext(context.to_string()));
+        }
+        *self.model_context.borrow_mut() = Some(context.to_string());
+        for entity in self.get_all_entities() {
+            if !self.entity_contexts.borrow().contains_key(&entity.id) {
+                self.apply_context_functions(&entity);
+            }
+        }
+        Ok(())
+    }
+
+    // entity_id が None の場合はモデル全体のコンテキストを切り替える
+    pub fn schedule_context(&self, entity_id: Option<Uuid>, schedule: ContextSchedule) {
+        self.context_schedules.borrow_mut().push((entity_id, schedule));
+    }
+
+    fn apply_context_schedules(&self, step: u64) {
+        let switches: Vec<(Option<Uuid>, String)> = self.context_schedules.borrow()
+            .iter()
+            .filter_map(|(entity_id, schedule)| schedule.context_at(step).map(|c| (*entity_id, c.to_string())))
+            .collect();
+        for (entity_id, context) in switches {
+            let result = match entity_id {
+                Some(entity_id) => self.switch_context(&entity_id, &context),
+                None => self.switch_model_context(&context),
+            };
+            self.record_rejection("SwitchContext", result);
+        }
+    }
+
+    // アクティブなコンテキストの関数を有効にし、他のコンテキストにだけ属する関数を無効にする
+    fn apply_context_functions(&self, entity: &Entity) {
+        let active = self.active_context(&entity.id);
+        let contexts = self.contexts.borrow();
+        let enabled: &[String] = active.as_ref()
+            .and_then(|name| contexts.get(name))
+            .map(|definition| definition.functions.as_slice())
+            .unwrap_or(&[]);
+        for definition in contexts.values() {
+            for name in &definition.functions {
+                if let Some(function) = entity.get_function(name) {
+                    if enabled.contains(name) {
+                        function.activate();
+                    } else {
+                        function.deactivate();
+                    }
+                }
+            }
         }
+    }
+
+    pub fn set_clock(&self, clock: Clock) {
+        self.clock.set(Some(clock));
+    }
 
+    pub fn current_time(&self) -> Option<NaiveDateTime> {
+        self.clock.get().and_then(|clock| clock.time_at(self.step.get()))
+    }
+
+    // AgentSet に設定した時間割はそのメンバー全員に適用される
+    pub fn set_timetable(&self, entity_id: &Uuid, timetable: Timetable) -> Result<(), ModelError> {
+        if !self.entities.borrow().contains_key(entity_id) {
+            return Err(ModelError::EntityNotFound(*entity_id));
+        }
+        self.timetables.borrow_mut().insert(*entity_id, timetable);
         Ok(())
     }
 
+    pub fn remove_timetable(&self, entity_id: &Uuid) -> Option<Timetable> {
+        self.timetables.borrow_mut().remove(entity_id)
+    }
+
+    pub fn get_timetable(&self, entity_id: &Uuid) -> Option<Timetable> {
+        self.timetables.borrow().get(entity_id).cloned()
+    }
+
+    // 現在時刻の時間帯に合わせて移動・コンテキスト切り替え・関数の有効化を行う
+    fn apply_timetables(&self) {
+        let Some(time) = self.current_time() else {
+            return;
+        };
+        let timetables: Vec<(Uuid, Timetable)> = self.timetables.borrow()
+            .iter()
+            .map(|(id, timetable)| (*id, timetable.clone()))
+            .collect();
+        for (owner_id, timetable) in timetables {
+            let Some(owner) = self.get_entity(&owner_id) else {
+                continue;
+            };
+            let targets = match self.get_base_type(&owner.entity_type) {
+                EntityType::AgentSet => self.members_of(&owner_id),
+                _ => vec![owner],
+            };
+            let slot = timetable.slot_at(time.time());
+            for entity in targets {
+                if let Some(slot) = slot {
+                    if let Some(spot_id) = slot.spot {
+                        self.record_rejection("MoveEntity", self.move_entity(&entity.id, &spot_id));
+                    }
+                    if let Some(context) = &slot.context {
+                        if self.active_context(&entity.id).as_ref() != Some(context) {
+                            self.record_rejection("SwitchContext", self.switch_context(&entity.id, context));
+                        }
+                    }
+                }
+                for name in timetable.scheduled_functions() {
+                    if let Some(function) = entity.get_function(name) {
+                        if slot.is_some_and(|slot| slot.functions.iter().any(|f| f == name)) {
+                            function.activate();
+                        } else {
+                            function.deactivate();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     pub fn get_relation(&self, id: &Uuid) -> Option<Rc<Relation>> {
         self.relations.borrow().get(id).cloned()
     }
@@ -182,13 +1066,57 @@ impl Model {
         self.relations.borrow().values().cloned().collect()
     }
 
+    pub fn graph(&self, relation_names: &[&str]) -> Graph<'_> {
+        Graph::new(self, relation_names)
+    }
+
+    // ネットワーク指標などの値を各エンティティの状態に書き込む
+    pub fn store_metric(&self, key: &str, values: &HashMap<Uuid, f64>) {
+        self.apply_results(metric_updates(key, values));
+    }
+
     // プロセスを追加するメソッド
     pub fn add_process(&self, process: Rc<Process>) {
         self.processes.borrow_mut().push(process);
     }
 
+    // システムは登録順に、すべてのプロセスの後で実行される。同名のシステムは置き換える
+    pub fn add_system(&self, system: System) -> Rc<System> {
+        let system = Rc::new(system);
+        let mut systems = self.systems.borrow_mut();
+        match systems.iter().position(|s| s.name == system.name) {
+            Some(index) => systems[index] = Rc::clone(&system),
+            None => systems.push(Rc::clone(&system)),
+        }
+        system
+    }
+
+    pub fn get_system(&self, name: &str) -> Option<Rc<System>> {
+        self.systems.borrow().iter().find(|s| s.name == name).cloned()
+    }
+
+    pub fn remove_system(&self, name: &str) -> Option<Rc<System>> {
+        let mut systems = self.systems.borrow_mut();
+        let index = systems.iter().position(|s| s.name == name)?;
+        Some(systems.remove(index))
+    }
+
+    // ステップ終了ごとに呼び出されるオブザーバーを追加するメソッド。
+    // 追加した時点の状態 (最初のステップの前なら初期状態) も一度観測する
+    pub fn add_observer(&self, mut observer: Box<dyn Observer>) {
+        observer.observe(self.step.get(), self);
+        self.observers.borrow_mut().push(observer);
+    }
+
+    pub fn current_step(&self) -> u64 {
+        self.step.get()
+    }
+
     // シミュレーター機能
     pub fn simulate(&self) {
+        self.apply_context_schedules(self.step.get());
+        self.apply_timetables();
+        self.prune_processes();
         let mut results = Vec::new();
         
         // モデルレベルのプロセスを実行
@@ -205,10 +1133,27 @@ impl Model {
                 }
             }
         }
+
+        // システムはプロセスと同じ読み取り専用のモデルを見る
+        let systems = self.systems.borrow().clone();
+        for system in systems {
+            system.run(self, &mut results);
+        }
         self.apply_results(results);
+        self.refresh_set_aggregates();
+
+        self.step.set(self.step.get() + 1);
+        self.notify_observers();
+    }
+
+    fn notify_observers(&self) {
+        let step = self.step.get();
+        for observer in self.observers.borrow_mut().iter_mut() {
+            observer.observe(step, self);
+        }
     }
 
-    fn apply_results(&self, results: Vec<ExecutionResult>) {
+    pub(crate) fn apply_results(&self, results: Vec<ExecutionResult>) {
         for result in results {
             match result {
                 ExecutionResult::CreateEntity(info) => {
@@ -218,7 +1163,7 @@ impl Model {
                     self.delete_entity_internal(id);
                 }
                 ExecutionResult::CreateRelation(info) => {
-                    self.create_relation_internal(info, None);
+                    self.record_rejection("CreateRelation", self.create_relation_internal(info, None));
                 }
                 ExecutionResult::DeleteRelation(id) => {
                     self.delete_relation_internal(id);
@@ -265,31 +1210,71 @@ impl Model {
                 ExecutionResult::RemoveRelationMetadata(relation_id, key) => {
                     self.remove_relation_metadata_internal(relation_id, key);
                 }
+                ExecutionResult::SetPosition(entity_id, position) => {
+                    self.set_position_internal(entity_id, position);
+                }
+                ExecutionResult::RemovePosition(entity_id) => {
+                    self.remove_position(&entity_id);
+                }
+                ExecutionResult::MoveEntity(agent_id, spot_id) => {
+                    self.record_rejection("MoveEntity", self.move_entity(&agent_id, &spot_id));
+                }
+                ExecutionResult::JoinSet(member_id, set_id) => {
+                    self.record_rejection("JoinSet", self.join_set(&member_id, &set_id));
+                }
+                ExecutionResult::LeaveSet(member_id, set_id) => {
+                    self.record_rejection("LeaveSet", self.leave_set(&member_id, &set_id));
+                }
+                ExecutionResult::SwitchContext(entity_id, context) => {
+                    self.record_rejection("SwitchContext", self.switch_context(&entity_id, &context));
+                }
             }
         }
+        self.prune_processes();
     }
     
     fn create_entity_internal(&self, info: EntityCreationInfo) -> Rc<Entity> {
         let entity = Rc::new(Entity::new(info.name, info.entity_type));
-        
+        let schema = self.get_schema(&entity.entity_type);
+
         for (key, value) in info.initial_state {
-            entity.get_state().borrow_mut().set(key, value);
+            match schema.as_ref().map(|schema| schema.check_value(&key, &value)) {
+                Some(Err(kind)) => self.report_violation(&entity, &key, kind),
+                _ => entity.get_state().borrow_mut().set(key, value),
+            }
         }
+        self.fill_schema_defaults(&entity);
+
+        // 関数と関係の追加はモデルに登録済みのエンティティを参照する
+        self.entities.borrow_mut().insert(entity.id, Rc::clone(&entity));
+        self.index.borrow_mut().insert(&entity);
+        self.entity_version.set(self.entity_version.get() + 1);
+        self.watch_state(&entity);
 
         for function_info in info.functions {
             self.add_function_internal(entity.id, function_info);
         }
+        self.attach_behaviors(&entity);
 
         for relation_info in info.relations {
-            self.create_relation_internal(relation_info, Some(entity.id));
+            self.record_rejection("CreateRelation", self.create_relation_internal(relation_info, Some(entity.id)));
         }
-
-        self.entities.borrow_mut().insert(entity.id, Rc::clone(&entity));
         entity
     }
 
     fn delete_entity_internal(&self, id: Uuid) {
-        if let Some(entity) = self.entities.borrow_mut().remove(&id) {
+        let removed = self.entities.borrow_mut().remove(&id);
+        if let Some(entity) = removed {
+            self.index.borrow_mut().remove(&entity);
+            self.entity_version.set(self.entity_version.get() + 1);
+            self.entity_refs.borrow_mut().remove(id);
+            self.remove_position(&id);
+            self.entity_contexts.borrow_mut().remove(&id);
+            self.context_schedules.borrow_mut().retain(|(entity_id, _)| *entity_id != Some(id));
+            self.timetables.borrow_mut().remove(&id);
+            self.inherited_functions.borrow_mut().remove(&id);
+            self.clear_entity_refs(&id);
+
             let relations_to_remove: Vec<Uuid> = self.relations.borrow()
                 .values()
                 .filter(|r| r.entity1.upgrade().map(|e| e.id) == Some(id) || r.entity2.upgrade().map(|e| e.id) == Some(id))
@@ -300,57 +1285,75 @@ impl Model {
                 self.delete_relation_internal(relation_id);
             }
 
-            self.processes.borrow_mut().retain(|process| {
-                process.owner.upgrade()
-                    .and_then(|f| f.owner.upgrade())
-                    .map(|e| e.id != id)
-                    .unwrap_or(true)
-            });
-
             for function in entity.get_all_functions() {
-                self.remove_function_internal(id, function.name.clone());
+                entity.remove_function(&function.name);
             }
+            self.stale_processes.set(true);
         }
     }
 
-    fn create_relation_internal(&self, info: RelationCreationInfo, source_entity_id: Option<Uuid>) {
-        let registry = self.relationship_registry.borrow();
-        let definition = registry.get_definition(&info.name)
-            .expect("Relation definition not found");
-        let source_id = source_entity_id.unwrap_or(info.target_entity_id.unwrap());
-        let target_id = info.target_entity_id.unwrap_or_else(|| {
-            self.entities.borrow().values()
-                .find(|e| e.name == *info.target_entity_name.as_ref().unwrap())
-                .map(|e| e.id)
-                .expect("Target entity not found")
-        });
-
-        let relation = Rc::new(Relation::new(
-            info.name.clone(),
-            definition.relation_type,
-            Rc::downgrade(&self.entities.borrow()[&source_id]),
-            Rc::downgrade(&self.entities.borrow()[&target_id])
-        ));
-
-        self.relations.borrow_mut().insert(relation.id, Rc::clone(&relation));
+    // 削除されたエンティティを指す EntityRef を、状態・パラメータ・関係メタデータから Null にする。
+    // 逆引きで参照を持つエンティティと関係だけを調べる
+    fn clear_entity_refs(&self, id: &Uuid) {
+        self.sync_state_index();
+        let holders = self.entity_refs.borrow().holders_of(id);
+        let mut updates = Vec::new();
+        for holder in holders {
+            if let Some(entity) = self.get_entity(&holder) {
+                for (key, value) in entity.get_state().borrow().iter() {
+                    if matches!(value, Value::EntityRef(_) | Value::Array(_) | Value::Map(_)) {
+                        let mut value = value.clone();
+                        if value.clear_entity_ref(id) {
+                            updates.push((entity.id, *key, value));
+                        }
+                    }
+                }
+                for function in entity.get_all_functions() {
+                    for (_, value) in function.get_parameter().borrow_mut().iter_mut() {
+                        value.clear_entity_ref(id);
+                    }
+                }
+            } else if let Some(relation) = self.relations.borrow().get(&holder) {
+                for (_, value) in relation.get_meta().borrow_mut().iter_mut() {
+                    value.clear_entity_ref(id);
+                }
+            }
+        }
+        for (entity_id, key, value) in updates {
+            self.update_entity_state_internal(entity_id, key, value);
+        }
+    }
 
-        self.entities.borrow()[&source_id].add_relation(info.name.clone(), Rc::downgrade(&relation));
-        self.entities.borrow()[&target_id].add_relation(info.name, Rc::downgrade(&relation));
+    // 端点を解決したうえで add_relation と同じ検査を通す
+    fn create_relation_internal(&self, info: RelationCreationInfo, source_entity_id: Option<Uuid>) -> Result<Rc<Relation>, ModelError> {
+        let target_id = match (info.target_entity_id, &info.target_entity_name) {
+            (Some(id), _) => id,
+            (None, Some(name)) => self.index.borrow()
+                .ids_by_name(name)
+                .first()
+                .copied()
+                .ok_or_else(|| ModelError::EntityNameNotFound(name.clone()))?,
+            (None, None) => return Err(ModelError::MissingRelationTarget(info.name)),
+        };
+        let source_id = source_entity_id.unwrap_or(target_id);
 
+        let relation = self.add_relation(info.name, &source_id, &target_id)?;
         if let Some(metadata) = info.metadata {
             for (key, value) in metadata {
-                self.add_relation_metadata_internal(relation.id, key, value);
+                self.add_relation_metadata_internal(relation.id, key.into(), value);
             }
         }
+        Ok(relation)
     }
 
     fn delete_relation_internal(&self, id: Uuid) {
         if let Some(relation) = self.relations.borrow_mut().remove(&id) {
+            self.entity_refs.borrow_mut().remove(id);
             if let Some(entity1) = relation.entity1.upgrade() {
-                entity1.remove_relation(&relation.name, relation.id);
+                entity1.remove_relation(relation.name, relation.id);
             }
             if let Some(entity2) = relation.entity2.upgrade() {
-                entity2.remove_relation(&relation.name, relation.id);
+                entity2.remove_relation(relation.name, relation.id);
             }
         }
     }
@@ -366,25 +1369,26 @@ impl Model {
                 function.get_parameter().borrow_mut().set(key, value);
             }
 
-            for process_info in function_info.processes {
-                self.add_process_internal(entity_id, function.name.clone(), process_info);
+            // 同名の関数は置き換え、古い関数のプロセスは後でまとめて取り除く
+            if entity.add_function(Rc::clone(&function)).is_some() {
+                self.stale_processes.set(true);
             }
 
-            entity.add_function(Rc::clone(&function));
+            for process_info in function_info.processes {
+                self.add_process_internal(entity_id, function.name, process_info);
+            }
         }
     }
 
-    fn remove_function_internal(&self, entity_id: Uuid, function_name: String) {
+    fn remove_function_internal(&self, entity_id: Uuid, function_name: Symbol) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if entity.remove_function(&function_name).is_some() {
-                self.processes.borrow_mut().retain(|process| {
-                    process.owner.upgrade().map(|f| f.name != function_name).unwrap_or(true)
-                });
+                self.stale_processes.set(true);
             }
         }
     }
 
-    fn activate_function_internal(&self, entity_id: Uuid, function_name: String) {
+    fn activate_function_internal(&self, entity_id: Uuid, function_name: Symbol) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 function.activate();
@@ -392,7 +1396,7 @@ impl Model {
         }
     }
 
-    fn deactivate_function_internal(&self, entity_id: Uuid, function_name: String) {
+    fn deactivate_function_internal(&self, entity_id: Uuid, function_name: Symbol) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 function.deactivate();
@@ -400,7 +1404,7 @@ impl Model {
         }
     }
 
-    fn add_process_internal(&self, entity_id: Uuid, function_name: String, process_info: ProcessCreationInfo) {
+    fn add_process_internal(&self, entity_id: Uuid, function_name: Symbol, process_info: ProcessCreationInfo) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 let process = Rc::new(Process::new(
@@ -418,28 +1422,38 @@ impl Model {
         }
     }
 
-    fn remove_process_internal(&self, entity_id: Uuid, function_name: String, process_name: String) {
+    fn remove_process_internal(&self, entity_id: Uuid, function_name: Symbol, process_name: String) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
-                function.remove_process(&process_name);
-                self.processes.borrow_mut().retain(|p| p.name != process_name);
+                if let Some(removed) = function.remove_process(&process_name) {
+                    self.processes.borrow_mut().retain(|p| !Rc::ptr_eq(p, &removed));
+                }
             }
         }
     }
 
-    fn update_entity_state_internal(&self, entity_id: Uuid, key: String, value: Value) {
+    // スキーマに違反する更新は適用せず、違反として記録する
+    fn update_entity_state_internal(&self, entity_id: Uuid, key: Symbol, value: Value) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
+            if let Some(Err(kind)) = self.get_schema(&entity.entity_type).map(|schema| schema.check_value(&key, &value)) {
+                self.report_violation(entity, &key, kind);
+                return;
+            }
             entity.get_state().borrow_mut().set(key, value);
         }
     }
 
-    fn delete_entity_state_internal(&self, entity_id: Uuid, key: String) {
+    fn delete_entity_state_internal(&self, entity_id: Uuid, key: Symbol) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
+            if let Some(Err(kind)) = self.get_schema(&entity.entity_type).map(|schema| schema.check_delete(&key)) {
+                self.report_violation(entity, &key, kind);
+                return;
+            }
             entity.get_state().borrow_mut().remove(&key);
         }
     }
 
-    fn update_function_parameter_internal(&self, entity_id: Uuid, function_name: String, key: String, value: Value) {
+    fn update_function_parameter_internal(&self, entity_id: Uuid, function_name: Symbol, key: Symbol, value: Value) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 function.get_parameter().borrow_mut().set(key, value);
@@ -447,7 +1461,7 @@ impl Model {
         }
     }
 
-    fn delete_function_parameter_internal(&self, entity_id: Uuid, function_name: String, key: String) {
+    fn delete_function_parameter_internal(&self, entity_id: Uuid, function_name: Symbol, key: Symbol) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 function.get_parameter().borrow_mut().remove(&key);
@@ -455,7 +1469,7 @@ impl Model {
         }
     }
 
-    fn add_condition_internal(&self, entity_id: Uuid, function_name: String, process_name: String, condition: Box<dyn Condition>) {
+    fn add_condition_internal(&self, entity_id: Uuid, function_name: Symbol, process_name: String, condition: Box<dyn Condition>) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 if let Some(process) = function.get_process(&process_name) {
@@ -465,7 +1479,7 @@ impl Model {
         }
     }
 
-    fn remove_condition_internal(&self, entity_id: Uuid, function_name: String, process_name: String) {
+    fn remove_condition_internal(&self, entity_id: Uuid, function_name: Symbol, process_name: String) {
         if let Some(entity) = self.entities.borrow().get(&entity_id) {
             if let Some(function) = entity.get_function(&function_name) {
                 if let Some(process) = function.get_process(&process_name) {
@@ -475,17 +1489,26 @@ impl Model {
         }
     }
 
-    fn add_relation_metadata_internal(&self, relation_id: Uuid, key: String, value: Value) {
+    fn add_relation_metadata_internal(&self, relation_id: Uuid, key: Symbol, value: Value) {
         if let Some(relation) = self.relations.borrow().get(&relation_id) {
             relation.add_metadata(key, value);
         }
     }
 
-    fn remove_relation_metadata_internal(&self, relation_id: Uuid, key: String) {
+    fn remove_relation_metadata_internal(&self, relation_id: Uuid, key: Symbol) {
         if let Some(relation) = self.relations.borrow().get(&relation_id) {
             relation.remove_metadata(&key);
         }
     }
+
+    fn set_position_internal(&self, entity_id: Uuid, position: Position) {
+        if !self.entities.borrow().contains_key(&entity_id) {
+            return;
+        }
+        if let Some(space) = self.space.borrow_mut().as_mut() {
+            space.set_position(entity_id, position);
+        }
+    }
 }
 
 impl ReadOnlyModel for Model {
@@ -505,6 +1528,54 @@ impl ReadOnlyModel for Model {
         self.get_entities_by_name(name).into_iter().map(|e| e as Rc<dyn ReadOnlyEntity>).collect()
     }
 
+    fn get_entities_by_state(&self, key: &str, value: &Value) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        self.get_entities_by_state(key, value).into_iter().map(|e| e as Rc<dyn ReadOnlyEntity>).collect()
+    }
+
+    fn has_state_index(&self, key: &str) -> bool {
+        self.has_state_index(key)
+    }
+
+    fn get_lattice(&self, relation_name: &str) -> Option<Rc<Lattice>> {
+        self.get_lattice(relation_name)
+    }
+
+    fn get_space(&self) -> Option<Ref<'_, Space>> {
+        self.get_space()
+    }
+
+    fn get_context(&self, name: &str) -> Option<ContextDefinition> {
+        self.get_context(name)
+    }
+
+    fn get_active_context(&self, entity_id: &Uuid) -> Option<String> {
+        self.active_context(entity_id)
+    }
+
+    fn get_current_time(&self) -> Option<NaiveDateTime> {
+        self.current_time()
+    }
+
+    fn get_schema(&self, entity_type: &EntityType) -> Option<Rc<StateSchema>> {
+        self.get_schema(entity_type)
+    }
+
+    fn get_timetable(&self, entity_id: &Uuid) -> Option<Timetable> {
+        self.get_timetable(entity_id)
+    }
+
+    fn is_subtype(&self, entity_type: &EntityType, ancestor: &EntityType) -> bool {
+        self.is_subtype(entity_type, ancestor)
+    }
+
+    fn get_subtypes(&self, entity_type: &EntityType) -> Vec<EntityType> {
+        self.get_subtypes(entity_type)
+    }
+
+    fn get_base_type(&self, entity_type: &EntityType) -> EntityType {
+        self.get_base_type(entity_type)
+    }
+
     fn get_relation(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyRelation>> {
         self.get_relation(id).map(|r| r as Rc<dyn ReadOnlyRelation>)
     }
@@ -512,4 +1583,230 @@ impl ReadOnlyModel for Model {
     fn get_all_relations(&self) -> Vec<Rc<dyn ReadOnlyRelation>> {
         self.get_all_relations().into_iter().map(|r| r as Rc<dyn ReadOnlyRelation>).collect()
     }
-}
\ No newline at end of file
+
+    fn entity_set_version(&self) -> Option<u64> {
+        Some(self.entity_version.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair_model(relation_type: RelationType) -> (Model, Vec<Uuid>) {
+        let model = Model::new();
+        model.define_relationship("r".to_string(), EntityType::Agent, EntityType::Agent, relation_type).unwrap();
+        let ids = (0..3).map(|i| model.create_entity(format!("a{}", i), EntityType::Agent).id).collect();
+        (model, ids)
+    }
+
+    #[test]
+    fn one_to_many_limits_incoming_per_target() {
+        let (model, ids) = pair_model(RelationType::OneToMany);
+        assert!(model.add_relation("r".to_string(), &ids[0], &ids[1]).is_ok());
+        assert!(model.add_relation("r".to_string(), &ids[0], &ids[2]).is_ok());
+        assert!(matches!(
+            model.add_relation("r".to_string(), &ids[2], &ids[1]),
+            Err(ModelError::InvalidRelationType { .. })
+        ));
+    }
+
+    #[test]
+    fn many_to_one_limits_outgoing_per_source() {
+        let (model, ids) = pair_model(RelationType::ManyToOne);
+        assert!(model.add_relation("r".to_string(), &ids[0], &ids[2]).is_ok());
+        assert!(model.add_relation("r".to_string(), &ids[1], &ids[2]).is_ok());
+        assert!(model.add_relation("r".to_string(), &ids[0], &ids[1]).is_err());
+    }
+
+    #[test]
+    fn one_to_one_limits_both_ends() {
+        let (model, ids) = pair_model(RelationType::OneToOne);
+        assert!(model.add_relation("r".to_string(), &ids[0], &ids[1]).is_ok());
+        assert!(model.add_relation("r".to_string(), &ids[0], &ids[2]).is_err());
+        assert!(model.add_relation("r".to_string(), &ids[2], &ids[1]).is_err());
+        assert!(model.add_relation("r".to_string(), &ids[1], &ids[2]).is_ok());
+    }
+
+    #[test]
+    fn add_relation_checks_definition_and_entities() {
+        let (model, ids) = pair_model(RelationType::ManyToMany);
+        assert!(matches!(model.add_relation("x".to_string(), &ids[0], &ids[1]), Err(ModelError::UndefinedRelation(_))));
+        assert!(matches!(model.add_relation("r".to_string(), &ids[0], &Uuid::new_v4()), Err(ModelError::EntityNotFound(_))));
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        assert!(matches!(model.add_relation("r".to_string(), &ids[0], &spot.id), Err(ModelError::InvalidRelationEntityTypes)));
+    }
+
+    #[test]
+    fn created_relations_are_checked_and_rejections_recorded() {
+        let (model, ids) = pair_model(RelationType::OneToMany);
+        let spot = model.create_entity("s".to_string(), EntityType::Spot);
+        let relation = |name: &str, target_entity_id: Option<Uuid>, target_entity_name: Option<&str>| RelationCreationInfo {
+            name: name.to_string(),
+            relation_type: RelationType::OneToMany,
+            target_entity_id,
+            target_entity_name: target_entity_name.map(str::to_string),
+            metadata: Some(HashMap::from([("weight".to_string(), Value::Float64(0.5))])),
+        };
+
+        model.apply_results(vec![
+            ExecutionResult::CreateRelation(relation("x", Some(ids[0]), None)),
+            ExecutionResult::CreateEntity(EntityCreationInfo {
+                name: "n".to_string(),
+                entity_type: EntityType::Agent,
+                initial_state: HashMap::new(),
+                functions: Vec::new(),
+                relations: vec![
+                    relation("r", None, Some("a1")),
+                    relation("r", None, Some("missing")),
+                    relation("r", Some(spot.id), None),
+                    relation("r", None, None),
+                    // a1 はすでに入力を持つので OneToMany に反する
+                    relation("r", Some(ids[1]), None),
+                ],
+            }),
+        ]);
+
+        let created = model.get_entities_by_name("n");
+        let relations = created[0].get_outgoing_relations("r");
+        assert_eq!(relations.len(), 1);
+        assert_eq!(relations[0].get_meta().borrow().get("weight"), Some(&Value::Float64(0.5)));
+        let errors: Vec<_> = model.take_rejected_results().into_iter().map(|r| (r.kind, r.error)).collect();
+        assert_eq!(errors, vec![
+            ("CreateRelation", ModelError::UndefinedRelation("x".to_string())),
+            ("CreateRelation", ModelError::EntityNameNotFound("missing".to_string())),
+            ("CreateRelation", ModelError::InvalidRelationEntityTypes),
+            ("CreateRelation", ModelError::MissingRelationTarget("r".to_string())),
+            ("CreateRelation", ModelError::InvalidRelationType { name: "r".to_string(), relation_type: RelationType::OneToMany }),
+        ]);
+    }
+
+    #[test]
+    fn state_indexes_stay_current_when_writes_are_not_logged() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        a.get_state().borrow_mut().set("sick", Value::Boolean(true));
+        model.create_state_index("sick");
+        assert_eq!(model.get_entities_by_state("sick", &Value::Boolean(true)).len(), 1);
+
+        model.drop_state_index("sick");
+        a.get_state().borrow_mut().set("sick", Value::Boolean(false));
+        assert!(model.state_log.take().is_empty());
+        model.create_state_index("sick");
+        assert!(model.get_entities_by_state("sick", &Value::Boolean(true)).is_empty());
+        a.get_state().borrow_mut().set("sick", Value::Boolean(true));
+        assert_eq!(model.get_entities_by_state("sick", &Value::Boolean(true)).len(), 1);
+    }
+
+    #[test]
+    fn deleting_an_entity_clears_references_to_it() {
+        let (model, ids) = pair_model(RelationType::ManyToMany);
+        let target = model.create_entity("target".to_string(), EntityType::Agent);
+        let holder = model.get_entity(&ids[0]).unwrap();
+        holder.get_state().borrow_mut().set("friend", Value::EntityRef(target.id));
+        holder.get_state().borrow_mut().set("others", Value::Array(vec![Value::EntityRef(target.id), Value::Integer(1)]));
+        let function = Rc::new(Function::new("f".to_string(), Rc::downgrade(&holder)));
+        holder.add_function(Rc::clone(&function));
+        // 関数を追加した後のパラメータの直接の書き換えも逆引きに反映される
+        function.get_parameter().borrow_mut().set("target", Value::EntityRef(target.id));
+        let relation = model.add_relation("r".to_string(), &ids[1], &ids[2]).unwrap();
+        relation.add_metadata("via", Value::EntityRef(target.id));
+
+        model.apply_results(vec![ExecutionResult::DeleteEntity(target.id)]);
+        let state = holder.get_state().borrow();
+        assert_eq!(state.get("friend"), Some(&Value::Null));
+        assert_eq!(state.get("others"), Some(&Value::Array(vec![Value::Null, Value::Integer(1)])));
+        assert_eq!(function.get_parameter().borrow().get("target"), Some(&Value::Null));
+        assert_eq!(relation.get_meta_value("via"), Some(Value::Null));
+    }
+
+    #[test]
+    fn reference_index_follows_overwrites_and_deletions() {
+        let (model, ids) = pair_model(RelationType::ManyToMany);
+        let holder = model.get_entity(&ids[0]).unwrap();
+        holder.get_state().borrow_mut().set("friend", Value::EntityRef(ids[1]));
+        model.sync_state_index();
+        assert_eq!(model.entity_refs.borrow().holders_of(&ids[1]), vec![ids[0]]);
+
+        holder.get_state().borrow_mut().set("friend", Value::EntityRef(ids[2]));
+        model.sync_state_index();
+        assert!(model.entity_refs.borrow().holders_of(&ids[1]).is_empty());
+        assert_eq!(model.entity_refs.borrow().holders_of(&ids[2]), vec![ids[0]]);
+
+        let relation = model.add_relation("r".to_string(), &ids[1], &ids[2]).unwrap();
+        relation.add_metadata("via", Value::EntityRef(ids[0]));
+        model.sync_state_index();
+        assert_eq!(model.entity_refs.borrow().holders_of(&ids[0]), vec![relation.id]);
+        model.remove_relation(&relation.id).unwrap();
+        assert!(model.entity_refs.borrow().holders_of(&ids[0]).is_empty());
+
+        model.apply_results(vec![ExecutionResult::DeleteEntity(ids[0])]);
+        assert!(model.entity_refs.borrow().holders_of(&ids[2]).is_empty());
+    }
+
+    fn function_info(name: &str, processes: &[&str]) -> FunctionCreationInfo {
+        FunctionCreationInfo {
+            name: name.to_string(),
+            initial_parameters: HashMap::new(),
+            processes: processes
+                .iter()
+                .map(|process| ProcessCreationInfo {
+                    name: process.to_string(),
+                    action: Box::new(|_: &ExecutionContext| Vec::new()),
+                    condition: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn process_owners(model: &Model) -> Vec<Uuid> {
+        let mut owners: Vec<Uuid> = model.processes.borrow()
+            .iter()
+            .filter_map(|p| p.owner.upgrade().and_then(|f| f.owner.upgrade()).map(|e| e.id))
+            .collect();
+        owners.sort();
+        owners
+    }
+
+    #[test]
+    fn replaced_functions_drop_their_processes_once_per_batch() {
+        let (model, ids) = pair_model(RelationType::ManyToMany);
+        model.apply_results(ids.iter().map(|id| ExecutionResult::AddFunction(*id, function_info("f", &["p"]))).collect());
+        assert_eq!(model.processes.borrow().len(), 3);
+
+        model.apply_results(vec![
+            ExecutionResult::AddFunction(ids[0], function_info("f", &["p", "q"])),
+            ExecutionResult::AddFunction(ids[1], function_info("f", &["p"])),
+        ]);
+        assert!(!model.stale_processes.get());
+        assert_eq!(model.processes.borrow().len(), 4);
+        assert_eq!(model.processes.borrow().iter().filter(|p| p.owner.strong_count() == 0).count(), 0);
+    }
+
+    #[test]
+    fn removing_a_function_or_process_only_affects_its_entity() {
+        let (model, ids) = pair_model(RelationType::ManyToMany);
+        model.apply_results(ids.iter().map(|id| ExecutionResult::AddFunction(*id, function_info("f", &["p"]))).collect());
+
+        model.apply_results(vec![ExecutionResult::remove_function(ids[0], "f")]);
+        let mut expected = vec![ids[1], ids[2]];
+        expected.sort();
+        assert_eq!(process_owners(&model), expected);
+
+        model.apply_results(vec![ExecutionResult::RemoveProcess(ids[1], Symbol::new("f"), "p".to_string())]);
+        assert_eq!(process_owners(&model), vec![ids[2]]);
+
+        model.apply_results(vec![ExecutionResult::DeleteEntity(ids[2])]);
+        assert!(model.processes.borrow().is_empty());
+    }
+
+    #[test]
+    fn redefining_a_behavior_replaces_inherited_processes() {
+        let (model, _) = pair_model(RelationType::ManyToMany);
+        model.define_behavior(EntityType::Agent, Behavior::new("b").process("p", |_| Vec::new()));
+        assert_eq!(model.processes.borrow().len(), 3);
+        model.define_behavior(EntityType::Agent, Behavior::new("b").process("p", |_| Vec::new()).process("q", |_| Vec::new()));
+        assert_eq!(model.processes.borrow().len(), 6);
+        assert!(model.processes.borrow().iter().all(|p| p.owner.strong_count() > 0));
+    }
+}
@@ -0,0 +1,227 @@
+use std::rc::Rc;
+use uuid::Uuid;
+use crate::context::{ExecutionContext, ReadOnlyModel, ReadOnlyRelation};
+use crate::graph::{Direction, Graph};
+use crate::model::ModelError;
+use crate::result::ExecutionResult;
+use crate::variable::Value;
+
+// 家庭・職場・学校のような名前付きコンテキスト。関係レイヤーと、そのコンテキストでのみ有効な関数を持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextDefinition {
+    pub name: String,
+    pub relation_names: Vec<String>,
+    pub functions: Vec<String>,
+}
+
+impl ContextDefinition {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            relation_names: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
+    pub fn relation(mut self, relation_name: &str) -> Self {
+        self.relation_names.push(relation_name.to_string());
+        self
+    }
+
+    pub fn function(mut self, function_name: &str) -> Self {
+        self.functions.push(function_name.to_string());
+        self
+    }
+}
+
+// コンテキスト固有の状態は "コンテキスト名::キー" として保存する
+pub fn scoped_key(context: &str, key: &str) -> String {
+    format!("{}::{}", context, key)
+}
+
+// period ステップ周期で、周期内のオフセットに応じてコンテキストを切り替える
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextSchedule {
+    pub period: u64,
+    pub entries: Vec<(u64, String)>,
+}
+
+impl ContextSchedule {
+    pub fn new(period: u64) -> Result<Self, ModelError> {
+        if period == 0 {
+            return Err(ModelError::InvalidSchedule("period must be positive".to_string()));
+        }
+        Ok(Self { period, entries: Vec::new() })
+    }
+
+    pub fn at(mut self, offset: u64, context: &str) -> Self {
+        self.entries.push((offset.checked_rem(self.period).unwrap_or(offset), context.to_string()));
+        self
+    }
+
+    // フィールドを直接組み立てた周期 0 のスケジュールは何も切り替えない
+    pub fn context_at(&self, step: u64) -> Option<&str> {
+        let offset = step.checked_rem(self.period)?;
+        self.entries
+            .iter()
+            .find(|(at, _)| *at == offset)
+            .map(|(_, context)| context.as_str())
+    }
+}
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn context_graph(&self, context: &str) -> Option<Graph<'_>> {
+        let definition = self.get_context(context)?;
+        let names: Vec<&str> = definition.relation_names.iter().map(|s| s.as_str()).collect();
+        Some(Graph::new(self, &names))
+    }
+}
+
+impl ExecutionContext<'_> {
+    pub fn active_context(&self) -> Option<String> {
+        self.model.get_active_context(&self.owner_entity.get_id())
+    }
+
+    pub fn in_context(&self, context: &str) -> bool {
+        self.active_context().as_deref() == Some(context)
+    }
+
+    // アクティブなコンテキストの状態を優先し、なければ共通の状態を返す
+    pub fn context_state(&self, key: &str) -> Option<Value> {
+        let state = self.owner_entity.get_state();
+        self.active_context()
+            .and_then(|context| state.get(&scoped_key(&context, key)).cloned())
+            .or_else(|| state.get(key).cloned())
+    }
+
+    pub fn set_context_state(&self, key: &str, value: Value) -> ExecutionResult {
+        let id = self.owner_entity.get_id();
+        let key = match self.active_context() {
+            Some(context) => scoped_key(&context, key),
+            None => key.to_string(),
+        };
+        ExecutionResult::UpdateEntityState(id, key.into(), value)
+    }
+
+    pub fn context_relations(&self) -> Vec<Rc<dyn ReadOnlyRelation>> {
+        let Some(definition) = self.active_context().and_then(|context| self.model.get_context(&context)) else {
+            return Vec::new();
+        };
+        definition.relation_names
+            .iter()
+            .flat_map(|name| self.owner_entity.get_relations(name))
+            .collect()
+    }
+
+    pub fn context_neighbors(&self) -> Vec<Uuid> {
+        self.active_context()
+            .and_then(|context| self.model.context_graph(&context))
+            .map(|graph| graph.direction(Direction::Both).neighbors(&self.owner_entity.get_id()))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::model::Model;
+    use crate::types::{EntityType, RelationType};
+
+    #[test]
+    fn schedule_rejects_zero_period_and_wraps_offsets() {
+        assert!(matches!(ContextSchedule::new(0), Err(ModelError::InvalidSchedule(_))));
+
+        let schedule = ContextSchedule::new(3).unwrap().at(0, "home").at(4, "work");
+        assert_eq!(schedule.context_at(0), Some("home"));
+        assert_eq!(schedule.context_at(1), Some("work"));
+        assert_eq!(schedule.context_at(2), None);
+        assert_eq!(schedule.context_at(6), Some("home"));
+
+        let broken = ContextSchedule { period: 0, entries: vec![(0, "home".to_string())] };
+        assert_eq!(broken.context_at(0), None);
+    }
+
+    #[test]
+    fn scheduled_switch_toggles_context_functions() {
+        let model = Model::new();
+        model.define_context(ContextDefinition::new("home").function("sleep"));
+        model.define_context(ContextDefinition::new("work").function("labor"));
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        for name in ["sleep", "labor"] {
+            let function = Function::new(name.to_string(), Rc::downgrade(&agent));
+            function.activate();
+            agent.add_function(Rc::new(function));
+        }
+        model.schedule_context(None, ContextSchedule::new(2).unwrap().at(0, "home").at(1, "work"));
+
+        model.simulate();
+        assert_eq!(model.active_context(&agent.id), Some("home".to_string()));
+        assert!(agent.get_function("sleep").unwrap().is_active());
+        assert!(!agent.get_function("labor").unwrap().is_active());
+
+        model.simulate();
+        assert_eq!(model.active_context(&agent.id), Some("work".to_string()));
+        assert!(agent.get_function("labor").unwrap().is_active());
+        assert!(!agent.get_function("sleep").unwrap().is_active());
+
+        // 個別の指定はモデル全体の切り替えより優先される
+        model.switch_context(&agent.id, "home").unwrap();
+        model.switch_model_context("work").unwrap();
+        assert_eq!(model.active_context(&agent.id), Some("home".to_string()));
+        assert!(matches!(model.switch_model_context("school"), Err(ModelError::UndefinedContext(_))));
+    }
+
+    #[test]
+    fn undefined_context_switches_are_recorded() {
+        let model = Model::new();
+        model.define_context(ContextDefinition::new("home"));
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        model.schedule_context(None, ContextSchedule::new(1).unwrap().at(0, "school"));
+
+        model.simulate();
+        model.apply_results(vec![
+            ExecutionResult::SwitchContext(agent.id, "office".to_string()),
+            ExecutionResult::SwitchContext(agent.id, "home".to_string()),
+        ]);
+        assert_eq!(model.active_context(&agent.id), Some("home".to_string()));
+        let rejected: Vec<_> = model.take_rejected_results().into_iter().map(|r| (r.step, r.kind, r.error)).collect();
+        assert_eq!(rejected, vec![
+            (0, "SwitchContext", ModelError::UndefinedContext("school".to_string())),
+            (1, "SwitchContext", ModelError::UndefinedContext("office".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn context_state_and_relations() {
+        let model = Model::new();
+        model.define_relationship("household".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        model.define_relationship("colleague".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToMany).unwrap();
+        model.define_context(ContextDefinition::new("home").relation("household"));
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        let c = model.create_entity("c".to_string(), EntityType::Agent);
+        model.add_relation("household".to_string(), &a.id, &b.id).unwrap();
+        model.add_relation("colleague".to_string(), &a.id, &c.id).unwrap();
+        {
+            let mut state = a.get_state().borrow_mut();
+            state.set("mood", Value::String("neutral".to_string()));
+            state.set(scoped_key("home", "mood"), Value::String("relaxed".to_string()));
+        }
+
+        let function = Function::new("f".to_string(), Rc::downgrade(&a));
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*a, model: &model };
+        assert_eq!(context.context_state("mood"), Some(Value::String("neutral".to_string())));
+        assert!(context.context_relations().is_empty());
+
+        model.switch_context(&a.id, "home").unwrap();
+        assert!(context.in_context("home"));
+        assert_eq!(context.context_state("mood"), Some(Value::String("relaxed".to_string())));
+        assert_eq!(context.context_neighbors(), vec![b.id]);
+        assert_eq!(context.context_relations().len(), 1);
+        assert!(matches!(
+            context.set_context_state("mood", Value::Null),
+            ExecutionResult::UpdateEntityState(id, key, _) if id == a.id && key.as_str() == "home::mood"
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use uuid::Uuid;
+use crate::model::ModelError;
+use crate::relation::Relation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkGenerator {
+    ErdosRenyi { p: f64 },
+    WattsStrogatz { k: usize, beta: f64 },
+    BarabasiAlbert { m: usize },
+    StochasticBlock { sizes: Vec<usize>, probabilities: Vec<Vec<f64>> },
+    Configuration { degrees: Vec<usize> },
+}
+
+// generate_network の結果。作成した関係と、制約に反して作成しなかった辺
+#[derive(Debug, Default)]
+pub struct NetworkReport {
+    pub relations: Vec<Rc<Relation>>,
+    pub rejected: Vec<RejectedEdge>,
+}
+
+#[derive(Debug)]
+pub struct RejectedEdge {
+    pub source: Uuid,
+    pub target: Uuid,
+    pub error: ModelError,
+}
+
+impl NetworkGenerator {
+    // n 個のノードに対する無向辺 (i, j) の列を生成する
+    pub fn edges<R: Rng>(&self, n: usize, rng: &mut R) -> Result<Vec<(usize, usize)>, String> {
+        let mut edges = EdgeSet::default();
+        match self {
+            NetworkGenerator::ErdosRenyi { p } => {
+                check_probability(*p)?;
+                for i in 0..n {
+                    for j in (i + 1)..n {
+                        if rng.gen_bool(*p) {
+                            edges.insert(i, j);
+                        }
+                    }
+                }
+            }
+            NetworkGenerator::WattsStrogatz { k, beta } => {
+                check_probability(*beta)?;
+                if k % 2 != 0 || *k >= n {
+                    return Err(format!("k must be even and smaller than n ({}), got {}", n, k));
+                }
+                for i in 0..n {
+                    for j in 1..=(k / 2) {
+                        edges.insert(i, (i + j) % n);
+                    }
+                }
+                // 各ノードの右側の辺を確率 beta で張り替える
+                for j in 1..=(k / 2) {
+                    for i in 0..n {
+                        let target = (i + j) % n;
+                        if !rng.gen_bool(*beta) || !edges.contains(i, target) {
+                            continue;
+                        }
+                        // 完全グラフに近い場合に備えて試行回数を制限する
+                        let new_target = (0..n)
+                            .map(|_| rng.gen_range(0..n))
+                            .find(|&c| c != i && !edges.contains(i, c));
+                        if let Some(new_target) = new_target {
+                            edges.remove(i, target);
+                            edges.insert(i, new_target);
+                        }
+                    }
+                }
+            }
+            NetworkGenerator::BarabasiAlbert { m } => {
+                if *m == 0 || *m >= n {
+                    return Err(format!("m must satisfy 1 <= m < n ({}), got {}", n, m));
+                }
+                let mut targets: Vec<usize> = (0..*m).collect();
+                let mut repeated: Vec<usize> = Vec::new();
+                for source in *m..n {
+                    for &target in &targets {
+                        edges.insert(source, target);
+                    }
+                    repeated.extend(targets.iter().copied());
+                    repeated.extend(std::iter::repeat_n(source, *m));
+
+                    let mut chosen = HashSet::new();
+                    while chosen.len() < *m {
+                        chosen.insert(*repeated.choose(rng).unwrap());
+                    }
+                    targets = chosen.into_iter().collect();
+                    targets.sort_unstable();
+                }
+            }
+            NetworkGenerator::StochasticBlock { sizes, probabilities } => {
+                if sizes.iter().sum::<usize>() != n {
+                    return Err(format!("block sizes must sum to n ({})", n));
+                }
+                if probabilities.len() != sizes.len() || probabilities.iter().any(|row| row.len() != sizes.len()) {
+                    return Err("probability matrix must be square with one row per block".to_string());
+                }
+                for p in probabilities.iter().flatten() {
+                    check_probability(*p)?;
+                }
+                let blocks: Vec<usize> = sizes
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(block, size)| std::iter::repeat_n(block, *size))
+                    .collect();
+                for i in 0..n {
+                    for j in (i + 1)..n {
+                        if rng.gen_bool(probabilities[blocks[i]][blocks[j]]) {
+                            edges.insert(i, j);
+                        }
+                    }
+                }
+            }
+            NetworkGenerator::Configuration { degrees } => {
+                if degrees.len() != n {
+                    return Err(format!("degree sequence must have n ({}) entries", n));
+                }
+                if degrees.iter().sum::<usize>() % 2 != 0 {
+                    return Err("sum of degrees must be even".to_string());
+                }
+                let mut stubs: Vec<usize> = degrees
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, d)| std::iter::repeat_n(i, *d))
+                    .collect();
+                stubs.shuffle(rng);
+                // 自己ループと多重辺は取り除く
+                for pair in stubs.chunks(2) {
+                    if pair[0] != pair[1] {
+                        edges.insert(pair[0], pair[1]);
+                    }
+                }
+            }
+        }
+        Ok(edges.into_vec())
+    }
+}
+
+fn check_probability(p: f64) -> Result<(), String> {
+    if (0.0..=1.0).contains(&p) {
+        Ok(())
+    } else {
+        Err(format!("probability must be within [0, 1], got {}", p))
+    }
+}
+
+// 挿入順を保ったまま重複を除いた無向辺の集合
+#[derive(Default)]
+struct EdgeSet {
+    order: Vec<Option<(usize, usize)>>,
+    positions: HashMap<(usize, usize), usize>,
+}
+
+impl EdgeSet {
+    fn key(a: usize, b: usize) -> (usize, usize) {
+        (a.min(b), a.max(b))
+    }
+
+    fn insert(&mut self, a: usize, b: usize) {
+        let key = Self::key(a, b);
+        if !self.positions.contains_key(&key) {
+            self.positions.insert(key, self.order.len());
+            self.order.push(Some((a, b)));
+        }
+    }
+
+    fn contains(&self, a: usize, b: usize) -> bool {
+        self.positions.contains_key(&Self::key(a, b))
+    }
+
+    fn remove(&mut self, a: usize, b: usize) {
+        if let Some(position) = self.positions.remove(&Self::key(a, b)) {
+            self.order[position] = None;
+        }
+    }
+
+    fn into_vec(self) -> Vec<(usize, usize)> {
+        self.order.into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::model::Model;
+    use crate::types::{EntityType, RelationType};
+
+    fn degrees(n: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+        let mut degrees = vec![0; n];
+        for (i, j) in edges {
+            degrees[*i] += 1;
+            degrees[*j] += 1;
+        }
+        degrees
+    }
+
+    fn edges(generator: &NetworkGenerator, n: usize, seed: u64) -> Result<Vec<(usize, usize)>, String> {
+        generator.edges(n, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn erdos_renyi_extremes() {
+        assert!(edges(&NetworkGenerator::ErdosRenyi { p: 0.0 }, 10, 1).unwrap().is_empty());
+        assert_eq!(edges(&NetworkGenerator::ErdosRenyi { p: 1.0 }, 10, 1).unwrap().len(), 45);
+        assert!(edges(&NetworkGenerator::ErdosRenyi { p: 1.5 }, 10, 1).is_err());
+    }
+
+    #[test]
+    fn same_seed_gives_same_edges() {
+        let generator = NetworkGenerator::ErdosRenyi { p: 0.3 };
+        assert_eq!(edges(&generator, 30, 42).unwrap(), edges(&generator, 30, 42).unwrap());
+        assert_ne!(edges(&generator, 30, 42).unwrap(), edges(&generator, 30, 43).unwrap());
+    }
+
+    #[test]
+    fn watts_strogatz_ring_and_rewiring() {
+        let ring = edges(&NetworkGenerator::WattsStrogatz { k: 4, beta: 0.0 }, 10, 1).unwrap();
+        assert_eq!(ring.len(), 20);
+        assert!(degrees(10, &ring).iter().all(|d| *d == 4));
+        let rewired = edges(&NetworkGenerator::WattsStrogatz { k: 4, beta: 0.5 }, 10, 1).unwrap();
+        assert_eq!(rewired.len(), 20);
+        assert!(edges(&NetworkGenerator::WattsStrogatz { k: 3, beta: 0.1 }, 10, 1).is_err());
+    }
+
+    #[test]
+    fn barabasi_albert_edge_count() {
+        let generated = edges(&NetworkGenerator::BarabasiAlbert { m: 2 }, 20, 5).unwrap();
+        assert_eq!(generated.len(), 2 * 18);
+        assert!(degrees(20, &generated)[2..].iter().all(|d| *d >= 2));
+        assert!(edges(&NetworkGenerator::BarabasiAlbert { m: 0 }, 20, 5).is_err());
+    }
+
+    #[test]
+    fn stochastic_block_without_cross_edges() {
+        let generator = NetworkGenerator::StochasticBlock {
+            sizes: vec![3, 3],
+            probabilities: vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+        };
+        let mut generated = edges(&generator, 6, 1).unwrap();
+        generated.sort();
+        assert_eq!(generated, vec![(0, 1), (0, 2), (1, 2), (3, 4), (3, 5), (4, 5)]);
+        assert!(edges(&generator, 7, 1).is_err());
+    }
+
+    #[test]
+    fn configuration_model_bounds_degrees() {
+        let generated = edges(&NetworkGenerator::Configuration { degrees: vec![2, 2, 2, 2] }, 4, 3).unwrap();
+        assert!(degrees(4, &generated).iter().all(|d| *d <= 2));
+        assert!(edges(&NetworkGenerator::Configuration { degrees: vec![1, 2] }, 2, 3).is_err());
+    }
+
+    #[test]
+    fn model_reports_rejected_edges() {
+        let model = Model::new();
+        model.define_relationship("boss".to_string(), EntityType::Agent, EntityType::Agent, RelationType::ManyToOne).unwrap();
+        let ids: Vec<Uuid> = (0..4)
+            .map(|i| model.create_entity(format!("a{}", i), EntityType::Agent).id)
+            .collect();
+        let report = model.generate_network(&ids, "boss", &NetworkGenerator::ErdosRenyi { p: 1.0 }, 1).unwrap();
+        assert_eq!(report.relations.len() + report.rejected.len(), 6);
+        assert_eq!(report.relations.len(), 3);
+        assert!(report.rejected.iter().all(|r| matches!(r.error, ModelError::InvalidRelationType { .. })));
+    }
+
+    #[test]
+    fn model_reports_type_mismatches() {
+        let model = Model::new();
+        model.define_relationship("visits".to_string(), EntityType::Agent, EntityType::Spot, RelationType::ManyToMany).unwrap();
+        let ids = vec![
+            model.create_entity("a".to_string(), EntityType::Agent).id,
+            model.create_entity("b".to_string(), EntityType::Agent).id,
+            model.create_entity("s".to_string(), EntityType::Spot).id,
+        ];
+        let report = model.generate_network(&ids, "visits", &NetworkGenerator::ErdosRenyi { p: 1.0 }, 1).unwrap();
+        assert_eq!(report.relations.len(), 2);
+        assert_eq!(report.rejected.len(), 1);
+        assert!(matches!(report.rejected[0].error, ModelError::InvalidRelationEntityTypes));
+        assert!(model.generate_network(&ids, "missing", &NetworkGenerator::ErdosRenyi { p: 1.0 }, 1).is_err());
+    }
+}
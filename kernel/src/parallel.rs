@@ -0,0 +1,788 @@
+use std::cell::{OnceCell, Ref, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::thread;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use crate::context::{ReadOnlyEntity, ReadOnlyFunction, ReadOnlyModel, ReadOnlyRelation};
+use crate::lattice::Lattice;
+use crate::model::ModelError;
+use crate::multiplex::ContextDefinition;
+use crate::result::{ExecutionResult, RelationCreationInfo};
+use crate::schedule::Timetable;
+use crate::schema::StateSchema;
+use crate::space::Space;
+use crate::symbol::{AsSymbol, Symbol};
+use crate::types::{EntityType, RelationType, TypeRegistry};
+use crate::variable::{Value, Variable};
+
+pub type ParallelAction = Arc<dyn Fn(&ParallelContext) -> Vec<ExecutionResult> + Send + Sync + 'static>;
+
+pub struct SharedEntity {
+    pub id: Uuid,
+    pub name: String,
+    pub entity_type: EntityType,
+    state: RwLock<Variable>,
+    processes: RwLock<Vec<(String, ParallelAction)>>,
+}
+
+impl SharedEntity {
+    fn new(name: String, entity_type: EntityType) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            entity_type,
+            state: RwLock::new(Variable::new()),
+            processes: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn state(&self) -> RwLockReadGuard<'_, Variable> {
+        self.state.read().unwrap()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SharedRelation {
+    id: Uuid,
+    name: Symbol,
+    relation_type: RelationType,
+    source: Uuid,
+    target: Uuid,
+    meta: HashMap<Symbol, Value>,
+}
+
+#[derive(Default)]
+struct Store {
+    // 作成順のスロット。削除されたエンティティは None になり、評価と適用の順序を固定する
+    entities: Vec<Option<Arc<SharedEntity>>>,
+    by_id: HashMap<Uuid, usize>,
+    relations: HashMap<Uuid, SharedRelation>,
+    adjacency: HashMap<Uuid, Vec<Uuid>>,
+    behaviors: Vec<(EntityType, String, ParallelAction)>,
+    type_registry: TypeRegistry,
+}
+
+impl Store {
+    fn get(&self, id: &Uuid) -> Option<Arc<SharedEntity>> {
+        self.by_id.get(id).and_then(|slot| self.entities[*slot].clone())
+    }
+
+    fn alive(&self) -> impl Iterator<Item = &Arc<SharedEntity>> + '_ {
+        self.entities.iter().flatten()
+    }
+
+    fn relations_of(&self, id: &Uuid, name: &str) -> Vec<&SharedRelation> {
+        let Some(name) = name.as_symbol() else {
+            return Vec::new();
+        };
+        self.adjacency.get(id)
+            .into_iter()
+            .flatten()
+            .filter_map(|relation_id| self.relations.get(relation_id))
+            .filter(|relation| relation.name == name)
+            .collect()
+    }
+
+    fn remove_relation(&mut self, id: &Uuid) -> Option<SharedRelation> {
+        let relation = self.relations.remove(id)?;
+        for endpoint in [relation.source, relation.target] {
+            if let Some(ids) = self.adjacency.get_mut(&endpoint) {
+                ids.retain(|other| other != id);
+            }
+        }
+        Some(relation)
+    }
+
+    // 祖先の型の振る舞いを近い順に集める。同名のものは派生型が優先される
+    fn behaviors_for(&self, entity_type: &EntityType) -> Vec<(String, ParallelAction)> {
+        let mut resolved: Vec<(String, ParallelAction)> = Vec::new();
+        for t in self.type_registry.ancestors(entity_type) {
+            for (behavior_type, name, action) in &self.behaviors {
+                if *behavior_type == t && !resolved.iter().any(|(n, _)| n == name) {
+                    resolved.push((name.clone(), Arc::clone(action)));
+                }
+            }
+        }
+        resolved
+    }
+}
+
+// ステップ開始時のエンティティと関係。評価中のスレッドは store のロックを取らずにこれを読む
+struct Snapshot {
+    step: u64,
+    entities: HashMap<Uuid, Arc<SharedEntity>>,
+    related: HashMap<(Uuid, Symbol), Vec<Uuid>>,
+}
+
+impl Snapshot {
+    fn new(store: &Store, step: u64) -> Self {
+        let mut related: HashMap<(Uuid, Symbol), Vec<Uuid>> = HashMap::new();
+        for (id, relation_ids) in &store.adjacency {
+            for relation in relation_ids.iter().filter_map(|relation_id| store.relations.get(relation_id)) {
+                let other = if relation.source == *id { relation.target } else { relation.source };
+                related.entry((*id, relation.name)).or_default().push(other);
+            }
+        }
+        Self {
+            step,
+            entities: store.alive().map(|entity| (entity.id, Arc::clone(entity))).collect(),
+            related,
+        }
+    }
+}
+
+// Model を Send + Sync にしたものではなく、プロセスの並列評価だけを行う別の小さなエンジン。
+// Function・Process・Behavior・System・スキーマ・状態インデックス・格子・空間・コンテキストは持たない。
+// 振る舞いは ParallelContext を受け取る ParallelAction として add_process か define_behavior で登録し、
+// 結果は状態と関係に関するものだけを扱う。
+// プロセスはステップ開始時のスナップショットを読みながら複数スレッドで評価し、結果はエンティティの作成順に
+// 並べてから適用する。そのため結果はスレッド数によらず逐次実行と同じになる
+pub struct ParallelModel {
+    store: Arc<RwLock<Store>>,
+    step: AtomicU64,
+    threads: usize,
+}
+
+impl Default for ParallelModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParallelModel {
+    pub fn new() -> Self {
+        Self::with_threads(thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+    }
+
+    pub fn with_threads(threads: usize) -> Self {
+        Self {
+            store: Arc::new(RwLock::new(Store::default())),
+            step: AtomicU64::new(0),
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn get_current_step(&self) -> u64 {
+        self.step.load(Ordering::SeqCst)
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.read().unwrap().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn register_entity_type(&self, entity_type: EntityType, parent: EntityType) -> Result<(), ModelError> {
+        self.store.write().unwrap().type_registry.register(entity_type, parent).map_err(ModelError::InvalidTypeHierarchy)
+    }
+
+    pub fn create_entity(&self, name: String, entity_type: EntityType) -> Arc<SharedEntity> {
+        self.create_entity_with_state(name, entity_type, HashMap::new())
+    }
+
+    pub fn create_entity_with_state(&self, name: String, entity_type: EntityType, initial_state: HashMap<String, Value>) -> Arc<SharedEntity> {
+        let entity = SharedEntity::new(name, entity_type);
+        {
+            let mut state = entity.state.write().unwrap();
+            for (key, value) in initial_state {
+                state.set(key, value);
+            }
+        }
+        let entity = Arc::new(entity);
+        let mut store = self.store.write().unwrap();
+        let slot = store.entities.len();
+        store.by_id.insert(entity.id, slot);
+        store.entities.push(Some(Arc::clone(&entity)));
+        entity
+    }
+
+    pub fn get_entity(&self, id: &Uuid) -> Option<Arc<SharedEntity>> {
+        self.store.read().unwrap().get(id)
+    }
+
+    pub fn get_all_entities(&self) -> Vec<Arc<SharedEntity>> {
+        self.store.read().unwrap().alive().cloned().collect()
+    }
+
+    pub fn delete_entity(&self, id: &Uuid) -> Result<(), ModelError> {
+        let mut store = self.store.write().unwrap();
+        let slot = store.by_id.remove(id).ok_or(ModelError::EntityNotFound(*id))?;
+        store.entities[slot] = None;
+        for relation_id in store.adjacency.remove(id).unwrap_or_default() {
+            store.remove_relation(&relation_id);
+        }
+        Ok(())
+    }
+
+    pub fn set_state(&self, id: &Uuid, key: impl Into<Symbol>, value: Value) -> Result<(), ModelError> {
+        let entity = self.get_entity(id).ok_or(ModelError::EntityNotFound(*id))?;
+        entity.state.write().unwrap().set(key, value);
+        Ok(())
+    }
+
+    pub fn add_relation(&self, name: impl Into<Symbol>, relation_type: RelationType, source: &Uuid, target: &Uuid) -> Result<Uuid, ModelError> {
+        let mut store = self.store.write().unwrap();
+        for id in [source, target] {
+            if !store.by_id.contains_key(id) {
+                return Err(ModelError::EntityNotFound(*id));
+            }
+        }
+        let relation = SharedRelation {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            relation_type,
+            source: *source,
+            target: *target,
+            meta: HashMap::new(),
+        };
+        let id = relation.id;
+        store.adjacency.entry(*source).or_default().push(id);
+        if source != target {
+            store.adjacency.entry(*target).or_default().push(id);
+        }
+        store.relations.insert(id, relation);
+        Ok(id)
+    }
+
+    pub fn remove_relation(&self, id: &Uuid) -> Result<(), ModelError> {
+        self.store.write().unwrap().remove_relation(id).map(|_| ()).ok_or(ModelError::RelationNotFound(*id))
+    }
+
+    // 関係 name で id とつながるエンティティ (向きは問わない)
+    pub fn related(&self, id: &Uuid, name: &str) -> Vec<Uuid> {
+        self.store.read().unwrap()
+            .relations_of(id, name)
+            .into_iter()
+            .map(|relation| if relation.source == *id { relation.target } else { relation.source })
+            .collect()
+    }
+
+    // 同名のプロセスは置き換える。型の振る舞いと同名なら、このエンティティではそちらより優先される
+    pub fn add_process<F>(&self, entity_id: &Uuid, name: &str, action: F) -> Result<(), ModelError>
+    where
+        F: Fn(&ParallelContext) -> Vec<ExecutionResult> + Send + Sync + 'static,
+    {
+        let entity = self.get_entity(entity_id).ok_or(ModelError::EntityNotFound(*entity_id))?;
+        let mut processes = entity.processes.write().unwrap();
+        processes.retain(|(n, _)| n != name);
+        processes.push((name.to_string(), Arc::new(action)));
+        Ok(())
+    }
+
+    pub fn remove_process(&self, entity_id: &Uuid, name: &str) -> Result<(), ModelError> {
+        let entity = self.get_entity(entity_id).ok_or(ModelError::EntityNotFound(*entity_id))?;
+        entity.processes.write().unwrap().retain(|(n, _)| n != name);
+        Ok(())
+    }
+
+    // 型 (派生型を含む) のすべてのエンティティで評価されるプロセス
+    pub fn define_behavior<F>(&self, entity_type: EntityType, name: &str, action: F)
+    where
+        F: Fn(&ParallelContext) -> Vec<ExecutionResult> + Send + Sync + 'static,
+    {
+        let mut store = self.store.write().unwrap();
+        store.behaviors.retain(|(t, n, _)| !(*t == entity_type && n == name));
+        store.behaviors.push((entity_type, name.to_string(), Arc::new(action)));
+    }
+
+    // 扱えない結果が含まれていれば何も適用せず、ステップも進めない
+    pub fn simulate(&self) -> Result<(), ModelError> {
+        let (snapshot, entities, behaviors) = {
+            let store = self.store.read().unwrap();
+            let entities: Vec<Arc<SharedEntity>> = store.alive().cloned().collect();
+            let mut behaviors: HashMap<EntityType, Vec<(String, ParallelAction)>> = HashMap::new();
+            for entity in &entities {
+                if !behaviors.contains_key(&entity.entity_type) {
+                    behaviors.insert(entity.entity_type.clone(), store.behaviors_for(&entity.entity_type));
+                }
+            }
+            (Snapshot::new(&store, self.get_current_step()), entities, behaviors)
+        };
+
+        let chunk_size = entities.len().div_ceil(self.threads).max(1);
+        let chunks: Vec<Vec<ExecutionResult>> = thread::scope(|scope| {
+            let handles: Vec<_> = entities
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let (snapshot, behaviors) = (&snapshot, &behaviors);
+                    scope.spawn(move || {
+                        chunk.iter().flat_map(|entity| evaluate(entity, snapshot, &behaviors[&entity.entity_type])).collect()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        // チャンクは作成順に並んでいるので、連結すれば逐次実行と同じ順序になる
+        self.apply_results(chunks.into_iter().flatten().collect())?;
+        self.step.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // 状態と関係に関する結果のみ扱う。存在しない対象への結果は無視する
+    fn apply_results(&self, results: Vec<ExecutionResult>) -> Result<(), ModelError> {
+        if let Some(result) = results.iter().find(|result| !result.is_state_or_relation()) {
+            return Err(ModelError::UnsupportedResult(result.kind()));
+        }
+        for result in results {
+            match result {
+                ExecutionResult::UpdateEntityState(id, key, value) => {
+                    if let Some(entity) = self.get_entity(&id) {
+                        entity.state.write().unwrap().set(key, value);
+                    }
+                }
+                ExecutionResult::DeleteEntityState(id, key) => {
+                    if let Some(entity) = self.get_entity(&id) {
+                        entity.state.write().unwrap().remove(&key);
+                    }
+                }
+                ExecutionResult::CreateEntity(info) => {
+                    let entity = self.create_entity_with_state(info.name, info.entity_type, info.initial_state);
+                    for relation_info in info.relations {
+                        self.create_relation_internal(relation_info, Some(entity.id));
+                    }
+                }
+                ExecutionResult::DeleteEntity(id) => {
+                    let _ = self.delete_entity(&id);
+                }
+                ExecutionResult::CreateRelation(info) => {
+                    self.create_relation_internal(info, None);
+                }
+                ExecutionResult::DeleteRelation(id) => {
+                    let _ = self.remove_relation(&id);
+                }
+                ExecutionResult::AddRelationMetadata(id, key, value) => {
+                    if let Some(relation) = self.store.write().unwrap().relations.get_mut(&id) {
+                        relation.meta.insert(key, value);
+                    }
+                }
+                ExecutionResult::RemoveRelationMetadata(id, key) => {
+                    if let Some(relation) = self.store.write().unwrap().relations.get_mut(&id) {
+                        relation.meta.remove(&key);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // 端点の解決は Model と同じ規則に従う
+    fn create_relation_internal(&self, info: RelationCreationInfo, source_entity_id: Option<Uuid>) {
+        let Some(source) = source_entity_id.or(info.target_entity_id) else {
+            return;
+        };
+        let target = info.target_entity_id.or_else(|| {
+            let name = info.target_entity_name.as_ref()?;
+            self.store.read().unwrap().alive().find(|e| e.name == *name).map(|e| e.id)
+        });
+        let Some(id) = target.and_then(|target| self.add_relation(&info.name, info.relation_type, &source, &target).ok()) else {
+            return;
+        };
+        if let Some(metadata) = info.metadata {
+            if let Some(relation) = self.store.write().unwrap().relations.get_mut(&id) {
+                relation.meta.extend(metadata.into_iter().map(|(key, value)| (Symbol::new(&key), value)));
+            }
+        }
+    }
+}
+
+fn evaluate(entity: &SharedEntity, snapshot: &Snapshot, behaviors: &[(String, ParallelAction)]) -> Vec<ExecutionResult> {
+    let context = ParallelContext { entity, snapshot };
+    let processes = entity.processes.read().unwrap();
+    let mut results = Vec::new();
+    for (name, action) in behaviors {
+        if !processes.iter().any(|(n, _)| n == name) {
+            results.extend(action(&context));
+        }
+    }
+    for (_, action) in processes.iter() {
+        results.extend(action(&context));
+    }
+    results
+}
+
+// ParallelModel のプロセスに渡す読み取り専用のコンテキスト。
+// 他のエンティティと関係はステップ開始時のスナップショットから引く
+pub struct ParallelContext<'a> {
+    pub entity: &'a SharedEntity,
+    snapshot: &'a Snapshot,
+}
+
+impl ParallelContext<'_> {
+    pub fn id(&self) -> Uuid {
+        self.entity.id
+    }
+
+    pub fn state(&self) -> RwLockReadGuard<'_, Variable> {
+        self.entity.state()
+    }
+
+    pub fn step(&self) -> u64 {
+        self.snapshot.step
+    }
+
+    pub fn get_entity(&self, id: &Uuid) -> Option<Arc<SharedEntity>> {
+        self.snapshot.entities.get(id).cloned()
+    }
+
+    pub fn related(&self, name: &str) -> Vec<Uuid> {
+        let Some(name) = name.as_symbol() else {
+            return Vec::new();
+        };
+        self.snapshot.related.get(&(self.entity.id, name)).cloned().unwrap_or_default()
+    }
+}
+
+// 独立した試行をスレッドに分けて実行し、試行番号の順に結果を返す。
+// Model は Send ではないので、各試行のモデルは run の中で作る
+pub fn replicate<R, F>(count: usize, threads: usize, run: F) -> Vec<R>
+where
+    R: Send,
+    F: Fn(usize) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(count));
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, count.max(1)) {
+            scope.spawn(|| loop {
+                let replication = next.fetch_add(1, Ordering::SeqCst);
+                if replication >= count {
+                    break;
+                }
+                let result = run(replication);
+                results.lock().unwrap().push((replication, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(replication, _)| *replication);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// ReadOnlyModel 経由で参照するときのビュー。Rc はそれを作ったスレッド内でのみ使われる
+struct EntityView {
+    store: Arc<RwLock<Store>>,
+    entity: Arc<SharedEntity>,
+    state: OnceCell<RefCell<Variable>>,
+}
+
+impl EntityView {
+    fn new(store: &Arc<RwLock<Store>>, entity: Arc<SharedEntity>) -> Rc<Self> {
+        Rc::new(Self { store: Arc::clone(store), entity, state: OnceCell::new() })
+    }
+}
+
+impl ReadOnlyEntity for EntityView {
+    fn get_id(&self) -> Uuid {
+        self.entity.id
+    }
+
+    fn get_name(&self) -> &str {
+        &self.entity.name
+    }
+
+    fn get_entity_type(&self) -> &EntityType {
+        &self.entity.entity_type
+    }
+
+    fn get_state(&self) -> Ref<'_, Variable> {
+        self.state.get_or_init(|| RefCell::new(self.entity.state().clone())).borrow()
+    }
+
+    fn get_function(&self, _name: &str) -> Option<Rc<dyn ReadOnlyFunction>> {
+        None
+    }
+
+    fn get_relations(&self, name: &str) -> Vec<Rc<dyn ReadOnlyRelation>> {
+        self.store.read().unwrap()
+            .relations_of(&self.entity.id, name)
+            .into_iter()
+            .map(|relation| RelationView::new(&self.store, relation.clone()) as Rc<dyn ReadOnlyRelation>)
+            .collect()
+    }
+}
+
+struct RelationView {
+    store: Arc<RwLock<Store>>,
+    relation: SharedRelation,
+}
+
+impl RelationView {
+    fn new(store: &Arc<RwLock<Store>>, relation: SharedRelation) -> Rc<Self> {
+        Rc::new(Self { store: Arc::clone(store), relation })
+    }
+
+    fn endpoint(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyEntity>> {
+        let entity = self.store.read().unwrap().get(id)?;
+        Some(EntityView::new(&self.store, entity) as Rc<dyn ReadOnlyEntity>)
+    }
+}
+
+impl ReadOnlyRelation for RelationView {
+    fn get_id(&self) -> Uuid {
+        self.relation.id
+    }
+
+    fn get_name(&self) -> &str {
+        self.relation.name.as_str()
+    }
+
+    fn get_relation_type(&self) -> &RelationType {
+        &self.relation.relation_type
+    }
+
+    fn get_entity1(&self) -> Option<Rc<dyn ReadOnlyEntity>> {
+        self.endpoint(&self.relation.source)
+    }
+
+    fn get_entity2(&self) -> Option<Rc<dyn ReadOnlyEntity>> {
+        self.endpoint(&self.relation.target)
+    }
+
+    fn get_meta_value(&self, key: &str) -> Option<Value> {
+        self.relation.meta.get(&key.as_symbol()?).cloned()
+    }
+
+    fn iter_meta(&self) -> HashMap<String, Value> {
+        self.relation.meta.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+    }
+}
+
+impl ParallelModel {
+    fn views<'a>(&self, entities: impl Iterator<Item = &'a Arc<SharedEntity>>) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        entities.map(|entity| EntityView::new(&self.store, Arc::clone(entity)) as Rc<dyn ReadOnlyEntity>).collect()
+    }
+}
+
+impl ReadOnlyModel for ParallelModel {
+    fn get_entity(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyEntity>> {
+        let entity = self.get_entity(id)?;
+        Some(EntityView::new(&self.store, entity) as Rc<dyn ReadOnlyEntity>)
+    }
+
+    fn get_all_entities(&self) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        self.views(self.get_all_entities().iter())
+    }
+
+    fn get_entities_by_type(&self, entity_type: &EntityType) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let store = self.store.read().unwrap();
+        let subtypes = store.type_registry.subtypes(entity_type);
+        self.views(store.alive().filter(|e| subtypes.contains(&e.entity_type)))
+    }
+
+    fn get_entities_by_name(&self, name: &str) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let store = self.store.read().unwrap();
+        self.views(store.alive().filter(|e| e.name == name))
+    }
+
+    fn get_entities_by_state(&self, key: &str, value: &Value) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let store = self.store.read().unwrap();
+        self.views(store.alive().filter(|e| e.state().get(key) == Some(value)))
+    }
+
+    fn has_state_index(&self, _key: &str) -> bool {
+        false
+    }
+
+    fn get_lattice(&self, _relation_name: &str) -> Option<Rc<Lattice>> {
+        None
+    }
+
+    fn get_space(&self) -> Option<Ref<'_, Space>> {
+        None
+    }
+
+    fn get_context(&self, _name: &str) -> Option<ContextDefinition> {
+        None
+    }
+
+    fn get_active_context(&self, _entity_id: &Uuid) -> Option<String> {
+        None
+    }
+
+    fn get_current_time(&self) -> Option<NaiveDateTime> {
+        None
+    }
+
+    fn get_timetable(&self, _entity_id: &Uuid) -> Option<Timetable> {
+        None
+    }
+
+    fn get_schema(&self, _entity_type: &EntityType) -> Option<Rc<StateSchema>> {
+        None
+    }
+
+    fn is_subtype(&self, entity_type: &EntityType, ancestor: &EntityType) -> bool {
+        self.store.read().unwrap().type_registry.is_subtype(entity_type, ancestor)
+    }
+
+    fn get_subtypes(&self, entity_type: &EntityType) -> Vec<EntityType> {
+        self.store.read().unwrap().type_registry.subtypes(entity_type)
+    }
+
+    fn get_base_type(&self, entity_type: &EntityType) -> EntityType {
+        self.store.read().unwrap().type_registry.base_type(entity_type)
+    }
+
+    fn get_relation(&self, id: &Uuid) -> Option<Rc<dyn ReadOnlyRelation>> {
+        let relation = self.store.read().unwrap().relations.get(id)?.clone();
+        Some(RelationView::new(&self.store, relation) as Rc<dyn ReadOnlyRelation>)
+    }
+
+    fn get_all_relations(&self) -> Vec<Rc<dyn ReadOnlyRelation>> {
+        let store = self.store.read().unwrap();
+        store.relations
+            .values()
+            .map(|relation| RelationView::new(&self.store, relation.clone()) as Rc<dyn ReadOnlyRelation>)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::EntityCreationInfo;
+
+    // 各エージェントは隣の値を読んでハブに書き込み、ときどき子を作る。
+    // ハブへの書き込みは衝突するので、適用順がずれると最終状態が変わる
+    fn run_with(threads: usize) -> Vec<(String, Vec<(String, Value)>)> {
+        let model = ParallelModel::with_threads(threads);
+        let hub = model.create_entity("hub".to_string(), EntityType::Custom("Hub".to_string())).id;
+        let agents: Vec<Uuid> = (0..40)
+            .map(|i| {
+                let state = HashMap::from([("x".to_string(), Value::Integer(i))]);
+                model.create_entity_with_state(format!("a{}", i), EntityType::Agent, state).id
+            })
+            .collect();
+        for (a, b) in agents.iter().zip(agents.iter().skip(1)) {
+            model.add_relation("next", RelationType::OneToOne, a, b).unwrap();
+        }
+        model.define_behavior(EntityType::Agent, "mix", move |context| {
+            let x = context.state().get_as::<i32>("x").unwrap_or(0);
+            let neighbours: i32 = context.related("next")
+                .iter()
+                .filter_map(|id| context.get_entity(id)?.state().get_as::<i32>("x").ok())
+                .sum();
+            let mut results = vec![
+                ExecutionResult::update_state(context.id(), "x", Value::Integer((x + neighbours) % 97)),
+                ExecutionResult::update_state(hub, "last", Value::String(context.entity.name.clone())),
+            ];
+            if x % 7 == 0 {
+                results.push(ExecutionResult::CreateEntity(EntityCreationInfo {
+                    name: format!("{}-{}", context.entity.name, context.step()),
+                    entity_type: EntityType::Custom("Child".to_string()),
+                    initial_state: HashMap::from([("x".to_string(), Value::Integer(x))]),
+                    functions: Vec::new(),
+                    relations: Vec::new(),
+                }));
+            }
+            results
+        });
+        for _ in 0..5 {
+            model.simulate().unwrap();
+        }
+        model.get_all_entities()
+            .iter()
+            .map(|entity| {
+                let mut state: Vec<(String, Value)> = entity.state().iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+                state.sort_by(|a, b| a.0.cmp(&b.0));
+                (entity.name.clone(), state)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn results_do_not_depend_on_thread_count() {
+        let expected = run_with(1);
+        assert!(expected.len() > 41);
+        for threads in [2, 3, 8] {
+            assert_eq!(run_with(threads), expected, "threads = {}", threads);
+        }
+    }
+
+    #[test]
+    fn entity_processes_override_type_behaviors() {
+        let model = ParallelModel::with_threads(2);
+        let a = model.create_entity("a".to_string(), EntityType::Agent).id;
+        let b = model.create_entity("b".to_string(), EntityType::Agent).id;
+        model.define_behavior(EntityType::Agent, "tick", |context| vec![ExecutionResult::update_state(context.id(), "by", Value::String("type".to_string()))]);
+        model.add_process(&b, "tick", |context| vec![ExecutionResult::update_state(context.id(), "by", Value::String("entity".to_string()))]).unwrap();
+        model.simulate().unwrap();
+        assert_eq!(model.get_entity(&a).unwrap().state().get("by"), Some(&Value::String("type".to_string())));
+        assert_eq!(model.get_entity(&b).unwrap().state().get("by"), Some(&Value::String("entity".to_string())));
+
+        model.remove_process(&b, "tick").unwrap();
+        model.simulate().unwrap();
+        assert_eq!(model.get_entity(&b).unwrap().state().get("by"), Some(&Value::String("type".to_string())));
+    }
+
+    #[test]
+    fn unsupported_results_stop_the_step() {
+        let model = ParallelModel::with_threads(2);
+        let a = model.create_entity("a".to_string(), EntityType::Agent).id;
+        model.add_process(&a, "bad", |context| vec![
+            ExecutionResult::update_state(context.id(), "x", Value::Integer(1)),
+            ExecutionResult::activate_function(context.id(), "f"),
+        ]).unwrap();
+        assert!(matches!(model.simulate(), Err(ModelError::UnsupportedResult("ActivateFunction"))));
+        assert_eq!(model.get_current_step(), 0);
+        assert_eq!(model.get_entity(&a).unwrap().state().get("x"), None);
+    }
+
+    #[test]
+    fn deleting_an_entity_removes_its_relations() {
+        let model = ParallelModel::with_threads(1);
+        let a = model.create_entity("a".to_string(), EntityType::Agent).id;
+        let b = model.create_entity("b".to_string(), EntityType::Agent).id;
+        let relation = model.add_relation("knows", RelationType::OneToOne, &a, &b).unwrap();
+        assert_eq!(model.related(&b, "knows"), vec![a]);
+
+        model.delete_entity(&a).unwrap();
+        assert!(model.related(&b, "knows").is_empty());
+        assert!(model.remove_relation(&relation).is_err());
+        let view: &dyn ReadOnlyModel = &model;
+        assert_eq!(view.get_all_entities().len(), 1);
+    }
+
+    #[test]
+    fn processes_do_not_take_the_store_lock() {
+        let model = ParallelModel::with_threads(4);
+        let ids: Vec<Uuid> = (0..8).map(|i| model.create_entity(format!("a{}", i), EntityType::Agent).id).collect();
+        model.add_relation("knows", RelationType::ManyToMany, &ids[0], &ids[1]).unwrap();
+        let store = Arc::clone(&model.store);
+        let locked = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&locked);
+        model.define_behavior(EntityType::Agent, "look", move |context| {
+            if store.try_write().is_err() {
+                count.fetch_add(1, Ordering::SeqCst);
+            }
+            let known = context.related("knows")
+                .iter()
+                .filter_map(|id| context.get_entity(id))
+                .count();
+            vec![ExecutionResult::update_state(context.id(), "known", Value::Integer(known as i32))]
+        });
+        model.simulate().unwrap();
+        assert_eq!(locked.load(Ordering::SeqCst), 0);
+        assert_eq!(model.get_entity(&ids[0]).unwrap().state().get("known"), Some(&Value::Integer(1)));
+        assert_eq!(model.get_entity(&ids[1]).unwrap().state().get("known"), Some(&Value::Integer(1)));
+        assert_eq!(model.get_entity(&ids[2]).unwrap().state().get("known"), Some(&Value::Integer(0)));
+    }
+
+    #[test]
+    fn replications_are_returned_in_order() {
+        let results = replicate(10, 4, |replication| replication * 2);
+        assert_eq!(results, (0..10).map(|r| r * 2).collect::<Vec<_>>());
+        assert!(replicate(0, 4, |replication| replication).is_empty());
+    }
+}
@@ -5,18 +5,30 @@ use crate::context::ExecutionContext;
 use crate::result::ExecutionResult;
 use std::cell::RefCell;
 
+pub type Action = Box<dyn Fn(&ExecutionContext) -> Vec<ExecutionResult> + 'static>;
+
+// ExecutionResult に載せる関数と条件。parallel フィーチャーでは結果をスレッド間で受け渡すため Send + Sync に限る
+#[cfg(not(feature = "parallel"))]
+pub type ResultAction = Action;
+#[cfg(not(feature = "parallel"))]
+pub type ResultCondition = Box<dyn Condition>;
+#[cfg(feature = "parallel")]
+pub type ResultAction = Box<dyn Fn(&ExecutionContext) -> Vec<ExecutionResult> + Send + Sync + 'static>;
+#[cfg(feature = "parallel")]
+pub type ResultCondition = Box<dyn Condition + Send + Sync>;
+
 pub struct Process {
     pub name: String,
     pub owner: Weak<Function>,
     condition: RefCell<Option<Box<dyn Condition>>>,
-    action: Box<dyn Fn(&ExecutionContext) -> Vec<ExecutionResult> + 'static>,
+    action: Action,
 }
 
 impl Process {
     pub fn new(
         name: String,
         owner: Weak<Function>,
-        action: Box<dyn Fn(&ExecutionContext) -> Vec<ExecutionResult> + 'static>,
+        action: Action,
     ) -> Self {
         Process {
             name,
@@ -44,7 +56,7 @@ impl Process {
     }
 
     fn check_condition(&self, context: &ExecutionContext) -> bool {
-        self.condition.borrow().as_ref().map_or(true, |c| c.is_met(context))
+        self.condition.borrow().as_ref().is_none_or(|c| c.is_met(context))
     }
 }
 
@@ -69,4 +81,65 @@ impl Condition for AlwaysTrueCondition {
     fn is_met(&self, _context: &ExecutionContext) -> bool {
         true
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use uuid::Uuid;
+    use crate::model::Model;
+    use crate::types::EntityType;
+
+    #[derive(Debug)]
+    struct Adult;
+
+    impl Condition for Adult {
+        fn is_met(&self, context: &ExecutionContext) -> bool {
+            context.owner_entity.get_state_value("age").is_some_and(|age| age >= crate::variable::Value::Integer(20))
+        }
+    }
+
+    #[test]
+    fn processes_run_only_when_active_and_the_condition_holds() {
+        let model = Model::new();
+        let entity = model.create_entity("a".to_string(), EntityType::Agent);
+        let function = Rc::new(Function::new("f", Rc::downgrade(&entity)));
+        let id = entity.id;
+        let process = Process::new(
+            "p".to_string(),
+            Rc::downgrade(&function),
+            Box::new(move |_: &ExecutionContext| vec![ExecutionResult::update_state(id, "seen", true)]),
+        );
+        let context = ExecutionContext { owner_function: &*function, owner_entity: &*entity, model: &model };
+        assert!(process.execute(&context).is_empty());
+
+        function.activate();
+        assert_eq!(process.execute(&context).len(), 1);
+
+        process.set_condition(Box::new(Adult));
+        assert!(process.execute(&context).is_empty());
+        entity.get_state().borrow_mut().set("age", crate::variable::Value::Integer(30));
+        assert_eq!(process.execute(&context).len(), 1);
+
+        process.remove_condition();
+        entity.get_state().borrow_mut().set("age", crate::variable::Value::Integer(3));
+        assert_eq!(process.execute(&context).len(), 1);
+        assert!(AlwaysTrueCondition {}.is_met(&context));
+    }
+
+    #[test]
+    fn processes_of_dropped_functions_do_nothing() {
+        let model = Model::new();
+        let entity = model.create_entity("a".to_string(), EntityType::Agent);
+        let owner = Rc::new(Function::new("f", Rc::downgrade(&entity)));
+        owner.activate();
+        let process = Process::new("p".to_string(), Rc::downgrade(&owner), Box::new(|_: &ExecutionContext| vec![ExecutionResult::RemovePosition(Uuid::nil())]));
+        let function = Function::new("g", Rc::downgrade(&entity));
+        let context = ExecutionContext { owner_function: &function, owner_entity: &*entity, model: &model };
+        assert_eq!(process.execute(&context).len(), 1);
+
+        drop(owner);
+        assert!(process.execute(&context).is_empty());
+    }
+}
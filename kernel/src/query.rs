@@ -0,0 +1,351 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::rc::Rc;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use crate::aggregate::{compare_values, numeric_value};
+use crate::context::{ReadOnlyEntity, ReadOnlyModel};
+use crate::types::EntityType;
+use crate::variable::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+type SharedPredicate = Rc<dyn Fn(&dyn ReadOnlyEntity) -> bool>;
+
+#[derive(Clone)]
+enum Criterion {
+    Name(String),
+    NamePattern(String),
+    HasState(String),
+    StateEquals(String, Value),
+    StateRange(String, (Bound<f64>, Bound<f64>)),
+    FunctionActive(String),
+    HasRelation(String),
+    Custom(SharedPredicate),
+}
+
+impl Criterion {
+    fn matches(&self, entity: &dyn ReadOnlyEntity) -> bool {
+        match self {
+            Criterion::Name(name) => entity.get_name() == name,
+            Criterion::NamePattern(pattern) => glob_match(pattern, entity.get_name()),
+            Criterion::HasState(key) => entity.get_state_value(key).is_some(),
+            Criterion::StateEquals(key, value) => entity.get_state_value(key).as_ref() == Some(value),
+            Criterion::StateRange(key, range) => entity.get_state_value(key)
+                .as_ref()
+                .and_then(numeric_value)
+                .is_some_and(|x| range.contains(&x)),
+            Criterion::FunctionActive(name) => entity.get_function(name).is_some_and(|f| f.is_active()),
+            Criterion::HasRelation(name) => !entity.get_relations(name).is_empty(),
+            Criterion::Custom(predicate) => predicate(entity),
+        }
+    }
+}
+
+impl fmt::Debug for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Criterion::Name(name) => f.debug_tuple("Name").field(name).finish(),
+            Criterion::NamePattern(pattern) => f.debug_tuple("NamePattern").field(pattern).finish(),
+            Criterion::HasState(key) => f.debug_tuple("HasState").field(key).finish(),
+            Criterion::StateEquals(key, value) => f.debug_tuple("StateEquals").field(key).field(value).finish(),
+            Criterion::StateRange(key, range) => f.debug_tuple("StateRange").field(key).field(range).finish(),
+            Criterion::FunctionActive(name) => f.debug_tuple("FunctionActive").field(name).finish(),
+            Criterion::HasRelation(name) => f.debug_tuple("HasRelation").field(name).finish(),
+            Criterion::Custom(_) => f.debug_tuple("Custom").field(&"<function>").finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EntityQuery {
+    entity_type: Option<EntityType>,
+    subtypes: Vec<EntityType>,
+    criteria: Vec<Criterion>,
+    sort: Option<(String, SortOrder)>,
+    sample: Option<(usize, u64)>,
+    limit: Option<usize>,
+}
+
+impl EntityQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn of_type(mut self, entity_type: EntityType) -> Self {
+        self.entity_type = Some(entity_type);
+        self
+    }
+
+    // of_type の型の派生型も一致させる。モデルの型レジストリから解決して渡す
+    pub(crate) fn with_subtypes(mut self, subtypes: Vec<EntityType>) -> Self {
+        self.subtypes = subtypes;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.criteria.push(Criterion::Name(name.to_string()));
+        self
+    }
+
+    // `*` と `?` をワイルドカードとして扱う
+    pub fn name_matches(mut self, pattern: &str) -> Self {
+        self.criteria.push(Criterion::NamePattern(pattern.to_string()));
+        self
+    }
+
+    pub fn has_state(mut self, key: &str) -> Self {
+        self.criteria.push(Criterion::HasState(key.to_string()));
+        self
+    }
+
+    pub fn state_equals(mut self, key: &str, value: Value) -> Self {
+        self.criteria.push(Criterion::StateEquals(key.to_string(), value));
+        self
+    }
+
+    pub fn state_in_range<R: RangeBounds<f64>>(mut self, key: &str, range: R) -> Self {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        self.criteria.push(Criterion::StateRange(key.to_string(), bounds));
+        self
+    }
+
+    pub fn function_active(mut self, name: &str) -> Self {
+        self.criteria.push(Criterion::FunctionActive(name.to_string()));
+        self
+    }
+
+    pub fn has_relation(mut self, name: &str) -> Self {
+        self.criteria.push(Criterion::HasRelation(name.to_string()));
+        self
+    }
+
+    pub fn filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&dyn ReadOnlyEntity) -> bool + 'static,
+    {
+        self.criteria.push(Criterion::Custom(Rc::new(predicate)));
+        self
+    }
+
+    pub fn sort_by_state(mut self, key: &str, order: SortOrder) -> Self {
+        self.sort = Some((key.to_string(), order));
+        self
+    }
+
+    pub fn sample(mut self, n: usize, seed: u64) -> Self {
+        self.sample = Some((n, seed));
+        self
+    }
+
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    pub fn entity_type(&self) -> Option<&EntityType> {
+        self.entity_type.as_ref()
+    }
+
+    pub fn name_lookup(&self) -> Option<&str> {
+        self.criteria.iter().find_map(|c| match c {
+            Criterion::Name(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn state_lookup(&self) -> Option<(&str, &Value)> {
+        self.criteria.iter().find_map(|c| match c {
+            Criterion::StateEquals(key, value) => Some((key.as_str(), value)),
+            _ => None,
+        })
+    }
+
+    // 一致するかどうかがエンティティの集合と型・名前だけで決まるか
+    pub(crate) fn is_structural(&self) -> bool {
+        self.sort.is_none() && self.criteria.iter().all(|c| matches!(c, Criterion::Name(_) | Criterion::NamePattern(_)))
+    }
+
+    // 型と名前の条件だけを残したクエリ
+    pub(crate) fn structural(&self) -> Self {
+        Self {
+            entity_type: self.entity_type.clone(),
+            subtypes: self.subtypes.clone(),
+            criteria: self.criteria.iter().filter(|c| matches!(c, Criterion::Name(_) | Criterion::NamePattern(_))).cloned().collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn matches(&self, entity: &dyn ReadOnlyEntity) -> bool {
+        self.entity_type.as_ref().is_none_or(|t| entity.get_entity_type() == t || self.subtypes.contains(entity.get_entity_type()))
+            && self.criteria.iter().all(|c| c.matches(entity))
+    }
+
+    // 候補に対してフィルタ、サンプリング、ソート、件数制限の順に適用する
+    pub fn apply<T, F>(&self, candidates: Vec<T>, view: F) -> Vec<T>
+    where
+        F: Fn(&T) -> &dyn ReadOnlyEntity,
+    {
+        let mut matched: Vec<T> = candidates.into_iter().filter(|c| self.matches(view(c))).collect();
+
+        if let Some((n, seed)) = self.sample {
+            matched.sort_by_key(|c| view(c).get_id());
+            let mut rng = StdRng::seed_from_u64(seed);
+            matched.shuffle(&mut rng);
+            matched.truncate(n);
+        }
+
+        if let Some((key, order)) = &self.sort {
+            matched.sort_by(|a, b| {
+                let a = view(a).get_state_value(key);
+                let b = view(b).get_state_value(key);
+                match (a, b) {
+                    (Some(a), Some(b)) => {
+                        let ordering = compare_values(&a, &b).unwrap_or(Ordering::Equal);
+                        match order {
+                            SortOrder::Ascending => ordering,
+                            SortOrder::Descending => ordering.reverse(),
+                        }
+                    }
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            });
+        }
+
+        if let Some(n) = self.limit {
+            matched.truncate(n);
+        }
+        matched
+    }
+}
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn query(&self, query: &EntityQuery) -> Vec<Rc<dyn ReadOnlyEntity>> {
+        let candidates = if let Some(name) = query.name_lookup() {
+            self.get_entities_by_name(name)
+        } else if let Some((key, value)) = query.state_lookup().filter(|(key, _)| self.has_state_index(key)) {
+            self.get_entities_by_state(key, value)
+        } else if let Some(entity_type) = query.entity_type() {
+            self.get_entities_by_type(entity_type)
+        } else {
+            self.get_all_entities()
+        };
+        let subtypes = query.entity_type().map(|t| self.get_subtypes(t)).unwrap_or_default();
+        query.clone().with_subtypes(subtypes).apply(candidates, |e| e.as_ref())
+    }
+}
+
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+    use crate::model::Model;
+
+    fn model() -> Model {
+        let model = Model::new();
+        for (name, age) in [("alice", 30), ("bob", 20), ("carol", 40), ("dave", 10)] {
+            let entity = model.create_entity(name.to_string(), EntityType::Agent);
+            entity.get_state().borrow_mut().set("age", Value::Integer(age));
+        }
+        model.create_entity("home".to_string(), EntityType::Spot);
+        model
+    }
+
+    fn names(entities: &[Rc<crate::entity::Entity>]) -> Vec<String> {
+        entities.iter().map(|e| e.name.clone()).collect()
+    }
+
+    #[test]
+    fn filters_by_type_state_and_range() {
+        let model = model();
+        let query = EntityQuery::new().of_type(EntityType::Agent).state_in_range("age", 15.0..35.0);
+        let mut found = names(&model.query(&query));
+        found.sort();
+        assert_eq!(found, vec!["alice", "bob"]);
+
+        assert_eq!(model.query(&EntityQuery::new().has_state("age")).len(), 4);
+        assert_eq!(model.query(&EntityQuery::new().of_type(EntityType::Spot)).len(), 1);
+        assert!(model.query(&EntityQuery::new().state_equals("age", Value::Integer(99))).is_empty());
+    }
+
+    #[test]
+    fn sorts_and_limits() {
+        let model = model();
+        let query = EntityQuery::new()
+            .of_type(EntityType::Agent)
+            .sort_by_state("age", SortOrder::Descending)
+            .limit(2);
+        assert_eq!(names(&model.query(&query)), vec!["carol", "alice"]);
+    }
+
+    #[test]
+    fn entities_without_the_sort_key_come_last() {
+        let model = model();
+        let query = EntityQuery::new().sort_by_state("age", SortOrder::Ascending);
+        assert_eq!(names(&model.query(&query)), vec!["dave", "bob", "alice", "carol", "home"]);
+    }
+
+    #[test]
+    fn sample_is_reproducible() {
+        let model = model();
+        let query = EntityQuery::new().of_type(EntityType::Agent).sample(2, 7);
+        let first = names(&model.query(&query));
+        assert_eq!(first.len(), 2);
+        assert_eq!(first, names(&model.query(&query)));
+    }
+
+    #[test]
+    fn name_pattern_and_active_function() {
+        let model = model();
+        let alice = model.get_entities_by_name("alice").pop().unwrap();
+        let function = Rc::new(Function::new("walk".to_string(), Rc::downgrade(&alice)));
+        function.activate();
+        alice.add_function(function);
+
+        assert_eq!(names(&model.query(&EntityQuery::new().function_active("walk"))), vec!["alice"]);
+        let mut found = names(&model.query(&EntityQuery::new().name_matches("*a?e*")));
+        found.sort();
+        assert_eq!(found, vec!["dave"]);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("abc", "abcd"));
+    }
+}
@@ -0,0 +1,462 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::cell::{Ref, RefCell};
+use crate::aggregate::{Aggregation, Summary};
+use crate::context::ReadOnlyModel;
+use crate::graph::Graph;
+use crate::metrics::NetworkMetric;
+use crate::types::EntityType;
+use crate::variable::{format_datetime, Value};
+
+pub trait Observer {
+    fn observe(&mut self, step: u64, model: &dyn ReadOnlyModel);
+}
+
+impl<T: Observer> Observer for Rc<RefCell<T>> {
+    fn observe(&mut self, step: u64, model: &dyn ReadOnlyModel) {
+        self.borrow_mut().observe(step, model);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Mean,
+    Variance,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    pub fn value_of(&self, summary: &Summary) -> Value {
+        let float = |x: Option<f64>| x.map_or(Value::Null, Value::Float64);
+        match self {
+            Aggregate::Count => Value::Integer(summary.count as i32),
+            Aggregate::Sum => float(summary.sum.or(Some(0.0))),
+            Aggregate::Mean => float(summary.mean),
+            Aggregate::Variance => float(summary.variance),
+            Aggregate::Min => summary.min.clone().unwrap_or(Value::Null),
+            Aggregate::Max => summary.max.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+pub type ObservableFn = Box<dyn Fn(&dyn ReadOnlyModel) -> Value>;
+
+pub enum Observable {
+    EntityCount(EntityType),
+    StateAggregate {
+        entity_type: Option<EntityType>,
+        key: String,
+        aggregate: Aggregate,
+    },
+    RelationCount(String),
+    Network {
+        relation_names: Vec<String>,
+        metric: NetworkMetric,
+    },
+    Custom(ObservableFn),
+}
+
+impl Observable {
+    pub fn sample(&self, model: &dyn ReadOnlyModel) -> Value {
+        match self {
+            Observable::EntityCount(entity_type) => {
+                Value::Integer(model.get_entities_by_type(entity_type).len() as i32)
+            }
+            Observable::StateAggregate { entity_type, key, aggregate } => {
+                let mut aggregation = Aggregation::new(model, key);
+                if let Some(entity_type) = entity_type {
+                    aggregation = aggregation.of_type(entity_type.clone());
+                }
+                aggregate.value_of(&aggregation.summary())
+            }
+            Observable::RelationCount(name) => {
+                let count = model.get_all_relations()
+                    .iter()
+                    .filter(|r| r.get_name() == name)
+                    .count();
+                Value::Integer(count as i32)
+            }
+            Observable::Network { relation_names, metric } => {
+                let names: Vec<&str> = relation_names.iter().map(|s| s.as_str()).collect();
+                Graph::new(model, &names).network_metric(metric)
+            }
+            Observable::Custom(f) => f(model),
+        }
+    }
+}
+
+impl fmt::Debug for Observable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Observable::EntityCount(t) => f.debug_tuple("EntityCount").field(t).finish(),
+            Observable::StateAggregate { entity_type, key, aggregate } => f.debug_struct("StateAggregate")
+                .field("entity_type", entity_type)
+                .field("key", key)
+                .field("aggregate", aggregate)
+                .finish(),
+            Observable::RelationCount(name) => f.debug_tuple("RelationCount").field(name).finish(),
+            Observable::Network { relation_names, metric } => f.debug_struct("Network")
+                .field("relation_names", relation_names)
+                .field("metric", metric)
+                .finish(),
+            Observable::Custom(_) => f.debug_tuple("Custom").field(&"<function>").finish(),
+        }
+    }
+}
+
+pub trait Sink {
+    fn begin(&mut self, columns: &[String]) -> io::Result<()>;
+    fn record(&mut self, step: u64, values: &[Value]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct Recorder {
+    sink: Box<dyn Sink>,
+    interval: u64,
+    columns: Vec<String>,
+    observables: Vec<Observable>,
+    started: bool,
+    last_error: Option<io::Error>,
+}
+
+impl Recorder {
+    pub fn new(sink: Box<dyn Sink>) -> Self {
+        Self {
+            sink,
+            interval: 1,
+            columns: Vec::new(),
+            observables: Vec::new(),
+            started: false,
+            last_error: None,
+        }
+    }
+
+    pub fn every(mut self, interval: u64) -> Self {
+        self.interval = interval.max(1);
+        self
+    }
+
+    pub fn observe(mut self, column: &str, observable: Observable) -> Self {
+        self.columns.push(column.to_string());
+        self.observables.push(observable);
+        self
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn sample(&mut self, step: u64, model: &dyn ReadOnlyModel) -> io::Result<()> {
+        if !self.started {
+            self.sink.begin(&self.columns)?;
+            self.started = true;
+        }
+        let values: Vec<Value> = self.observables.iter().map(|o| o.sample(model)).collect();
+        self.sink.record(step, &values)?;
+        self.sink.flush()
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.last_error.take()
+    }
+}
+
+impl Observer for Recorder {
+    fn observe(&mut self, step: u64, model: &dyn ReadOnlyModel) {
+        if !step.is_multiple_of(self.interval) {
+            return;
+        }
+        if let Err(e) = self.sample(step, model) {
+            self.last_error = Some(e);
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("interval", &self.interval)
+            .field("columns", &self.columns)
+            .finish()
+    }
+}
+
+pub struct CsvSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn begin(&mut self, columns: &[String]) -> io::Result<()> {
+        let header: Vec<String> = std::iter::once("step".to_string())
+            .chain(columns.iter().map(|c| csv_escape(c)))
+            .collect();
+        writeln!(self.writer, "{}", header.join(","))
+    }
+
+    fn record(&mut self, step: u64, values: &[Value]) -> io::Result<()> {
+        let row: Vec<String> = std::iter::once(step.to_string())
+            .chain(values.iter().map(csv_field))
+            .collect();
+        writeln!(self.writer, "{}", row.join(","))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+    columns: Vec<String>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, columns: Vec::new() }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Sink for JsonLinesSink<W> {
+    fn begin(&mut self, columns: &[String]) -> io::Result<()> {
+        self.columns = columns.to_vec();
+        Ok(())
+    }
+
+    fn record(&mut self, step: u64, values: &[Value]) -> io::Result<()> {
+        let mut line = format!("{{\"step\":{}", step);
+        for (column, value) in self.columns.iter().zip(values) {
+            line.push(',');
+            line.push_str(&json_string(column));
+            line.push(':');
+            line.push_str(&json_value(value));
+        }
+        line.push('}');
+        writeln!(self.writer, "{}", line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub step: u64,
+    pub values: Vec<Value>,
+}
+
+#[derive(Debug, Default)]
+pub struct TimeSeries {
+    pub columns: Vec<String>,
+    pub records: Vec<Record>,
+}
+
+impl TimeSeries {
+    pub fn column(&self, name: &str) -> Option<Vec<Value>> {
+        let index = self.columns.iter().position(|c| c == name)?;
+        Some(self.records.iter().map(|r| r.values[index].clone()).collect())
+    }
+}
+
+// クローンしたハンドルから記録済みのデータを参照できる
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    data: Rc<RefCell<TimeSeries>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn series(&self) -> Ref<'_, TimeSeries> {
+        self.data.borrow()
+    }
+}
+
+impl Sink for MemorySink {
+    fn begin(&mut self, columns: &[String]) -> io::Result<()> {
+        self.data.borrow_mut().columns = columns.to_vec();
+        Ok(())
+    }
+
+    fn record(&mut self, step: u64, values: &[Value]) -> io::Result<()> {
+        self.data.borrow_mut().records.push(Record { step, values: values.to_vec() });
+        Ok(())
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Int64(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Float64(f) => f.to_string(),
+        Value::String(s) => csv_escape(s),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(_) | Value::Map(_) => csv_escape(&json_value(value)),
+        Value::EntityRef(id) => id.to_string(),
+        Value::DateTime(datetime) => format_datetime(datetime),
+        Value::Null => String::new(),
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn json_value(value: &Value) -> String {
+    match value {
+        Value::Integer(i) => i.to_string(),
+        Value::Int64(i) => i.to_string(),
+        Value::Float(f) if f.is_finite() => f.to_string(),
+        Value::Float64(f) if f.is_finite() => f.to_string(),
+        Value::Float(_) | Value::Float64(_) | Value::Null => "null".to_string(),
+        Value::String(s) => json_string(s),
+        Value::Boolean(b) => b.to_string(),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(json_value).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Map(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(k, v)| format!("{}:{}", json_string(k), json_value(v)))
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::EntityRef(id) => json_string(&id.to_string()),
+        Value::DateTime(datetime) => json_string(&format_datetime(datetime)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    fn model_with_ages(ages: &[i32]) -> Model {
+        let model = Model::new();
+        for (i, age) in ages.iter().enumerate() {
+            let entity = model.create_entity(format!("agent{}", i), EntityType::Agent);
+            entity.get_state().borrow_mut().set("age", Value::Integer(*age));
+        }
+        model
+    }
+
+    #[test]
+    fn records_initial_state_when_attached() {
+        let model = model_with_ages(&[10, 20]);
+        let sink = MemorySink::new();
+        let recorder = Recorder::new(Box::new(sink.clone()))
+            .observe("agents", Observable::EntityCount(EntityType::Agent));
+        model.add_observer(Box::new(recorder));
+        model.simulate();
+        model.simulate();
+
+        let series = sink.series();
+        assert_eq!(series.columns, vec!["agents".to_string()]);
+        let steps: Vec<u64> = series.records.iter().map(|r| r.step).collect();
+        assert_eq!(steps, vec![0, 1, 2]);
+        assert_eq!(series.column("agents").unwrap(), vec![Value::Integer(2); 3]);
+    }
+
+    #[test]
+    fn interval_skips_steps() {
+        let model = model_with_ages(&[1]);
+        let sink = MemorySink::new();
+        let recorder = Recorder::new(Box::new(sink.clone()))
+            .every(2)
+            .observe("agents", Observable::EntityCount(EntityType::Agent));
+        model.add_observer(Box::new(recorder));
+        for _ in 0..5 {
+            model.simulate();
+        }
+        let steps: Vec<u64> = sink.series().records.iter().map(|r| r.step).collect();
+        assert_eq!(steps, vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn state_aggregate_and_relation_count() {
+        let model = model_with_ages(&[10, 20, 30]);
+        let observable = Observable::StateAggregate {
+            entity_type: Some(EntityType::Agent),
+            key: "age".to_string(),
+            aggregate: Aggregate::Mean,
+        };
+        assert_eq!(observable.sample(&model), Value::Float64(20.0));
+        assert_eq!(Observable::RelationCount("friend".to_string()).sample(&model), Value::Integer(0));
+    }
+
+    #[test]
+    fn aggregate_of_empty_summary_is_null() {
+        let model = Model::new();
+        let observable = Observable::StateAggregate {
+            entity_type: None,
+            key: "age".to_string(),
+            aggregate: Aggregate::Mean,
+        };
+        assert_eq!(observable.sample(&model), Value::Null);
+    }
+
+    #[test]
+    fn csv_sink_writes_header_and_escaped_rows() {
+        let mut sink = CsvSink::new(Vec::new());
+        sink.begin(&["a,b".to_string(), "c".to_string()]).unwrap();
+        sink.record(3, &[Value::String("x\"y".to_string()), Value::Null]).unwrap();
+        let text = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(text, "step,\"a,b\",c\n3,\"x\"\"y\",\n");
+    }
+
+    #[test]
+    fn json_lines_sink_writes_one_object_per_step() {
+        let mut sink = JsonLinesSink::new(Vec::new());
+        sink.begin(&["n".to_string(), "s".to_string()]).unwrap();
+        sink.record(1, &[Value::Integer(4), Value::String("a\nb".to_string())]).unwrap();
+        sink.record(2, &[Value::Float64(f64::NAN), Value::Boolean(true)]).unwrap();
+        let text = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(text, "{\"step\":1,\"n\":4,\"s\":\"a\\nb\"}\n{\"step\":2,\"n\":null,\"s\":true}\n");
+    }
+}
@@ -4,13 +4,14 @@ use std::collections::HashMap;
 use uuid::Uuid;
 use crate::entity::Entity;
 use crate::types::{EntityType, RelationType};
+use crate::symbol::Symbol;
 use crate::variable::{Variable, Value};
 use crate::context::{ReadOnlyRelation, ReadOnlyEntity};
 
 #[derive(Debug)]
 pub struct Relation {
     pub id: Uuid,
-    pub name: String,
+    pub name: Symbol,
     pub relation_type: RelationType,
     pub entity1: Weak<Entity>,
     pub entity2: Weak<Entity>,
@@ -18,10 +19,10 @@ pub struct Relation {
 }
 
 impl Relation {
-    pub fn new(name: String, relation_type: RelationType, entity1: Weak<Entity>, entity2: Weak<Entity>) -> Self {
+    pub fn new(name: impl Into<Symbol>, relation_type: RelationType, entity1: Weak<Entity>, entity2: Weak<Entity>) -> Self {
         Self {
             id: Uuid::new_v4(),
-            name,
+            name: name.into(),
             relation_type,
             entity1,
             entity2,
@@ -45,7 +46,7 @@ impl Relation {
         &self.meta
     }
 
-    pub fn add_metadata(&self, key: String, value: Value) {
+    pub fn add_metadata(&self, key: impl Into<Symbol>, value: Value) {
         self.meta.borrow_mut().set(key, value);
     }
 
@@ -58,7 +59,7 @@ impl Relation {
     }
 
     pub fn iter_meta(&self) -> HashMap<String, Value> {
-        self.meta.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        self.meta.borrow().iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
     }
 }
 
@@ -88,7 +89,7 @@ impl ReadOnlyRelation for Relation {
     }
 
     fn iter_meta(&self) -> HashMap<String, Value> {
-        self.meta.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        self.meta.borrow().iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
     }
 }
 
@@ -105,6 +106,12 @@ pub struct RelationshipRegistry {
     pub definitions: HashMap<String, RelationshipDefinition>,
 }
 
+impl Default for RelationshipRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RelationshipRegistry {
     pub fn new() -> Self {
         Self {
@@ -127,4 +134,54 @@ impl RelationshipRegistry {
     pub fn iter_definitions(&self) -> std::collections::hash_map::Iter<'_, String, RelationshipDefinition> {
         self.definitions.iter()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_entity_is_the_opposite_endpoint() {
+        let a = Rc::new(Entity::new("a".to_string(), EntityType::Agent));
+        let b = Rc::new(Entity::new("b".to_string(), EntityType::Agent));
+        let relation = Relation::new("knows", RelationType::OneToOne, Rc::downgrade(&a), Rc::downgrade(&b));
+        assert_eq!(relation.get_other_entity(&a).and_then(|e| e.upgrade()).map(|e| e.id), Some(b.id));
+        assert_eq!(relation.get_other_entity(&b).and_then(|e| e.upgrade()).map(|e| e.id), Some(a.id));
+        assert_eq!(ReadOnlyRelation::get_name(&relation), "knows");
+        assert_eq!(relation.get_entity2().map(|e| e.get_id()), Some(b.id));
+
+        drop(b);
+        assert!(relation.get_entity2().is_none());
+    }
+
+    #[test]
+    fn metadata_is_stored_by_symbol() {
+        let relation = Relation::new("knows".to_string(), RelationType::ManyToMany, Weak::new(), Weak::new());
+        relation.add_metadata("weight", Value::Float64(0.5));
+        relation.add_metadata(Symbol::new("since"), Value::Integer(3));
+        assert_eq!(relation.get_meta_value("weight"), Some(Value::Float64(0.5)));
+        assert_eq!(relation.iter_meta().len(), 2);
+
+        relation.remove_metadata("weight");
+        relation.remove_metadata("relation-test-unknown");
+        assert_eq!(ReadOnlyRelation::get_meta_value(&relation, "weight"), None);
+        assert_eq!(ReadOnlyRelation::iter_meta(&relation), HashMap::from([("since".to_string(), Value::Integer(3))]));
+    }
+
+    #[test]
+    fn definitions_are_replaced_by_name() {
+        let mut registry = RelationshipRegistry::new();
+        let definition = |relation_type| RelationshipDefinition {
+            name: "knows".to_string(),
+            source_type: EntityType::Agent,
+            target_type: EntityType::Agent,
+            relation_type,
+        };
+        registry.add_definition(definition(RelationType::OneToOne));
+        registry.add_definition(definition(RelationType::ManyToMany));
+        assert_eq!(registry.iter_definitions().count(), 1);
+        assert_eq!(registry.get_definition("knows").map(|d| d.relation_type), Some(RelationType::ManyToMany));
+        assert!(registry.remove_definition("knows").is_some());
+        assert!(registry.get_definition("knows").is_none());
+    }
+}
@@ -1,31 +1,121 @@
 use std::fmt;
 use std::collections::HashMap;
 use uuid::Uuid;
-use crate::context::ExecutionContext;
 use crate::types::{EntityType, RelationType};
-use crate::process::Condition;
+use crate::process::{ResultAction, ResultCondition};
 use crate::variable::Value;
+use crate::space::Position;
+use crate::symbol::Symbol;
 
 #[derive(Debug)]
 pub enum ExecutionResult {
-    UpdateEntityState(Uuid, String, Value),
-    DeleteEntityState(Uuid, String),
+    UpdateEntityState(Uuid, Symbol, Value),
+    DeleteEntityState(Uuid, Symbol),
     CreateEntity(EntityCreationInfo),
     DeleteEntity(Uuid),
     CreateRelation(RelationCreationInfo),
     DeleteRelation(Uuid),
     AddFunction(Uuid, FunctionCreationInfo),
-    RemoveFunction(Uuid, String),
-    ActivateFunction(Uuid, String),
-    DeactivateFunction(Uuid, String),
-    UpdateFunctionParameter(Uuid, String, String, Value),
-    DeleteFunctionParameter(Uuid, String, String),
-    AddProcess(Uuid, String, ProcessCreationInfo),
-    RemoveProcess(Uuid, String, String),
-    AddCondition(Uuid, String, String, Box<dyn Condition>),
-    RemoveCondition(Uuid, String, String),
-    AddRelationMetadata(Uuid, String, Value),
-    RemoveRelationMetadata(Uuid, String),
+    RemoveFunction(Uuid, Symbol),
+    ActivateFunction(Uuid, Symbol),
+    DeactivateFunction(Uuid, Symbol),
+    UpdateFunctionParameter(Uuid, Symbol, Symbol, Value),
+    DeleteFunctionParameter(Uuid, Symbol, Symbol),
+    AddProcess(Uuid, Symbol, ProcessCreationInfo),
+    RemoveProcess(Uuid, Symbol, String),
+    AddCondition(Uuid, Symbol, String, ResultCondition),
+    RemoveCondition(Uuid, Symbol, String),
+    AddRelationMetadata(Uuid, Symbol, Value),
+    RemoveRelationMetadata(Uuid, Symbol),
+    SetPosition(Uuid, Position),
+    RemovePosition(Uuid),
+    MoveEntity(Uuid, Uuid),
+    JoinSet(Uuid, Uuid),
+    LeaveSet(Uuid, Uuid),
+    SwitchContext(Uuid, String),
+}
+
+// キーや関数名は文字列でも Symbol でも渡せる。未登録の文字列はここで登録する
+impl ExecutionResult {
+    pub fn update_state(entity_id: Uuid, key: impl Into<Symbol>, value: impl Into<Value>) -> Self {
+        ExecutionResult::UpdateEntityState(entity_id, key.into(), value.into())
+    }
+
+    pub fn delete_state(entity_id: Uuid, key: impl Into<Symbol>) -> Self {
+        ExecutionResult::DeleteEntityState(entity_id, key.into())
+    }
+
+    pub fn remove_function(entity_id: Uuid, function_name: impl Into<Symbol>) -> Self {
+        ExecutionResult::RemoveFunction(entity_id, function_name.into())
+    }
+
+    pub fn activate_function(entity_id: Uuid, function_name: impl Into<Symbol>) -> Self {
+        ExecutionResult::ActivateFunction(entity_id, function_name.into())
+    }
+
+    pub fn deactivate_function(entity_id: Uuid, function_name: impl Into<Symbol>) -> Self {
+        ExecutionResult::DeactivateFunction(entity_id, function_name.into())
+    }
+
+    pub fn update_parameter(entity_id: Uuid, function_name: impl Into<Symbol>, key: impl Into<Symbol>, value: impl Into<Value>) -> Self {
+        ExecutionResult::UpdateFunctionParameter(entity_id, function_name.into(), key.into(), value.into())
+    }
+
+    pub fn delete_parameter(entity_id: Uuid, function_name: impl Into<Symbol>, key: impl Into<Symbol>) -> Self {
+        ExecutionResult::DeleteFunctionParameter(entity_id, function_name.into(), key.into())
+    }
+
+    pub fn add_relation_metadata(relation_id: Uuid, key: impl Into<Symbol>, value: impl Into<Value>) -> Self {
+        ExecutionResult::AddRelationMetadata(relation_id, key.into(), value.into())
+    }
+
+    pub fn remove_relation_metadata(relation_id: Uuid, key: impl Into<Symbol>) -> Self {
+        ExecutionResult::RemoveRelationMetadata(relation_id, key.into())
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExecutionResult::UpdateEntityState(..) => "UpdateEntityState",
+            ExecutionResult::DeleteEntityState(..) => "DeleteEntityState",
+            ExecutionResult::CreateEntity(_) => "CreateEntity",
+            ExecutionResult::DeleteEntity(_) => "DeleteEntity",
+            ExecutionResult::CreateRelation(_) => "CreateRelation",
+            ExecutionResult::DeleteRelation(_) => "DeleteRelation",
+            ExecutionResult::AddFunction(..) => "AddFunction",
+            ExecutionResult::RemoveFunction(..) => "RemoveFunction",
+            ExecutionResult::ActivateFunction(..) => "ActivateFunction",
+            ExecutionResult::DeactivateFunction(..) => "DeactivateFunction",
+            ExecutionResult::UpdateFunctionParameter(..) => "UpdateFunctionParameter",
+            ExecutionResult::DeleteFunctionParameter(..) => "DeleteFunctionParameter",
+            ExecutionResult::AddProcess(..) => "AddProcess",
+            ExecutionResult::RemoveProcess(..) => "RemoveProcess",
+            ExecutionResult::AddCondition(..) => "AddCondition",
+            ExecutionResult::RemoveCondition(..) => "RemoveCondition",
+            ExecutionResult::AddRelationMetadata(..) => "AddRelationMetadata",
+            ExecutionResult::RemoveRelationMetadata(..) => "RemoveRelationMetadata",
+            ExecutionResult::SetPosition(..) => "SetPosition",
+            ExecutionResult::RemovePosition(_) => "RemovePosition",
+            ExecutionResult::MoveEntity(..) => "MoveEntity",
+            ExecutionResult::JoinSet(..) => "JoinSet",
+            ExecutionResult::LeaveSet(..) => "LeaveSet",
+            ExecutionResult::SwitchContext(..) => "SwitchContext",
+        }
+    }
+
+    // 状態と関係だけに関わる結果か。関数やプロセスを持たないモデルはこれ以外を受け付けない
+    pub(crate) fn is_state_or_relation(&self) -> bool {
+        match self {
+            ExecutionResult::CreateEntity(info) => info.functions.is_empty(),
+            ExecutionResult::UpdateEntityState(..)
+            | ExecutionResult::DeleteEntityState(..)
+            | ExecutionResult::DeleteEntity(_)
+            | ExecutionResult::CreateRelation(_)
+            | ExecutionResult::DeleteRelation(_)
+            | ExecutionResult::AddRelationMetadata(..)
+            | ExecutionResult::RemoveRelationMetadata(..) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -46,8 +136,8 @@ pub struct FunctionCreationInfo {
 
 pub struct ProcessCreationInfo {
     pub name: String,
-    pub action: Box<dyn Fn(&ExecutionContext) -> Vec<ExecutionResult>>,
-    pub condition: Option<Box<dyn Condition>>,
+    pub action: ResultAction,
+    pub condition: Option<ResultCondition>,
 }
 
 impl fmt::Debug for ProcessCreationInfo {
@@ -67,4 +157,52 @@ pub struct RelationCreationInfo {
     pub target_entity_id: Option<Uuid>,
     pub target_entity_name: Option<String>,
     pub metadata: Option<HashMap<String, Value>>,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructors_accept_strings_and_symbols() {
+        let id = Uuid::new_v4();
+        match ExecutionResult::update_state(id, "age", 3) {
+            ExecutionResult::UpdateEntityState(entity_id, key, value) => {
+                assert_eq!(entity_id, id);
+                assert_eq!(key, Symbol::new("age"));
+                assert_eq!(value, Value::Integer(3));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match ExecutionResult::update_parameter(id, "walk".to_string(), Symbol::new("speed"), 1.5) {
+            ExecutionResult::UpdateFunctionParameter(_, function_name, key, value) => {
+                assert_eq!(function_name, "walk");
+                assert_eq!(key, "speed");
+                assert_eq!(value, Value::Float64(1.5));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(ExecutionResult::activate_function(id, "walk"), ExecutionResult::ActivateFunction(_, name) if name == "walk"));
+    }
+
+    #[test]
+    fn state_and_relation_results_are_classified() {
+        let id = Uuid::new_v4();
+        let entity = |functions: Vec<FunctionCreationInfo>| ExecutionResult::CreateEntity(EntityCreationInfo {
+            name: "a".to_string(),
+            entity_type: EntityType::Agent,
+            initial_state: HashMap::new(),
+            functions,
+            relations: Vec::new(),
+        });
+        assert!(ExecutionResult::delete_state(id, "age").is_state_or_relation());
+        assert!(ExecutionResult::remove_relation_metadata(id, "weight").is_state_or_relation());
+        assert!(entity(Vec::new()).is_state_or_relation());
+
+        let function = FunctionCreationInfo { name: "f".to_string(), initial_parameters: HashMap::new(), processes: Vec::new() };
+        assert!(!entity(vec![function]).is_state_or_relation());
+        assert!(!ExecutionResult::remove_function(id, "f").is_state_or_relation());
+        assert!(!ExecutionResult::RemovePosition(id).is_state_or_relation());
+        assert_eq!(ExecutionResult::RemovePosition(id).kind(), "RemovePosition");
+    }
+}
@@ -0,0 +1,208 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+use uuid::Uuid;
+use crate::context::{ExecutionContext, ReadOnlyModel};
+
+// ステップ数を日時に対応づけるシミュレーション時計
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    pub start: NaiveDateTime,
+    pub step_duration: Duration,
+}
+
+impl Clock {
+    pub fn new(start: NaiveDateTime, step_duration: Duration) -> Self {
+        Self { start, step_duration }
+    }
+
+    // 表現できない日時になる場合は None
+    pub fn time_at(&self, step: u64) -> Option<NaiveDateTime> {
+        let step = i64::try_from(step).ok()?;
+        let seconds = self.step_duration.num_seconds().checked_mul(step)?;
+        let nanos = i64::from(self.step_duration.subsec_nanos()).checked_mul(step)?;
+        let elapsed = Duration::try_seconds(seconds)?.checked_add(&Duration::nanoseconds(nanos))?;
+        self.start.checked_add_signed(elapsed)
+    }
+}
+
+// [start, end) の時間帯。start > end の場合は日付をまたぐ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeSlot {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub spot: Option<Uuid>,
+    pub context: Option<String>,
+    pub functions: Vec<String>,
+}
+
+impl TimeSlot {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self {
+            start,
+            end,
+            spot: None,
+            context: None,
+            functions: Vec::new(),
+        }
+    }
+
+    pub fn at_spot(mut self, spot_id: Uuid) -> Self {
+        self.spot = Some(spot_id);
+        self
+    }
+
+    pub fn in_context(mut self, context: &str) -> Self {
+        self.context = Some(context.to_string());
+        self
+    }
+
+    pub fn function(mut self, function_name: &str) -> Self {
+        self.functions.push(function_name.to_string());
+        self
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+// 時間帯が重なる場合は先に追加したものを優先する
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timetable {
+    pub slots: Vec<TimeSlot>,
+}
+
+impl Timetable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn slot(mut self, slot: TimeSlot) -> Self {
+        self.slots.push(slot);
+        self
+    }
+
+    pub fn slot_at(&self, time: NaiveTime) -> Option<&TimeSlot> {
+        self.slots.iter().find(|slot| slot.contains(time))
+    }
+
+    // いずれかの時間帯で切り替えの対象になる関数
+    pub fn scheduled_functions(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for name in self.slots.iter().flat_map(|slot| slot.functions.iter()) {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+}
+
+impl ExecutionContext<'_> {
+    pub fn current_time(&self) -> Option<NaiveDateTime> {
+        self.model.get_current_time()
+    }
+}
+
+impl dyn ReadOnlyModel + '_ {
+    pub fn current_slot(&self, entity_id: &Uuid) -> Option<TimeSlot> {
+        let time = self.get_current_time()?;
+        self.get_timetable(entity_id)?.slot_at(time.time()).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use chrono::NaiveDate;
+    use super::*;
+    use crate::function::Function;
+    use crate::model::Model;
+    use crate::multiplex::ContextDefinition;
+    use crate::types::EntityType;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn midnight() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_time(time(0, 0))
+    }
+
+    #[test]
+    fn clock_maps_steps_to_times() {
+        let clock = Clock::new(midnight(), Duration::minutes(90));
+        assert_eq!(clock.time_at(0), Some(midnight()));
+        assert_eq!(clock.time_at(3).unwrap().time(), time(4, 30));
+
+        // i32 を超えるステップ数でも切り捨てない
+        let seconds = Clock::new(midnight(), Duration::seconds(1));
+        let step = i32::MAX as u64 + 1;
+        assert_eq!(seconds.time_at(step), Some(midnight() + Duration::seconds(step as i64)));
+
+        let sub_second = Clock::new(midnight(), Duration::milliseconds(1500));
+        assert_eq!(sub_second.time_at(3), Some(midnight() + Duration::milliseconds(4500)));
+
+        assert_eq!(clock.time_at(u64::MAX), None);
+        assert_eq!(Clock::new(midnight(), Duration::days(365)).time_at(1_000_000), None);
+    }
+
+    #[test]
+    fn slots_wrap_past_midnight() {
+        let night = TimeSlot::new(time(22, 0), time(6, 0));
+        assert!(night.contains(time(23, 0)));
+        assert!(night.contains(time(5, 59)));
+        assert!(!night.contains(time(6, 0)));
+        assert!(!night.contains(time(12, 0)));
+
+        let day = TimeSlot::new(time(9, 0), time(17, 0));
+        assert!(day.contains(time(9, 0)));
+        assert!(!day.contains(time(17, 0)));
+    }
+
+    #[test]
+    fn timetable_prefers_earlier_slots() {
+        let timetable = Timetable::new()
+            .slot(TimeSlot::new(time(9, 0), time(12, 0)).function("meeting"))
+            .slot(TimeSlot::new(time(8, 0), time(18, 0)).function("work").function("meeting"));
+        assert_eq!(timetable.slot_at(time(10, 0)).unwrap().functions, vec!["meeting".to_string()]);
+        assert_eq!(timetable.slot_at(time(13, 0)).unwrap().functions.len(), 2);
+        assert!(timetable.slot_at(time(20, 0)).is_none());
+        assert_eq!(timetable.scheduled_functions(), vec!["meeting", "work"]);
+    }
+
+    #[test]
+    fn timetable_moves_agents_and_switches_functions() {
+        let model = Model::new();
+        model.define_context(ContextDefinition::new("office"));
+        let home = model.create_entity("home".to_string(), EntityType::Spot);
+        let office = model.create_entity("office".to_string(), EntityType::Spot);
+        let agent = model.create_entity("a".to_string(), EntityType::Agent);
+        let work = Function::new("work".to_string(), Rc::downgrade(&agent));
+        agent.add_function(Rc::new(work));
+
+        model.set_clock(Clock::new(midnight() + Duration::hours(8), Duration::hours(1)));
+        let timetable = Timetable::new()
+            .slot(TimeSlot::new(time(9, 0), time(17, 0)).at_spot(office.id).in_context("office").function("work"))
+            .slot(TimeSlot::new(time(17, 0), time(9, 0)).at_spot(home.id));
+        model.set_timetable(&agent.id, timetable).unwrap();
+        assert!(model.set_timetable(&Uuid::new_v4(), Timetable::new()).is_err());
+
+        model.simulate();
+        assert_eq!(model.location_of(&agent.id).map(|e| e.id), Some(home.id));
+        assert!(!agent.get_function("work").unwrap().is_active());
+
+        // 各ステップの始めの時刻 (9:00) で切り替わり、終了後の時計は次のステップを指す
+        assert_eq!(model.current_time().unwrap().time(), time(9, 0));
+        model.simulate();
+        assert_eq!(model.current_time().unwrap().time(), time(10, 0));
+        assert_eq!(model.location_of(&agent.id).map(|e| e.id), Some(office.id));
+        assert_eq!(model.active_context(&agent.id), Some("office".to_string()));
+        assert!(agent.get_function("work").unwrap().is_active());
+        let view: &dyn ReadOnlyModel = &model;
+        assert_eq!(view.current_slot(&agent.id).and_then(|slot| slot.spot), Some(office.id));
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use uuid::Uuid;
+use crate::types::EntityType;
+use crate::variable::{Value, ValueType, Variable};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSchema {
+    pub value_type: ValueType,
+    pub default: Option<Value>,
+    pub min: Bound<f64>,
+    pub max: Bound<f64>,
+    pub required: bool,
+}
+
+impl FieldSchema {
+    pub fn new(value_type: ValueType) -> Self {
+        Self {
+            value_type,
+            default: None,
+            min: Bound::Unbounded,
+            max: Bound::Unbounded,
+            required: false,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn default(mut self, value: Value) -> Self {
+        self.default = Some(value);
+        self
+    }
+
+    // 数値型のキーにのみ意味を持つ
+    pub fn range<R: RangeBounds<f64>>(mut self, range: R) -> Self {
+        self.min = range.start_bound().cloned();
+        self.max = range.end_bound().cloned();
+        self
+    }
+
+    fn check(&self, value: &Value) -> Result<(), ViolationKind> {
+        if value.is_null() && !self.required {
+            return Ok(());
+        }
+        if value.value_type() != self.value_type {
+            return Err(ViolationKind::TypeMismatch { expected: self.value_type, found: value.value_type() });
+        }
+        if let Ok(x) = value.as_f64() {
+            if !(self.min, self.max).contains(&x) {
+                return Err(ViolationKind::OutOfRange(value.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// EntityType ごとの状態スキーマ。strict でなければ宣言していないキーも許可する
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateSchema {
+    pub fields: BTreeMap<String, FieldSchema>,
+    pub strict: bool,
+}
+
+impl StateSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, key: &str, field: FieldSchema) -> Self {
+        self.fields.insert(key.to_string(), field);
+        self
+    }
+
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub fn check_value(&self, key: &str, value: &Value) -> Result<(), ViolationKind> {
+        match self.fields.get(key) {
+            Some(field) => field.check(value),
+            None if self.strict => Err(ViolationKind::UnknownKey),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_delete(&self, key: &str) -> Result<(), ViolationKind> {
+        match self.fields.get(key) {
+            Some(field) if field.required => Err(ViolationKind::RequiredDeleted),
+            _ => Ok(()),
+        }
+    }
+
+    // 未設定のキーに既定値を入れ、既定値のない必須キーを返す
+    pub fn fill_defaults(&self, state: &mut Variable) -> Vec<String> {
+        let mut missing = Vec::new();
+        for (key, field) in &self.fields {
+            if state.get(key).is_some() {
+                continue;
+            }
+            match &field.default {
+                Some(default) => state.set(key.clone(), default.clone()),
+                None if field.required => missing.push(key.clone()),
+                None => {}
+            }
+        }
+        missing
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ViolationKind {
+    TypeMismatch { expected: ValueType, found: ValueType },
+    OutOfRange(Value),
+    UnknownKey,
+    MissingRequired,
+    RequiredDeleted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaViolation {
+    pub step: u64,
+    pub entity_id: Uuid,
+    pub entity_type: EntityType,
+    pub key: String,
+    pub kind: ViolationKind,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}: {} {} key '{}': ", self.step, self.entity_type, self.entity_id, self.key)?;
+        match &self.kind {
+            ViolationKind::TypeMismatch { expected, found } => write!(f, "expected {:?}, found {:?}", expected, found),
+            ViolationKind::OutOfRange(value) => write!(f, "{} is out of range", value),
+            ViolationKind::UnknownKey => write!(f, "key is not declared in the schema"),
+            ViolationKind::MissingRequired => write!(f, "required key is missing"),
+            ViolationKind::RequiredDeleted => write!(f, "required key cannot be deleted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::model::{Model, ModelError};
+    use crate::result::ExecutionResult;
+
+    fn schema() -> StateSchema {
+        StateSchema::new()
+            .field("age", FieldSchema::new(ValueType::Integer).required().range(0.0..150.0))
+            .field("name", FieldSchema::new(ValueType::String).required())
+            .field("score", FieldSchema::new(ValueType::Float64).default(Value::Float64(0.0)))
+    }
+
+    #[test]
+    fn check_value_validates_type_range_and_unknown_keys() {
+        let schema = schema();
+        assert_eq!(schema.check_value("age", &Value::Integer(30)), Ok(()));
+        assert_eq!(schema.check_value("age", &Value::Integer(150)), Err(ViolationKind::OutOfRange(Value::Integer(150))));
+        assert_eq!(
+            schema.check_value("age", &Value::Float64(3.0)),
+            Err(ViolationKind::TypeMismatch { expected: ValueType::Integer, found: ValueType::Float64 })
+        );
+        assert!(schema.check_value("age", &Value::Null).is_err());
+        assert_eq!(schema.check_value("score", &Value::Null), Ok(()));
+        assert_eq!(schema.check_value("other", &Value::Null), Ok(()));
+        assert_eq!(schema.clone().strict().check_value("other", &Value::Null), Err(ViolationKind::UnknownKey));
+        assert_eq!(schema.check_delete("name"), Err(ViolationKind::RequiredDeleted));
+        assert_eq!(schema.check_delete("score"), Ok(()));
+    }
+
+    #[test]
+    fn fill_defaults_returns_missing_required_keys() {
+        let mut state = Variable::new();
+        state.set("age", Value::Integer(1));
+        assert_eq!(schema().fill_defaults(&mut state), vec!["name".to_string()]);
+        assert_eq!(state.get("score"), Some(&Value::Float64(0.0)));
+    }
+
+    #[test]
+    fn create_entity_records_missing_required_keys() {
+        let model = Model::new();
+        model.define_schema(EntityType::Agent, schema());
+        let entity = model.create_entity("a".to_string(), EntityType::Agent);
+        assert_eq!(entity.get_state().borrow().get("score"), Some(&Value::Float64(0.0)));
+        let violations = model.take_schema_violations();
+        let missing: Vec<&str> = violations.iter().map(|v| v.key.as_str()).collect();
+        assert_eq!(missing, vec!["age", "name"]);
+        assert!(violations.iter().all(|v| v.kind == ViolationKind::MissingRequired && v.entity_id == entity.id));
+
+        let result = model.create_entity_with_state("b".to_string(), EntityType::Agent, HashMap::from([("age".to_string(), Value::Integer(1))]));
+        assert!(matches!(result, Err(ModelError::SchemaViolation(SchemaViolation { kind: ViolationKind::MissingRequired, .. }))));
+        assert_eq!(model.get_all_entities().len(), 1);
+    }
+
+    #[test]
+    fn invalid_results_are_recorded_and_skipped() {
+        let model = Model::new();
+        model.define_schema(EntityType::Agent, schema());
+        let entity = model.create_entity_with_state(
+            "a".to_string(),
+            EntityType::Agent,
+            HashMap::from([("age".to_string(), Value::Integer(1)), ("name".to_string(), Value::from("a"))]),
+        ).unwrap();
+
+        model.apply_results(vec![
+            ExecutionResult::UpdateEntityState(entity.id, "age".into(), Value::Integer(-1)),
+            ExecutionResult::DeleteEntityState(entity.id, "name".into()),
+            ExecutionResult::UpdateEntityState(entity.id, "age".into(), Value::Integer(2)),
+        ]);
+        assert_eq!(entity.get_state().borrow().get("age"), Some(&Value::Integer(2)));
+        assert_eq!(entity.get_state().borrow().get("name"), Some(&Value::from("a")));
+        let kinds: Vec<ViolationKind> = model.take_schema_violations().into_iter().map(|v| v.kind).collect();
+        assert_eq!(kinds, vec![ViolationKind::OutOfRange(Value::Integer(-1)), ViolationKind::RequiredDeleted]);
+
+        assert!(matches!(
+            model.set_entity_state(&entity.id, "name", Value::Integer(1)),
+            Err(ModelError::SchemaViolation(SchemaViolation { kind: ViolationKind::TypeMismatch { .. }, .. }))
+        ));
+    }
+}
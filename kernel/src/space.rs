@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Position {
+    pub fn new(x: f64, y: f64) -> Self {
+        Self { x, y, z: 0.0 }
+    }
+
+    pub fn new_3d(x: f64, y: f64, z: f64) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn distance(&self, other: &Position) -> f64 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt()
+    }
+}
+
+type Cell = (i64, i64, i64);
+
+// 一様グリッドによる空間インデックス。cell_size は典型的な探索半径程度にするとよい
+#[derive(Debug, Clone)]
+pub struct Space {
+    dimensions: usize,
+    cell_size: f64,
+    positions: HashMap<Uuid, Position>,
+    grid: HashMap<Cell, Vec<Uuid>>,
+}
+
+impl Space {
+    pub fn new(dimensions: usize, cell_size: f64) -> Result<Self, String> {
+        if dimensions != 2 && dimensions != 3 {
+            return Err(format!("space must be 2D or 3D, got {} dimensions", dimensions));
+        }
+        if !(cell_size > 0.0 && cell_size.is_finite()) {
+            return Err(format!("cell size must be positive and finite, got {}", cell_size));
+        }
+        Ok(Self {
+            dimensions,
+            cell_size,
+            positions: HashMap::new(),
+            grid: HashMap::new(),
+        })
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    fn cell_of(&self, position: &Position) -> Cell {
+        let z = if self.dimensions == 3 { (position.z / self.cell_size).floor() as i64 } else { 0 };
+        (
+            (position.x / self.cell_size).floor() as i64,
+            (position.y / self.cell_size).floor() as i64,
+            z,
+        )
+    }
+
+    pub fn set_position(&mut self, id: Uuid, mut position: Position) {
+        if self.dimensions == 2 {
+            position.z = 0.0;
+        }
+        let cell = self.cell_of(&position);
+        if let Some(old) = self.positions.insert(id, position) {
+            let old_cell = self.cell_of(&old);
+            if old_cell == cell {
+                return;
+            }
+            self.remove_from_cell(old_cell, id);
+        }
+        self.grid.entry(cell).or_default().push(id);
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Position> {
+        let position = self.positions.remove(id)?;
+        self.remove_from_cell(self.cell_of(&position), *id);
+        Some(position)
+    }
+
+    fn remove_from_cell(&mut self, cell: Cell, id: Uuid) {
+        if let Some(ids) = self.grid.get_mut(&cell) {
+            ids.retain(|other| *other != id);
+            if ids.is_empty() {
+                self.grid.remove(&cell);
+            }
+        }
+    }
+
+    pub fn position(&self, id: &Uuid) -> Option<Position> {
+        self.positions.get(id).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, Position)> + '_ {
+        self.positions.iter().map(|(id, position)| (*id, *position))
+    }
+
+    // チェビシェフ距離 ring のセルを列挙する
+    fn ring(&self, center: Cell, ring: i64) -> Vec<Cell> {
+        let z_range = if self.dimensions == 3 { -ring..=ring } else { 0..=0 };
+        let mut cells = Vec::new();
+        for dz in z_range {
+            for dy in -ring..=ring {
+                for dx in -ring..=ring {
+                    if dx.abs().max(dy.abs()).max(dz.abs()) == ring {
+                        cells.push((center.0 + dx, center.1 + dy, center.2 + dz));
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    // チェビシェフ距離 ring のセルの数
+    fn ring_size(&self, ring: i64) -> f64 {
+        let side = |r: i64| (2 * r + 1) as f64;
+        match (self.dimensions, ring) {
+            (_, 0) => 1.0,
+            (3, r) => side(r).powi(3) - side(r - 1).powi(3),
+            (_, r) => side(r).powi(2) - side(r - 1).powi(2),
+        }
+    }
+
+    // 全件の距離を求めて昇順に並べる。探索するセルが占有セルより多くなる場合に使う
+    fn scan(&self, center: &Position) -> Vec<(Uuid, f64)> {
+        let mut found: Vec<(Uuid, f64)> = self.positions
+            .iter()
+            .map(|(id, position)| (*id, position.distance(center)))
+            .collect();
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    // 半径内のエンティティを距離の昇順で返す
+    pub fn within_radius(&self, center: &Position, radius: f64) -> Vec<(Uuid, f64)> {
+        if radius.is_nan() || radius < 0.0 || self.positions.is_empty() {
+            return Vec::new();
+        }
+        let reach = (radius / self.cell_size).ceil();
+        // 探索範囲のセル数が占有セル数を超える場合は全件を走査する方が速い
+        if (2.0 * reach + 1.0).powi(self.dimensions as i32) > self.grid.len() as f64 {
+            let mut found = self.scan(center);
+            found.retain(|(_, distance)| *distance <= radius);
+            return found;
+        }
+        let origin = self.cell_of(center);
+        let mut found = Vec::new();
+        for ring in 0..=reach as i64 {
+            for cell in self.ring(origin, ring) {
+                for id in self.grid.get(&cell).into_iter().flatten() {
+                    let distance = self.positions[id].distance(center);
+                    if distance <= radius {
+                        found.push((*id, distance));
+                    }
+                }
+            }
+        }
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        found
+    }
+
+    pub fn nearest(&self, center: &Position, k: usize) -> Vec<(Uuid, f64)> {
+        if k == 0 || self.positions.is_empty() {
+            return Vec::new();
+        }
+        let origin = self.cell_of(center);
+        let mut found = Vec::new();
+        let mut visited = 0;
+        let mut scanned_cells = 0.0;
+        let mut ring = 0;
+        loop {
+            // 遠くの点を探すためにリングを広げ続けるより、全件を走査する方が速くなったら切り替える
+            scanned_cells += self.ring_size(ring);
+            if scanned_cells > self.grid.len() as f64 {
+                let mut found = self.scan(center);
+                found.truncate(k);
+                return found;
+            }
+            for cell in self.ring(origin, ring) {
+                for id in self.grid.get(&cell).into_iter().flatten() {
+                    found.push((*id, self.positions[id].distance(center)));
+                    visited += 1;
+                }
+            }
+            found.sort_by(|a, b| a.1.total_cmp(&b.1));
+            // 次のリング以降の点は ring * cell_size より近くにはない
+            let settled = found.len() >= k && found[k - 1].1 <= ring as f64 * self.cell_size;
+            if settled || visited == self.positions.len() {
+                break;
+            }
+            ring += 1;
+        }
+        found.truncate(k);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn space(points: &[(f64, f64)]) -> (Space, Vec<Uuid>) {
+        let mut space = Space::new(2, 1.0).unwrap();
+        let ids: Vec<Uuid> = points.iter().map(|_| Uuid::new_v4()).collect();
+        for (id, (x, y)) in ids.iter().zip(points) {
+            space.set_position(*id, Position::new(*x, *y));
+        }
+        (space, ids)
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(Space::new(1, 1.0).is_err());
+        assert!(Space::new(4, 1.0).is_err());
+        assert!(Space::new(2, 0.0).is_err());
+        assert!(Space::new(3, f64::NAN).is_err());
+        assert!(Space::new(3, 0.5).is_ok());
+    }
+
+    #[test]
+    fn model_reports_invalid_space() {
+        let model = crate::model::Model::new();
+        let entity = model.create_entity("a".to_string(), crate::types::EntityType::Agent);
+        assert!(matches!(model.set_position(&entity.id, Position::new(0.0, 0.0)), Err(crate::model::ModelError::SpaceNotEnabled)));
+        assert!(matches!(model.enable_space(5, 1.0), Err(crate::model::ModelError::InvalidSpace(_))));
+        assert!(model.get_space().is_none());
+        model.enable_space(2, 1.0).unwrap();
+        model.set_position(&entity.id, Position::new(0.5, 0.5)).unwrap();
+        assert_eq!(model.get_space().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn radius_query_is_sorted_and_inclusive() {
+        let (space, ids) = space(&[(0.0, 0.0), (3.0, 4.0), (1.0, 0.0), (10.0, 0.0)]);
+        let found = space.within_radius(&Position::new(0.0, 0.0), 5.0);
+        assert_eq!(found.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![ids[0], ids[2], ids[1]]);
+        assert_eq!(found[2].1, 5.0);
+        assert!(space.within_radius(&Position::new(0.0, 0.0), -1.0).is_empty());
+        assert_eq!(space.within_radius(&Position::new(0.0, 0.0), f64::INFINITY).len(), 4);
+    }
+
+    #[test]
+    fn nearest_across_cells() {
+        let (space, ids) = space(&[(0.5, 0.5), (2.5, 0.5), (0.5, 3.5), (-4.0, -4.0)]);
+        let nearest = space.nearest(&Position::new(0.0, 0.0), 2);
+        assert_eq!(nearest.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![ids[0], ids[1]]);
+        assert_eq!(space.nearest(&Position::new(0.0, 0.0), 10).len(), 4);
+        assert!(space.nearest(&Position::new(0.0, 0.0), 0).is_empty());
+    }
+
+    #[test]
+    fn queries_far_from_sparse_points_fall_back_to_a_scan() {
+        let (space, ids) = space(&[(0.0, 0.0), (3.0, 4.0)]);
+        let found = space.within_radius(&Position::new(0.0, 0.0), 5000.0);
+        assert_eq!(found, vec![(ids[0], 0.0), (ids[1], 5.0)]);
+
+        // リングを広げていくと届かない距離でも、全件走査で正しい最近傍を返す
+        let far = Position::new(1e9, 1e9);
+        let nearest = space.nearest(&far, 1);
+        assert_eq!(nearest, vec![(ids[1], Position::new(3.0, 4.0).distance(&far))]);
+        let found = space.within_radius(&Position::new(1e12, 0.0), 1e13);
+        assert_eq!(found.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![ids[1], ids[0]]);
+    }
+
+    #[test]
+    fn moving_and_removing_points() {
+        let (mut space, ids) = space(&[(0.0, 0.0), (5.0, 5.0)]);
+        space.set_position(ids[0], Position::new(5.2, 5.2));
+        assert_eq!(space.within_radius(&Position::new(0.0, 0.0), 1.0).len(), 0);
+        assert_eq!(space.nearest(&Position::new(5.2, 5.2), 1)[0].0, ids[0]);
+        assert_eq!(space.remove(&ids[0]), Some(Position::new(5.2, 5.2)));
+        assert_eq!(space.len(), 1);
+        assert_eq!(space.nearest(&Position::new(5.2, 5.2), 1)[0].0, ids[1]);
+    }
+
+    #[test]
+    fn two_dimensional_space_ignores_z() {
+        let mut space = Space::new(2, 1.0).unwrap();
+        let id = Uuid::new_v4();
+        space.set_position(id, Position::new_3d(1.0, 1.0, 9.0));
+        assert_eq!(space.position(&id).unwrap().z, 0.0);
+
+        let mut space = Space::new(3, 1.0).unwrap();
+        space.set_position(id, Position::new_3d(0.0, 0.0, 3.0));
+        assert!(space.within_radius(&Position::new(0.0, 0.0), 2.0).is_empty());
+        assert_eq!(space.nearest(&Position::new(0.0, 0.0), 1)[0].1, 3.0);
+    }
+}
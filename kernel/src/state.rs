@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use crate::context::ExecutionContext;
+use crate::result::ExecutionResult;
+use crate::value::ValueError;
+use crate::variable::{Value, Variable};
+
+// Variable の1つのキーに対応するフィールドの型。Option<T> はキーがない状態を None として扱う
+pub trait StateField: Sized {
+    fn read(value: Option<&Value>) -> Result<Self, ValueError>;
+    fn write(&self) -> Option<Value>;
+}
+
+macro_rules! impl_state_field {
+    ($($t:ty),*) => {
+        $(
+            impl StateField for $t {
+                fn read(value: Option<&Value>) -> Result<Self, ValueError> {
+                    value.ok_or(ValueError::MissingKey(String::new())).and_then(<$t>::try_from)
+                }
+
+                fn write(&self) -> Option<Value> {
+                    Some(Value::from(self.clone()))
+                }
+            }
+        )*
+    };
+}
+
+impl_state_field!(i32, i64, f32, f64, bool, String, Uuid, NaiveDateTime);
+
+impl StateField for Value {
+    fn read(value: Option<&Value>) -> Result<Self, ValueError> {
+        value.cloned().ok_or(ValueError::MissingKey(String::new()))
+    }
+
+    fn write(&self) -> Option<Value> {
+        Some(self.clone())
+    }
+}
+
+impl StateField for Vec<Value> {
+    fn read(value: Option<&Value>) -> Result<Self, ValueError> {
+        let value = value.ok_or(ValueError::MissingKey(String::new()))?;
+        value.as_array().map(|items| items.to_vec())
+    }
+
+    fn write(&self) -> Option<Value> {
+        Some(Value::Array(self.clone()))
+    }
+}
+
+impl StateField for BTreeMap<String, Value> {
+    fn read(value: Option<&Value>) -> Result<Self, ValueError> {
+        let value = value.ok_or(ValueError::MissingKey(String::new()))?;
+        value.as_map().cloned()
+    }
+
+    fn write(&self) -> Option<Value> {
+        Some(Value::Map(self.clone()))
+    }
+}
+
+impl<T: StateField> StateField for Option<T> {
+    fn read(value: Option<&Value>) -> Result<Self, ValueError> {
+        match value {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => T::read(Some(value)).map(Some),
+        }
+    }
+
+    fn write(&self) -> Option<Value> {
+        self.as_ref().and_then(StateField::write)
+    }
+}
+
+// #[derive(EntityState)] で実装される。フィールドに対応するキーは keys() が返す
+pub trait EntityState: Sized {
+    fn keys() -> &'static [&'static str];
+    fn from_variable(state: &Variable) -> Result<Self, ValueError>;
+    fn to_values(&self) -> Vec<(&'static str, Option<Value>)>;
+
+    // 現在の状態と異なるキーだけを更新・削除する結果を作る
+    fn diff(&self, entity_id: Uuid, current: &Variable) -> Vec<ExecutionResult> {
+        self.to_values()
+            .into_iter()
+            .filter_map(|(key, value)| match (value, current.get(key)) {
+                (Some(value), Some(old)) if value == *old && value.value_type() == old.value_type() => None,
+                (Some(value), _) => Some(ExecutionResult::UpdateEntityState(entity_id, key.into(), value)),
+                (None, Some(_)) => Some(ExecutionResult::DeleteEntityState(entity_id, key.into())),
+                (None, None) => None,
+            })
+            .collect()
+    }
+
+    fn write_to(&self, state: &mut Variable) {
+        for (key, value) in self.to_values() {
+            match value {
+                Some(value) => state.set(key.to_string(), value),
+                None => state.remove(key),
+            }
+        }
+    }
+}
+
+// 派生コードから呼ばれ、エラーにキー名を付ける
+#[doc(hidden)]
+pub fn read_field<T: StateField>(state: &Variable, key: &str) -> Result<T, ValueError> {
+    T::read(state.get(key)).map_err(|error| match error {
+        ValueError::MissingKey(_) => ValueError::MissingKey(key.to_string()),
+        error => ValueError::InvalidKey { key: key.to_string(), error: Box::new(error) },
+    })
+}
+
+impl ExecutionContext<'_> {
+    pub fn read_state<T: EntityState>(&self) -> Result<T, ValueError> {
+        T::from_variable(&self.owner_entity.get_state())
+    }
+
+    pub fn write_state<T: EntityState>(&self, state: &T) -> Vec<ExecutionResult> {
+        state.diff(self.owner_entity.get_id(), &self.owner_entity.get_state())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EntityState;
+
+    #[derive(Debug, PartialEq, EntityState)]
+    struct Person {
+        age: i32,
+        #[state(rename = "display_name")]
+        name: String,
+        partner: Option<Uuid>,
+    }
+
+    fn state(age: Value) -> Variable {
+        let mut state = Variable::new();
+        state.set("age", age);
+        state.set("display_name", Value::from("alice"));
+        state
+    }
+
+    #[test]
+    fn derive_reads_renamed_and_optional_fields() {
+        assert_eq!(Person::keys(), &["age", "display_name", "partner"]);
+        let person = Person::from_variable(&state(Value::Integer(30))).unwrap();
+        assert_eq!(person, Person { age: 30, name: "alice".to_string(), partner: None });
+
+        let mut with_null = state(Value::Integer(30));
+        with_null.set("partner", Value::Null);
+        assert_eq!(Person::from_variable(&with_null).unwrap().partner, None);
+    }
+
+    #[test]
+    fn derive_reports_missing_and_mismatched_keys() {
+        let mut missing = Variable::new();
+        missing.set("age", Value::Integer(1));
+        assert_eq!(Person::from_variable(&missing), Err(ValueError::MissingKey("display_name".to_string())));
+
+        let error = Person::from_variable(&state(Value::from("old"))).unwrap_err();
+        assert!(matches!(
+            error,
+            ValueError::InvalidKey { ref key, ref error }
+                if key == "age" && matches!(**error, ValueError::TypeMismatch { found: crate::ValueType::String, .. })
+        ));
+
+        let mut bad_partner = state(Value::Integer(1));
+        bad_partner.set("partner", Value::Integer(2));
+        assert!(matches!(Person::from_variable(&bad_partner), Err(ValueError::InvalidKey { key, .. }) if key == "partner"));
+    }
+
+    #[test]
+    fn diff_and_write_only_touch_changed_keys() {
+        let id = Uuid::new_v4();
+        let mut current = state(Value::Integer(30));
+        current.set("partner", Value::EntityRef(id));
+        let person = Person { age: 31, name: "alice".to_string(), partner: None };
+
+        let results = person.diff(id, &current);
+        assert_eq!(results.len(), 2);
+        assert!(matches!(&results[0], ExecutionResult::UpdateEntityState(_, key, Value::Integer(31)) if key.as_str() == "age"));
+        assert!(matches!(&results[1], ExecutionResult::DeleteEntityState(_, key) if key.as_str() == "partner"));
+
+        // 値が等しくても型が違えば書き直す
+        let mut float_age = state(Value::Float64(31.0));
+        float_age.set("partner", Value::Null);
+        assert_eq!(person.diff(id, &float_age).len(), 2);
+
+        person.write_to(&mut current);
+        assert_eq!(Person::from_variable(&current).unwrap(), person);
+        assert!(current.get("partner").is_none());
+    }
+}
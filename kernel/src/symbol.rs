@@ -0,0 +1,267 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Mutex, OnceLock};
+
+// 状態キー・パラメータ名・関数名・関係名を表す整数のシンボル。文字列はプロセス全体で共有する表に一度だけ登録される
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// 文字列は追記のみのチャンクに置く。チャンク k は 2^k 個の枠を持ち、一度書いた枠は変わらないので読み取りにロックはいらない
+const CHUNKS: usize = 32;
+
+struct Names {
+    chunks: [OnceLock<Box<[OnceLock<&'static str>]>>; CHUNKS],
+    len: AtomicUsize,
+}
+
+fn names() -> &'static Names {
+    static NAMES: OnceLock<Names> = OnceLock::new();
+    NAMES.get_or_init(|| Names { chunks: std::array::from_fn(|_| OnceLock::new()), len: AtomicUsize::new(0) })
+}
+
+// 登録は一つずつ行う。検索用の表はこのスレッドの写しより新しいときだけ使う
+fn registry() -> &'static Mutex<HashMap<&'static str, u32>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<&'static str, u32>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn slot(id: u32) -> (usize, usize) {
+    let position = id as usize + 1;
+    let chunk = (usize::BITS - 1 - position.leading_zeros()) as usize;
+    (chunk, position - (1 << chunk))
+}
+
+// 文字列からの検索に使う、スレッドごとの表の写し。全体の件数より少なければ差分だけ取り込む
+#[derive(Default)]
+struct LocalTable {
+    ids: HashMap<&'static str, u32>,
+    synced: usize,
+}
+
+impl LocalTable {
+    fn sync(&mut self, names: &Names) {
+        let len = names.len.load(AtomicOrdering::Acquire);
+        for id in self.synced as u32..len as u32 {
+            self.ids.insert(Symbol(id).as_str(), id);
+        }
+        self.synced = len;
+    }
+}
+
+thread_local! {
+    static LOCAL: RefCell<LocalTable> = RefCell::new(LocalTable::default());
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Self {
+        if let Some(symbol) = Self::lookup(name) {
+            return symbol;
+        }
+        let mut registry = registry().lock().unwrap();
+        if let Some(id) = registry.get(name) {
+            return Symbol(*id);
+        }
+        let names = names();
+        let id = registry.len() as u32;
+        let (chunk, offset) = slot(id);
+        let chunk = names.chunks[chunk].get_or_init(|| (0..1usize << chunk).map(|_| OnceLock::new()).collect());
+        // 登録した文字列は解放しない
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let _ = chunk[offset].set(name);
+        registry.insert(name, id);
+        names.len.store(id as usize + 1, AtomicOrdering::Release);
+        Symbol(id)
+    }
+
+    // 登録済みの場合のみ返す。検索で表を増やさないために使う
+    pub fn lookup(name: &str) -> Option<Self> {
+        LOCAL
+            .try_with(|local| {
+                let mut local = local.borrow_mut();
+                local.sync(names());
+                local.ids.get(name).copied()
+            })
+            // スレッドの終了処理中は写しが使えないので共有の表を引く
+            .unwrap_or_else(|_| registry().lock().unwrap().get(name).copied())
+            .map(Symbol)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        let (chunk, offset) = slot(self.0);
+        names().chunks[chunk].get().and_then(|chunk| chunk[offset].get()).expect("symbol is registered")
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// 登録順ではなく文字列の順に並べる
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.0 == other.0 {
+            return Ordering::Equal;
+        }
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::new(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::new(&name)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(name: &String) -> Self {
+        Symbol::new(name)
+    }
+}
+
+impl From<Symbol> for String {
+    fn from(symbol: Symbol) -> Self {
+        symbol.as_str().to_string()
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+// 検索用のキー。文字列は未登録なら表に追加せず None を返す
+pub trait AsSymbol {
+    fn as_symbol(&self) -> Option<Symbol>;
+}
+
+impl AsSymbol for Symbol {
+    fn as_symbol(&self) -> Option<Symbol> {
+        Some(*self)
+    }
+}
+
+impl AsSymbol for str {
+    fn as_symbol(&self) -> Option<Symbol> {
+        Symbol::lookup(self)
+    }
+}
+
+impl AsSymbol for String {
+    fn as_symbol(&self) -> Option<Symbol> {
+        Symbol::lookup(self)
+    }
+}
+
+impl<T: AsSymbol + ?Sized> AsSymbol for &T {
+    fn as_symbol(&self) -> Option<Symbol> {
+        (**self).as_symbol()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn slots_fill_chunks_in_order() {
+        assert_eq!(slot(0), (0, 0));
+        assert_eq!(slot(1), (1, 0));
+        assert_eq!(slot(2), (1, 1));
+        assert_eq!(slot(3), (2, 0));
+        assert_eq!(slot(6), (2, 3));
+        assert_eq!(slot(u32::MAX - 1), (31, (1 << 31) - 1));
+    }
+
+    #[test]
+    fn same_name_gives_same_symbol() {
+        let a = Symbol::new("symbol-test-a");
+        assert_eq!(Symbol::new("symbol-test-a"), a);
+        assert_eq!(Symbol::from("symbol-test-a".to_string()), a);
+        assert_eq!(a.as_str(), "symbol-test-a");
+        assert_eq!(a, "symbol-test-a");
+        assert_eq!(format!("{} {:?}", a, a), "symbol-test-a \"symbol-test-a\"");
+    }
+
+    #[test]
+    fn lookup_does_not_register() {
+        assert_eq!(Symbol::lookup("symbol-test-unknown"), None);
+        assert_eq!("symbol-test-unknown".as_symbol(), None);
+        assert_eq!(Symbol::lookup("symbol-test-unknown"), None);
+        let symbol = Symbol::new("symbol-test-unknown");
+        assert_eq!(Symbol::lookup("symbol-test-unknown"), Some(symbol));
+    }
+
+    #[test]
+    fn symbols_order_by_name() {
+        let b = Symbol::new("symbol-test-order-b");
+        let a = Symbol::new("symbol-test-order-a");
+        let mut symbols = vec![b, a];
+        symbols.sort();
+        assert_eq!(symbols, vec![a, b]);
+    }
+
+    #[test]
+    fn symbols_are_shared_between_threads() {
+        let ids: Vec<Vec<Symbol>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| (0..200).map(|i| Symbol::new(&format!("symbol-test-shared-{}", i))).collect()))
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert!(ids.windows(2).all(|pair| pair[0] == pair[1]));
+        for (i, symbol) in ids[0].iter().enumerate() {
+            assert_eq!(symbol.as_str(), format!("symbol-test-shared-{}", i));
+            assert_eq!(Symbol::lookup(&format!("symbol-test-shared-{}", i)), Some(*symbol));
+        }
+    }
+}
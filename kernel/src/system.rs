@@ -0,0 +1,259 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+use uuid::Uuid;
+use crate::context::{ReadOnlyEntity, ReadOnlyModel};
+use crate::query::EntityQuery;
+use crate::result::ExecutionResult;
+use crate::symbol::Symbol;
+use crate::types::EntityType;
+use crate::value::ValueError;
+use crate::variable::Value;
+
+type SystemFn = Box<dyn Fn(&SystemContext, &mut Vec<ExecutionResult>) + 'static>;
+
+// 一致するエンティティ全体に対して各ステップ一度だけ実行される振る舞い。
+// 結果はステップ全体で共有するバッファに書き込む
+pub struct System {
+    pub name: String,
+    pub query: EntityQuery,
+    run: SystemFn,
+    active: Cell<bool>,
+    matches: RefCell<Option<Matches>>,
+}
+
+// entity_set_version が同じ間は候補を使い回す。query は派生型を解決済みのクエリ
+struct Matches {
+    version: u64,
+    query: EntityQuery,
+    candidates: Vec<Rc<dyn ReadOnlyEntity>>,
+}
+
+impl System {
+    pub fn new<F>(name: &str, query: EntityQuery, run: F) -> Self
+    where
+        F: Fn(&SystemContext, &mut Vec<ExecutionResult>) + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            query,
+            run: Box::new(run),
+            active: Cell::new(true),
+            matches: RefCell::new(None),
+        }
+    }
+
+    pub fn for_type<F>(name: &str, entity_type: EntityType, run: F) -> Self
+    where
+        F: Fn(&SystemContext, &mut Vec<ExecutionResult>) + 'static,
+    {
+        Self::new(name, EntityQuery::new().of_type(entity_type), run)
+    }
+
+    // エンティティごとの処理を、個別の Vec を作らずに書くための簡易形
+    pub fn per_entity<F>(name: &str, query: EntityQuery, f: F) -> Self
+    where
+        F: Fn(&dyn ReadOnlyEntity, &dyn ReadOnlyModel, &mut Vec<ExecutionResult>) + 'static,
+    {
+        Self::new(name, query, move |context, results| {
+            for entity in context.entities {
+                f(entity.as_ref(), context.model, results);
+            }
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.get()
+    }
+
+    pub fn activate(&self) {
+        self.active.set(true);
+    }
+
+    pub fn deactivate(&self) {
+        self.active.set(false);
+    }
+
+    pub(crate) fn run(&self, model: &dyn ReadOnlyModel, results: &mut Vec<ExecutionResult>) {
+        if !self.is_active() {
+            return;
+        }
+        let Some(version) = model.entity_set_version() else {
+            return self.run_on(model, &model.query(&self.query), results);
+        };
+        if self.matches.borrow().as_ref().is_none_or(|m| m.version != version) {
+            let subtypes = self.query.entity_type().map(|t| model.get_subtypes(t)).unwrap_or_default();
+            let query = self.query.clone().with_subtypes(subtypes);
+            let candidates = if query.is_structural() { model.query(&query) } else { model.query(&query.structural()) };
+            *self.matches.borrow_mut() = Some(Matches { version, query, candidates });
+        }
+        let matches = self.matches.borrow();
+        let Matches { query, candidates, .. } = matches.as_ref().unwrap();
+        if query.is_structural() {
+            self.run_on(model, candidates, results);
+        } else {
+            // 状態などに依存する条件は候補に対して毎ステップ評価し直す
+            let entities = query.apply(candidates.clone(), |e| e.as_ref());
+            self.run_on(model, &entities, results);
+        }
+    }
+
+    fn run_on(&self, model: &dyn ReadOnlyModel, entities: &[Rc<dyn ReadOnlyEntity>], results: &mut Vec<ExecutionResult>) {
+        if entities.is_empty() {
+            return;
+        }
+        let context = SystemContext { model, entities };
+        (self.run)(&context, results);
+    }
+}
+
+impl fmt::Debug for System {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("System")
+            .field("name", &self.name)
+            .field("query", &self.query)
+            .field("active", &self.active.get())
+            .finish()
+    }
+}
+
+pub struct SystemContext<'a> {
+    pub model: &'a dyn ReadOnlyModel,
+    pub entities: &'a [Rc<dyn ReadOnlyEntity>],
+}
+
+impl SystemContext<'_> {
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn ids(&self) -> Vec<Uuid> {
+        self.entities.iter().map(|e| e.get_id()).collect()
+    }
+
+    // entities と同じ順序で状態 key の値を並べる。列指向のモデルでは列から直接読む
+    pub fn column(&self, key: &str) -> Vec<Option<Value>> {
+        self.model
+            .read_column(key, &self.ids())
+            .unwrap_or_else(|| self.entities.iter().map(|e| e.get_state_value(key)).collect())
+    }
+
+    pub fn column_as<T>(&self, key: &str) -> Vec<Result<T, ValueError>>
+    where
+        T: for<'v> TryFrom<&'v Value, Error = ValueError>,
+    {
+        self.column(key)
+            .iter()
+            .map(|value| value.as_ref().ok_or_else(|| ValueError::MissingKey(key.to_string())).and_then(T::try_from))
+            .collect()
+    }
+
+    // entities と同じ順序の値の列を状態 key に書き戻す。None の要素は更新しない
+    pub fn write_column<I>(&self, key: &str, values: I, results: &mut Vec<ExecutionResult>)
+    where
+        I: IntoIterator<Item = Option<Value>>,
+    {
+        let key = Symbol::new(key);
+        results.extend(
+            self.entities
+                .iter()
+                .zip(values)
+                .filter_map(|(e, value)| value.map(|v| ExecutionResult::UpdateEntityState(e.get_id(), key, v))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::columnar::ColumnarModel;
+    use crate::model::Model;
+
+    fn seen_by(model: &Model, system: &System) -> Vec<String> {
+        let mut results = Vec::new();
+        system.run(model, &mut results);
+        let mut names: Vec<String> = results
+            .iter()
+            .filter_map(|r| match r {
+                ExecutionResult::UpdateEntityState(id, _, _) => model.get_entity(id).map(|e| e.name.clone()),
+                _ => None,
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn touch_all() -> impl Fn(&SystemContext, &mut Vec<ExecutionResult>) {
+        |context, results| context.write_column("seen", context.ids().iter().map(|_| Some(Value::Boolean(true))), results)
+    }
+
+    #[test]
+    fn matched_set_is_reused_until_entities_change() {
+        let model = Model::new();
+        model.create_entity("a".to_string(), EntityType::Agent);
+        let system = System::for_type("all", EntityType::Agent, touch_all());
+        assert_eq!(seen_by(&model, &system), vec!["a"]);
+        let version = system.matches.borrow().as_ref().unwrap().version;
+
+        seen_by(&model, &system);
+        assert_eq!(system.matches.borrow().as_ref().unwrap().version, version);
+
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        assert_eq!(seen_by(&model, &system), vec!["a", "b"]);
+        model.apply_results(vec![ExecutionResult::DeleteEntity(b.id)]);
+        assert_eq!(seen_by(&model, &system), vec!["a"]);
+
+        model.register_entity_type(EntityType::Custom("Person".to_string()), EntityType::Agent).unwrap();
+        model.create_entity("p".to_string(), EntityType::Custom("Person".to_string()));
+        assert_eq!(seen_by(&model, &system), vec!["a", "p"]);
+    }
+
+    #[test]
+    fn state_criteria_are_evaluated_every_step() {
+        let model = Model::new();
+        let a = model.create_entity("a".to_string(), EntityType::Agent);
+        let b = model.create_entity("b".to_string(), EntityType::Agent);
+        a.get_state().borrow_mut().set("sick", Value::Boolean(true));
+        let query = EntityQuery::new().of_type(EntityType::Agent).state_equals("sick", Value::Boolean(true));
+        let system = System::new("sick", query, touch_all());
+        assert_eq!(seen_by(&model, &system), vec!["a"]);
+
+        a.get_state().borrow_mut().set("sick", Value::Boolean(false));
+        b.get_state().borrow_mut().set("sick", Value::Boolean(true));
+        assert_eq!(seen_by(&model, &system), vec!["b"]);
+
+        system.deactivate();
+        assert!(seen_by(&model, &system).is_empty());
+    }
+
+    #[test]
+    fn columns_are_read_in_entity_order() {
+        let model = ColumnarModel::new();
+        let ids: Vec<Uuid> = (0..3)
+            .map(|i| {
+                let id = model.create_entity(format!("a{}", i), EntityType::Agent);
+                if i != 1 {
+                    model.set_state(&id, "age", Value::Integer(i)).unwrap();
+                }
+                id
+            })
+            .collect();
+        let view: &dyn ReadOnlyModel = &model;
+        let entities: Vec<Rc<dyn ReadOnlyEntity>> = ids.iter().rev().filter_map(|id| view.get_entity(id)).collect();
+        let context = SystemContext { model: view, entities: &entities };
+        assert_eq!(context.column("age"), vec![Some(Value::Integer(2)), None, Some(Value::Integer(0))]);
+        let ages = context.column_as::<i64>("age");
+        assert_eq!(ages[0], Ok(2));
+        assert_eq!(ages[1], Err(ValueError::MissingKey("age".to_string())));
+        assert_eq!(context.column("height"), vec![None, None, None]);
+
+        let mut results = Vec::new();
+        context.write_column("age", vec![Some(Value::Integer(9)), None, None], &mut results);
+        model.apply_results(results).unwrap();
+        assert_eq!(model.get_state(&ids[2], "age"), Some(Value::Integer(9)));
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum EntityType {
@@ -21,6 +23,24 @@ impl fmt::Display for EntityType {
     }
 }
 
+impl FromStr for EntityType {
+    type Err = std::convert::Infallible;
+
+    // 組み込みの型名に一致しない文字列は Custom として扱う
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Agent" => EntityType::Agent,
+            "Spot" => EntityType::Spot,
+            "AgentSet" | "Agent Set" => EntityType::AgentSet,
+            "SpotSet" | "Spot Set" => EntityType::SpotSet,
+            s => match s.strip_prefix("Custom(").and_then(|s| s.strip_suffix(')')) {
+                Some(inner) => EntityType::Custom(inner.to_string()),
+                None => EntityType::Custom(s.to_string()),
+            },
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RelationType {
     OneToOne,
@@ -38,4 +58,147 @@ impl fmt::Display for RelationType {
             RelationType::ManyToMany => write!(f, "ManyToMany"),
         }
     }
-}
\ No newline at end of file
+}
+
+// Custom 型の親子関係。組み込み型は常に根になる
+#[derive(Debug, Clone, Default)]
+pub struct TypeRegistry {
+    parents: HashMap<EntityType, EntityType>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, entity_type: EntityType, parent: EntityType) -> Result<(), String> {
+        if !matches!(entity_type, EntityType::Custom(_)) {
+            return Err(format!("built-in type {} cannot have a parent", entity_type));
+        }
+        if self.is_subtype(&parent, &entity_type) {
+            return Err(format!("{} cannot inherit from its own subtype {}", entity_type, parent));
+        }
+        self.parents.insert(entity_type, parent);
+        Ok(())
+    }
+
+    // 親として、または親を持つ型として登録されているか
+    pub fn contains(&self, entity_type: &EntityType) -> bool {
+        self.parents.contains_key(entity_type) || self.parents.values().any(|parent| parent == entity_type)
+    }
+
+    pub fn parent_of(&self, entity_type: &EntityType) -> Option<&EntityType> {
+        self.parents.get(entity_type)
+    }
+
+    // 自身から根までの型を近い順に返す
+    pub fn ancestors(&self, entity_type: &EntityType) -> Vec<EntityType> {
+        let mut ancestors = vec![entity_type.clone()];
+        let mut current = entity_type;
+        while let Some(parent) = self.parents.get(current) {
+            ancestors.push(parent.clone());
+            current = parent;
+        }
+        ancestors
+    }
+
+    // 階層の根 (登録されていない型は自身)
+    pub fn base_type(&self, entity_type: &EntityType) -> EntityType {
+        self.ancestors(entity_type).pop().unwrap()
+    }
+
+    pub fn is_subtype(&self, entity_type: &EntityType, ancestor: &EntityType) -> bool {
+        let mut current = entity_type;
+        loop {
+            if current == ancestor {
+                return true;
+            }
+            match self.parents.get(current) {
+                Some(parent) => current = parent,
+                None => return false,
+            }
+        }
+    }
+
+    // 自身を含むすべての子孫の型
+    pub fn subtypes(&self, entity_type: &EntityType) -> Vec<EntityType> {
+        let mut subtypes = vec![entity_type.clone()];
+        subtypes.extend(
+            self.parents
+                .keys()
+                .filter(|t| *t != entity_type && self.is_subtype(t, entity_type))
+                .cloned(),
+        );
+        subtypes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Model, ModelError};
+    use crate::schema::{FieldSchema, StateSchema};
+    use crate::variable::{Value, ValueType};
+
+    fn custom(name: &str) -> EntityType {
+        EntityType::Custom(name.to_string())
+    }
+
+    #[test]
+    fn entity_type_names_round_trip() {
+        for entity_type in [EntityType::Agent, EntityType::Spot, EntityType::AgentSet, EntityType::SpotSet, custom("Car")] {
+            assert_eq!(entity_type.to_string().parse::<EntityType>(), Ok(entity_type));
+        }
+        assert_eq!("SpotSet".parse::<EntityType>(), Ok(EntityType::SpotSet));
+        assert_eq!("Car".parse::<EntityType>(), Ok(custom("Car")));
+    }
+
+    #[test]
+    fn registry_resolves_ancestors_and_subtypes() {
+        let mut registry = TypeRegistry::new();
+        registry.register(custom("Person"), EntityType::Agent).unwrap();
+        registry.register(custom("Student"), custom("Person")).unwrap();
+
+        assert_eq!(registry.ancestors(&custom("Student")), vec![custom("Student"), custom("Person"), EntityType::Agent]);
+        assert_eq!(registry.base_type(&custom("Student")), EntityType::Agent);
+        assert_eq!(registry.base_type(&custom("Car")), custom("Car"));
+        assert!(registry.is_subtype(&custom("Student"), &EntityType::Agent));
+        assert!(!registry.is_subtype(&EntityType::Agent, &custom("Person")));
+        let mut subtypes = registry.subtypes(&EntityType::Agent);
+        subtypes.sort_by_key(|t| t.to_string());
+        assert_eq!(subtypes, vec![EntityType::Agent, custom("Person"), custom("Student")]);
+        assert!(registry.contains(&EntityType::Agent));
+        assert!(!registry.contains(&EntityType::Spot));
+    }
+
+    #[test]
+    fn registry_rejects_built_in_children_and_cycles() {
+        let mut registry = TypeRegistry::new();
+        assert!(registry.register(EntityType::Agent, EntityType::Spot).is_err());
+        registry.register(custom("A"), custom("B")).unwrap();
+        assert!(registry.register(custom("B"), custom("A")).is_err());
+        assert!(registry.register(custom("C"), custom("C")).is_err());
+        assert_eq!(registry.parent_of(&custom("B")), None);
+    }
+
+    #[test]
+    fn model_queries_and_schemas_follow_the_hierarchy() {
+        let model = Model::new();
+        model.register_entity_type(custom("Person"), EntityType::Agent).unwrap();
+        assert!(matches!(
+            model.register_entity_type(EntityType::Agent, custom("Person")),
+            Err(ModelError::InvalidTypeHierarchy(_))
+        ));
+        model.create_entity("a".to_string(), EntityType::Agent);
+        model.create_entity("p".to_string(), custom("Person"));
+        assert_eq!(model.get_entities_by_type(&EntityType::Agent).len(), 2);
+        assert_eq!(model.get_entities_by_type(&custom("Person")).len(), 1);
+
+        model.define_schema(EntityType::Agent, StateSchema::new().field("age", FieldSchema::new(ValueType::Integer).default(Value::Integer(0))));
+        model.define_schema(custom("Person"), StateSchema::new().field("name", FieldSchema::new(ValueType::String)));
+        let schema = model.get_schema(&custom("Person")).unwrap();
+        assert_eq!(schema.fields.keys().collect::<Vec<_>>(), vec!["age", "name"]);
+        let person = model.create_entity("q".to_string(), custom("Person"));
+        assert_eq!(person.get_state().borrow().get("age"), Some(&Value::Integer(0)));
+    }
+}
@@ -0,0 +1,579 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use crate::variable::{format_datetime, Value, ValueType, Variable};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueError {
+    TypeMismatch { expected: ValueType, found: ValueType },
+    OutOfRange(ValueType),
+    DivisionByZero,
+    InvalidOperation { operation: &'static str, left: ValueType, right: ValueType },
+    MissingKey(String),
+    InvalidKey { key: String, error: Box<ValueError> },
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueError::TypeMismatch { expected, found } => write!(f, "expected {:?}, found {:?}", expected, found),
+            ValueError::OutOfRange(value_type) => write!(f, "value out of range for {:?}", value_type),
+            ValueError::DivisionByZero => write!(f, "division by zero"),
+            ValueError::InvalidOperation { operation, left, right } => {
+                write!(f, "cannot apply {} to {:?} and {:?}", operation, left, right)
+            }
+            ValueError::MissingKey(key) => write!(f, "key '{}' not found", key),
+            ValueError::InvalidKey { key, error } => write!(f, "key '{}': {}", key, error),
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl Value {
+    fn mismatch(&self, expected: ValueType) -> ValueError {
+        ValueError::TypeMismatch { expected, found: self.value_type() }
+    }
+
+    pub fn as_i32(&self) -> Result<i32, ValueError> {
+        match self {
+            Value::Integer(i) => Ok(*i),
+            Value::Int64(i) => i32::try_from(*i).map_err(|_| ValueError::OutOfRange(ValueType::Integer)),
+            _ => Err(self.mismatch(ValueType::Integer)),
+        }
+    }
+
+    pub fn as_i64(&self) -> Result<i64, ValueError> {
+        match self {
+            Value::Integer(i) => Ok(*i as i64),
+            Value::Int64(i) => Ok(*i),
+            _ => Err(self.mismatch(ValueType::Int64)),
+        }
+    }
+
+    // 数値はすべて浮動小数点数として読める
+    pub fn as_f64(&self) -> Result<f64, ValueError> {
+        match self {
+            Value::Integer(i) => Ok(*i as f64),
+            Value::Int64(i) => Ok(*i as f64),
+            Value::Float(f) => Ok(*f as f64),
+            Value::Float64(f) => Ok(*f),
+            _ => Err(self.mismatch(ValueType::Float64)),
+        }
+    }
+
+    pub fn as_f32(&self) -> Result<f32, ValueError> {
+        match self {
+            Value::Float(f) => Ok(*f),
+            _ => self.as_f64().map(|f| f as f32).map_err(|_| self.mismatch(ValueType::Float)),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, ValueError> {
+        match self {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err(self.mismatch(ValueType::Boolean)),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, ValueError> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(self.mismatch(ValueType::String)),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Value], ValueError> {
+        match self {
+            Value::Array(items) => Ok(items),
+            _ => Err(self.mismatch(ValueType::Array)),
+        }
+    }
+
+    pub fn as_map(&self) -> Result<&BTreeMap<String, Value>, ValueError> {
+        match self {
+            Value::Map(entries) => Ok(entries),
+            _ => Err(self.mismatch(ValueType::Map)),
+        }
+    }
+
+    pub fn as_entity_ref(&self) -> Result<Uuid, ValueError> {
+        match self {
+            Value::EntityRef(id) => Ok(*id),
+            _ => Err(self.mismatch(ValueType::EntityRef)),
+        }
+    }
+
+    pub fn as_datetime(&self) -> Result<NaiveDateTime, ValueError> {
+        match self {
+            Value::DateTime(datetime) => Ok(*datetime),
+            _ => Err(self.mismatch(ValueType::DateTime)),
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        Number::of(self).is_some()
+    }
+}
+
+impl Variable {
+    pub fn get_as<T>(&self, key: &str) -> Result<T, ValueError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = ValueError>,
+    {
+        self.get(key)
+            .ok_or_else(|| ValueError::MissingKey(key.to_string()))
+            .and_then(T::try_from)
+    }
+}
+
+macro_rules! impl_conversions {
+    ($($t:ty => $variant:ident, $getter:ident;)*) => {
+        $(
+            impl From<$t> for Value {
+                fn from(value: $t) -> Self {
+                    Value::$variant(value)
+                }
+            }
+
+            impl TryFrom<&Value> for $t {
+                type Error = ValueError;
+
+                fn try_from(value: &Value) -> Result<Self, Self::Error> {
+                    value.$getter()
+                }
+            }
+
+            impl TryFrom<Value> for $t {
+                type Error = ValueError;
+
+                fn try_from(value: Value) -> Result<Self, Self::Error> {
+                    value.$getter()
+                }
+            }
+        )*
+    };
+}
+
+impl_conversions! {
+    i32 => Integer, as_i32;
+    i64 => Int64, as_i64;
+    f32 => Float, as_f32;
+    f64 => Float64, as_f64;
+    bool => Boolean, as_bool;
+    Uuid => EntityRef, as_entity_ref;
+    NaiveDateTime => DateTime, as_datetime;
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(value: BTreeMap<String, Value>) -> Self {
+        Value::Map(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl From<&Value> for Value {
+    fn from(value: &Value) -> Self {
+        value.clone()
+    }
+}
+
+impl TryFrom<&Value> for String {
+    type Error = ValueError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        value.as_str().map(|s| s.to_string())
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = ValueError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            other => Err(other.mismatch(ValueType::String)),
+        }
+    }
+}
+
+// 数値の比較・演算用の内部表現。rank は Integer < Int64 < Float < Float64 の昇格順
+#[derive(Clone, Copy)]
+enum Number {
+    Int(i64, u8),
+    Float(f64, u8),
+}
+
+impl Number {
+    fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::Integer(i) => Some(Number::Int(*i as i64, 0)),
+            Value::Int64(i) => Some(Number::Int(*i, 1)),
+            Value::Float(f) => Some(Number::Float(*f as f64, 2)),
+            Value::Float64(f) => Some(Number::Float(*f, 3)),
+            _ => None,
+        }
+    }
+}
+
+const I64_BOUND: f64 = 9_223_372_036_854_775_808.0;
+
+// 整数値を持つ浮動小数点数を i64 として取り出す
+fn integral(f: f64) -> Option<i64> {
+    (f.fract() == 0.0 && (-I64_BOUND..I64_BOUND).contains(&f)).then_some(f as i64)
+}
+
+// i64 と f64 を丸めずに比較する
+fn compare_int_float(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    if f >= I64_BOUND {
+        return Some(Ordering::Less);
+    }
+    if f < -I64_BOUND {
+        return Some(Ordering::Greater);
+    }
+    let truncated = f.trunc();
+    Some(i.cmp(&(truncated as i64)).then(truncated.partial_cmp(&f).unwrap_or(Ordering::Equal)))
+}
+
+fn compare_numbers(a: Number, b: Number) -> Option<Ordering> {
+    match (a, b) {
+        (Number::Int(x, _), Number::Int(y, _)) => Some(x.cmp(&y)),
+        (Number::Int(x, _), Number::Float(y, _)) => compare_int_float(x, y),
+        (Number::Float(x, _), Number::Int(y, _)) => compare_int_float(y, x).map(Ordering::reverse),
+        (Number::Float(x, _), Number::Float(y, _)) if x.is_nan() && y.is_nan() => Some(Ordering::Equal),
+        (Number::Float(x, _), Number::Float(y, _)) => x.partial_cmp(&y),
+    }
+}
+
+// 数値は型をまたいで値で比較する (Integer(1) == Float64(1.0))。NaN 同士は等しいとみなす
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (Number::of(self), Number::of(other)) {
+            (Some(a), Some(b)) => compare_numbers(a, b) == Some(Ordering::Equal),
+            (None, None) => match (self, other) {
+                (Value::String(a), Value::String(b)) => a == b,
+                (Value::Boolean(a), Value::Boolean(b)) => a == b,
+                (Value::Array(a), Value::Array(b)) => a == b,
+                (Value::Map(a), Value::Map(b)) => a == b,
+                (Value::EntityRef(a), Value::EntityRef(b)) => a == b,
+                (Value::DateTime(a), Value::DateTime(b)) => a == b,
+                (Value::Null, Value::Null) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+// 等しい値が同じハッシュになるよう、整数値を持つ浮動小数点数は整数としてハッシュする
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match Number::of(self) {
+            Some(Number::Int(i, _)) => (0u8, i).hash(state),
+            Some(Number::Float(f, _)) => match integral(f) {
+                Some(i) => (0u8, i).hash(state),
+                None if f.is_nan() => (1u8, f64::NAN.to_bits()).hash(state),
+                None => (1u8, f.to_bits()).hash(state),
+            },
+            None => {
+                std::mem::discriminant(self).hash(state);
+                match self {
+                    Value::String(s) => s.hash(state),
+                    Value::Boolean(b) => b.hash(state),
+                    Value::Array(items) => items.hash(state),
+                    Value::Map(entries) => entries.hash(state),
+                    Value::EntityRef(id) => id.hash(state),
+                    Value::DateTime(datetime) => datetime.hash(state),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (Number::of(self), Number::of(other)) {
+            (Some(a), Some(b)) => compare_numbers(a, b),
+            (None, None) => match (self, other) {
+                (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+                (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+                (Value::Array(a), Value::Array(b)) => a.partial_cmp(b),
+                (Value::Map(a), Value::Map(b)) => a.partial_cmp(b),
+                (Value::EntityRef(a), Value::EntityRef(b)) => a.partial_cmp(b),
+                (Value::DateTime(a), Value::DateTime(b)) => a.partial_cmp(b),
+                (Value::Null, Value::Null) => Some(Ordering::Equal),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Int64(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Float64(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Map(entries) => {
+                let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Value::EntityRef(id) => write!(f, "{}", id),
+            Value::DateTime(datetime) => write!(f, "{}", format_datetime(datetime)),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Operator {
+    fn name(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Rem => "%",
+        }
+    }
+
+    fn checked(&self, a: i64, b: i64) -> Result<Option<i64>, ValueError> {
+        match self {
+            Operator::Add => Ok(a.checked_add(b)),
+            Operator::Sub => Ok(a.checked_sub(b)),
+            Operator::Mul => Ok(a.checked_mul(b)),
+            Operator::Div | Operator::Rem if b == 0 => Err(ValueError::DivisionByZero),
+            Operator::Div => Ok(a.checked_div(b)),
+            Operator::Rem => Ok(a.checked_rem(b)),
+        }
+    }
+
+    fn float(&self, a: f64, b: f64) -> f64 {
+        match self {
+            Operator::Add => a + b,
+            Operator::Sub => a - b,
+            Operator::Mul => a * b,
+            Operator::Div => a / b,
+            Operator::Rem => a % b,
+        }
+    }
+}
+
+// 整数同士は整数のまま (i32 で溢れたら Int64 に昇格)、浮動小数点数が混ざれば浮動小数点数で計算する。
+// Int64 や Float64 が関わる場合は f64 で計算し、それ以外は f32 に戻す
+fn arithmetic(operator: Operator, left: &Value, right: &Value) -> Result<Value, ValueError> {
+    let invalid = || ValueError::InvalidOperation {
+        operation: operator.name(),
+        left: left.value_type(),
+        right: right.value_type(),
+    };
+    if let (Operator::Add, Value::String(a), Value::String(b)) = (operator, left, right) {
+        return Ok(Value::String(format!("{}{}", a, b)));
+    }
+    let (Some(a), Some(b)) = (Number::of(left), Number::of(right)) else {
+        return Err(invalid());
+    };
+    match (a, b) {
+        (Number::Int(x, rx), Number::Int(y, ry)) => {
+            let result = operator.checked(x, y)?.ok_or(ValueError::OutOfRange(ValueType::Int64))?;
+            match i32::try_from(result) {
+                Ok(result) if rx == 0 && ry == 0 => Ok(Value::Integer(result)),
+                _ => Ok(Value::Int64(result)),
+            }
+        }
+        (Number::Int(x, rx), Number::Float(y, ry)) | (Number::Float(y, ry), Number::Int(x, rx)) => {
+            let (x, y) = if matches!(a, Number::Int(..)) { (x as f64, y) } else { (y, x as f64) };
+            let result = operator.float(x, y);
+            if rx == 0 && ry == 2 {
+                Ok(Value::Float(result as f32))
+            } else {
+                Ok(Value::Float64(result))
+            }
+        }
+        (Number::Float(x, rx), Number::Float(y, ry)) => {
+            let result = operator.float(x, y);
+            if rx == 2 && ry == 2 {
+                Ok(Value::Float(result as f32))
+            } else {
+                Ok(Value::Float64(result))
+            }
+        }
+    }
+}
+
+macro_rules! impl_operator {
+    ($($trait:ident, $method:ident, $operator:ident;)*) => {
+        $(
+            impl<T: Into<Value>> $trait<T> for Value {
+                type Output = Result<Value, ValueError>;
+
+                fn $method(self, rhs: T) -> Self::Output {
+                    arithmetic(Operator::$operator, &self, &rhs.into())
+                }
+            }
+
+            impl<T: Into<Value>> $trait<T> for &Value {
+                type Output = Result<Value, ValueError>;
+
+                fn $method(self, rhs: T) -> Self::Output {
+                    arithmetic(Operator::$operator, self, &rhs.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_operator! {
+    Add, add, Add;
+    Sub, sub, Sub;
+    Mul, mul, Mul;
+    Div, div, Div;
+    Rem, rem, Rem;
+}
+
+impl Neg for &Value {
+    type Output = Result<Value, ValueError>;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Value::Integer(i) => Ok(i.checked_neg().map_or(Value::Int64(-(*i as i64)), Value::Integer)),
+            Value::Int64(i) => i.checked_neg().map(Value::Int64).ok_or(ValueError::OutOfRange(ValueType::Int64)),
+            Value::Float(f) => Ok(Value::Float(-f)),
+            Value::Float64(f) => Ok(Value::Float64(-f)),
+            _ => Err(ValueError::TypeMismatch { expected: ValueType::Float64, found: self.value_type() }),
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = Result<Value, ValueError>;
+
+    fn neg(self) -> Self::Output {
+        -&self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    #[test]
+    fn accessors_convert_within_range() {
+        assert_eq!(Value::Int64(7).as_i32(), Ok(7));
+        assert_eq!(Value::Int64(i64::MAX).as_i32(), Err(ValueError::OutOfRange(ValueType::Integer)));
+        assert_eq!(Value::Integer(2).as_f64(), Ok(2.0));
+        assert_eq!(Value::Float64(0.5).as_f32(), Ok(0.5));
+        assert_eq!(
+            Value::String("x".to_string()).as_f64(),
+            Err(ValueError::TypeMismatch { expected: ValueType::Float64, found: ValueType::String })
+        );
+        assert!(Value::Null.as_bool().is_err());
+        assert!(!Value::Boolean(true).is_numeric());
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let value: Value = 3_i64.into();
+        assert_eq!(i64::try_from(&value), Ok(3));
+        assert_eq!(String::try_from(Value::from("a")), Ok("a".to_string()));
+        assert_eq!(Value::from(None::<i32>), Value::Null);
+        assert_eq!(Value::from(Some(true)), Value::Boolean(true));
+
+        let mut variable = Variable::new();
+        variable.set("n", Value::Integer(4));
+        assert_eq!(variable.get_as::<f64>("n"), Ok(4.0));
+        assert_eq!(variable.get_as::<i32>("m"), Err(ValueError::MissingKey("m".to_string())));
+        assert!(variable.get_as::<bool>("n").is_err());
+    }
+
+    #[test]
+    fn numbers_compare_and_hash_across_types() {
+        assert_eq!(Value::Integer(1), Value::Float64(1.0));
+        assert_eq!(Value::Int64(2), Value::Float(2.0));
+        assert_ne!(Value::Integer(1), Value::Boolean(true));
+        assert_eq!(Value::Float64(f64::NAN), Value::Float(f32::NAN));
+        assert!(Value::Int64(i64::MAX) < Value::Float64(I64_BOUND));
+        assert!(Value::Integer(1) < Value::Float64(1.5));
+        assert_eq!(Value::String("a".to_string()).partial_cmp(&Value::Integer(1)), None);
+
+        let set: HashSet<Value> = [Value::Integer(1), Value::Float64(1.0), Value::Int64(1), Value::Float64(1.5)].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn arithmetic_promotes_and_reports_errors() {
+        assert_eq!(Value::Integer(2) + 3, Ok(Value::Integer(5)));
+        assert!(matches!(Value::Integer(i32::MAX) + 1, Ok(Value::Int64(_))));
+        assert_eq!(Value::Int64(i64::MAX) + 1_i64, Err(ValueError::OutOfRange(ValueType::Int64)));
+        assert!(matches!(Value::Integer(1) + 0.5_f32, Ok(Value::Float(_))));
+        assert!(matches!(Value::Int64(1) * 0.5_f32, Ok(Value::Float64(_))));
+        assert_eq!(Value::Integer(1) / 0, Err(ValueError::DivisionByZero));
+        assert_eq!(Value::Integer(7) % 4, Ok(Value::Integer(3)));
+        assert_eq!(Value::from("a") + "b", Ok(Value::from("ab")));
+        assert!(matches!(Value::from("a") - "b", Err(ValueError::InvalidOperation { operation: "-", .. })));
+        assert_eq!(-Value::Integer(i32::MIN), Ok(Value::Int64(-(i32::MIN as i64))));
+        assert!((-Value::Null).is_err());
+    }
+
+    #[test]
+    fn display_formats_nested_values() {
+        let value = Value::Map(BTreeMap::from([
+            ("a".to_string(), Value::Array(vec![Value::Integer(1), Value::Null])),
+        ]));
+        assert_eq!(value.to_string(), "{a: [1, null]}");
+        assert_eq!(
+            ValueError::InvalidKey { key: "k".to_string(), error: Box::new(ValueError::DivisionByZero) }.to_string(),
+            "key 'k': division by zero"
+        );
+    }
+}
@@ -1,39 +1,352 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use crate::symbol::{AsSymbol, Symbol};
 
 #[derive(Debug)]
 pub struct Variable {
-    values: HashMap<String, Value>,
+    values: HashMap<Symbol, Value>,
+    watch: Option<StateWatch>,
+    dirty: bool,
+}
+
+// 記録先はモデルのスレッドでしか使わない。parallel フィーチャーでは Variable をスレッド間で共有するため Arc と Mutex にする
+#[cfg(not(feature = "parallel"))]
+pub(crate) type SharedStateLog = std::rc::Rc<StateLog>;
+#[cfg(feature = "parallel")]
+pub(crate) type SharedStateLog = std::sync::Arc<StateLog>;
+
+// 状態・パラメータ・関係メタデータの書き換えをモデルに知らせるための記録先。モデルは次にインデックスを
+// 引くときに記録されたエンティティや関係を索引し直すため、直接書き換えてもインデックスは古くならない。
+// 状態インデックスがない間は、EntityRef の逆引きに関わる書き換えだけを記録する
+#[derive(Debug, Default)]
+pub(crate) struct StateLog {
+    #[cfg(not(feature = "parallel"))]
+    ids: std::cell::RefCell<Vec<Uuid>>,
+    #[cfg(feature = "parallel")]
+    ids: std::sync::Mutex<Vec<Uuid>>,
+    indexed: AtomicBool,
+}
+
+impl StateLog {
+    #[cfg(not(feature = "parallel"))]
+    fn ids(&self) -> impl DerefMut<Target = Vec<Uuid>> + '_ {
+        self.ids.borrow_mut()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn ids(&self) -> impl DerefMut<Target = Vec<Uuid>> + '_ {
+        self.ids.lock().unwrap()
+    }
+
+    pub(crate) fn take(&self) -> Vec<Uuid> {
+        std::mem::take(&mut *self.ids())
+    }
+
+    pub(crate) fn set_indexed(&self, indexed: bool) {
+        self.indexed.store(indexed, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StateWatch {
+    pub log: SharedStateLog,
+    pub id: Uuid,
+}
+
+impl Default for Variable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 複製した状態の変更は元のエンティティに知らせない
+impl Clone for Variable {
+    fn clone(&self) -> Self {
+        Variable {
+            values: self.values.clone(),
+            watch: None,
+            dirty: false,
+        }
+    }
 }
 
 impl Variable {
     pub fn new() -> Self {
         Variable {
             values: HashMap::new(),
+            watch: None,
+            dirty: false,
+        }
+    }
+
+    pub fn get<K: AsSymbol + ?Sized>(&self, key: &K) -> Option<&Value> {
+        self.values.get(&key.as_symbol()?)
+    }
+
+    pub fn set(&mut self, key: impl Into<Symbol>, value: Value) {
+        let refs = value.may_hold_entity_refs();
+        let previous = self.values.insert(key.into(), value);
+        self.touch(refs || previous.is_some_and(|previous| previous.may_hold_entity_refs()));
+    }
+
+    pub fn remove<K: AsSymbol + ?Sized>(&mut self, key: &K) {
+        if let Some(previous) = key.as_symbol().and_then(|symbol| self.values.remove(&symbol)) {
+            self.touch(previous.may_hold_entity_refs());
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<&Value> {
-        self.values.get(key)
+    pub(crate) fn watch(&mut self, watch: StateWatch) {
+        self.watch = Some(watch);
+        self.dirty = false;
     }
 
-    pub fn set(&mut self, key: String, value: Value) {
-        self.values.insert(key, value);
+    // source と同じ記録先に変更を知らせる。共有した時点の内容も索引し直してもらう
+    pub(crate) fn share_watch(&mut self, source: &Variable) {
+        self.watch = source.watch.clone();
+        self.dirty = false;
+        self.touch(true);
     }
 
-    pub fn remove(&mut self, key: &str) {
-        self.values.remove(key);
+    pub(crate) fn mark_clean(&mut self) {
+        self.dirty = false;
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, Value> {
+    // 前回モデルが索引し直してから最初の変更のときだけ記録する。refs は EntityRef を含みうる値を書き換えたか
+    fn touch(&mut self, refs: bool) {
+        if self.dirty {
+            return;
+        }
+        if let Some(watch) = &self.watch {
+            if refs || watch.log.indexed.load(Ordering::Relaxed) {
+                watch.log.ids().push(watch.id);
+                self.dirty = true;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, Symbol, Value> {
         self.values.iter()
     }
+
+    pub(crate) fn iter_mut(&mut self) -> std::collections::hash_map::IterMut<'_, Symbol, Value> {
+        self.touch(true);
+        self.values.iter_mut()
+    }
 }
 
+// 比較・ハッシュ・演算は value.rs で実装している
 #[derive(Debug, Clone)]
 pub enum Value {
     Integer(i32),
+    Int64(i64),
     Float(f32),
+    Float64(f64),
     String(String),
     Boolean(bool),
     Array(Vec<Value>),
-}
\ No newline at end of file
+    Map(BTreeMap<String, Value>),
+    EntityRef(Uuid),
+    DateTime(NaiveDateTime),
+    Null,
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Integer(_) => ValueType::Integer,
+            Value::Int64(_) => ValueType::Int64,
+            Value::Float(_) => ValueType::Float,
+            Value::Float64(_) => ValueType::Float64,
+            Value::String(_) => ValueType::String,
+            Value::Boolean(_) => ValueType::Boolean,
+            Value::Array(_) => ValueType::Array,
+            Value::Map(_) => ValueType::Map,
+            Value::EntityRef(_) => ValueType::EntityRef,
+            Value::DateTime(_) => ValueType::DateTime,
+            Value::Null => ValueType::Null,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    // 削除されたエンティティへの参照を (入れ子の中も含めて) Null に置き換え、変更があったかを返す
+    pub(crate) fn clear_entity_ref(&mut self, id: &Uuid) -> bool {
+        match self {
+            Value::EntityRef(target) if target == id => {
+                *self = Value::Null;
+                true
+            }
+            Value::Array(items) => items.iter_mut().fold(false, |changed, item| item.clear_entity_ref(id) | changed),
+            Value::Map(entries) => entries.values_mut().fold(false, |changed, item| item.clear_entity_ref(id) | changed),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn may_hold_entity_refs(&self) -> bool {
+        matches!(self, Value::EntityRef(_) | Value::Array(_) | Value::Map(_))
+    }
+
+    // 値が (入れ子の中も含めて) 参照しているエンティティを ids に加える
+    pub(crate) fn collect_entity_refs(&self, ids: &mut HashSet<Uuid>) {
+        match self {
+            Value::EntityRef(id) => {
+                ids.insert(*id);
+            }
+            Value::Array(items) => items.iter().for_each(|item| item.collect_entity_refs(ids)),
+            Value::Map(entries) => entries.values().for_each(|item| item.collect_entity_refs(ids)),
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn format_datetime(datetime: &NaiveDateTime) -> String {
+    datetime.format("%Y-%m-%dT%H:%M:%S%.f").to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    Integer,
+    Int64,
+    Float,
+    Float64,
+    String,
+    Boolean,
+    Array,
+    Map,
+    EntityRef,
+    DateTime,
+    Null,
+}
+
+impl ValueType {
+    // 文字列を指定された型の値に変換する。配列と Map はカンマ区切りとして扱わない
+    pub fn parse(&self, s: &str) -> Option<Value> {
+        match self {
+            ValueType::Integer => s.parse().ok().map(Value::Integer),
+            ValueType::Int64 => s.parse().ok().map(Value::Int64),
+            ValueType::Float => s.parse().ok().map(Value::Float),
+            ValueType::Float64 => s.parse().ok().map(Value::Float64),
+            ValueType::String => Some(Value::String(s.to_string())),
+            ValueType::Boolean => match s.to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" => Some(Value::Boolean(true)),
+                "false" | "0" | "no" => Some(Value::Boolean(false)),
+                _ => None,
+            },
+            ValueType::Array | ValueType::Map => None,
+            ValueType::EntityRef => Uuid::parse_str(s).ok().map(Value::EntityRef),
+            ValueType::DateTime => s.parse()
+                .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+                .ok()
+                .map(Value::DateTime),
+            ValueType::Null => match s {
+                "" | "null" | "NULL" => Some(Value::Null),
+                _ => None,
+            },
+        }
+    }
+
+    // 精度を落とさないよう、小数は Float64 として読む
+    pub fn infer(s: &str) -> Value {
+        [ValueType::Integer, ValueType::Int64, ValueType::Float64]
+            .iter()
+            .find_map(|t| t.parse(s))
+            .or_else(|| match s {
+                "true" | "false" => ValueType::Boolean.parse(s),
+                _ => None,
+            })
+            .or_else(|| ValueType::DateTime.parse(s))
+            .unwrap_or_else(|| Value::String(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infer_prefers_wide_numeric_types() {
+        assert_eq!(ValueType::infer("42"), Value::Integer(42));
+        assert_eq!(ValueType::infer("3000000000"), Value::Int64(3_000_000_000));
+        assert_eq!(ValueType::infer("0.1"), Value::Float64(0.1));
+        assert_eq!(ValueType::infer("true"), Value::Boolean(true));
+        assert!(matches!(ValueType::infer("2024-01-01T00:00:00"), Value::DateTime(_)));
+        assert_eq!(ValueType::infer("yes"), Value::String("yes".to_string()));
+    }
+
+    #[test]
+    fn entity_refs_are_found_and_cleared_in_nested_values() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut value = Value::Map(BTreeMap::from([
+            ("a".to_string(), Value::EntityRef(a)),
+            ("list".to_string(), Value::Array(vec![Value::EntityRef(b), Value::EntityRef(a)])),
+        ]));
+        let mut ids = HashSet::new();
+        value.collect_entity_refs(&mut ids);
+        assert_eq!(ids, HashSet::from([a, b]));
+
+        assert!(value.clear_entity_ref(&a));
+        assert!(!value.clear_entity_ref(&a));
+        let mut ids = HashSet::new();
+        value.collect_entity_refs(&mut ids);
+        assert_eq!(ids, HashSet::from([b]));
+    }
+
+    #[test]
+    fn watched_variable_logs_once_until_clean() {
+        let log = SharedStateLog::default();
+        log.set_indexed(true);
+        let id = Uuid::new_v4();
+        let mut variable = Variable::new();
+        variable.set("x", Value::Integer(0));
+        variable.watch(StateWatch { log: SharedStateLog::clone(&log), id });
+        variable.set("x", Value::Integer(1));
+        variable.remove("x");
+        assert_eq!(log.take(), vec![id]);
+
+        variable.mark_clean();
+        variable.set("y", Value::Null);
+        assert_eq!(log.take(), vec![id]);
+
+        // 複製は記録先を引き継がない
+        let mut copy = variable.clone();
+        copy.mark_clean();
+        copy.set("z", Value::Null);
+        assert!(log.take().is_empty());
+
+        let mut shared = Variable::new();
+        shared.share_watch(&variable);
+        assert_eq!(log.take(), vec![id]);
+    }
+
+    #[test]
+    fn without_state_indexes_only_reference_changes_are_logged() {
+        let log = SharedStateLog::default();
+        let id = Uuid::new_v4();
+        let mut variable = Variable::new();
+        variable.watch(StateWatch { log: SharedStateLog::clone(&log), id });
+        variable.set("x", Value::Integer(1));
+        variable.remove("x");
+        variable.remove("missing");
+        assert!(log.take().is_empty());
+
+        variable.set("friend", Value::EntityRef(Uuid::new_v4()));
+        assert_eq!(log.take(), vec![id]);
+        variable.mark_clean();
+        // 参照を含んでいた値の上書きも記録する
+        variable.set("friend", Value::Null);
+        assert_eq!(log.take(), vec![id]);
+    }
+}
@@ -14,4 +14,38 @@ pub use kernel;
 pub fn init() {
     println!("Initializing MCSS framework");
     // ここにMCSSの初期化ロジックを実装します
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::EntityState;
+
+    // 再エクスポート経由で derive を使う利用者と同じく、kernel へのパスを指定する
+    #[derive(Debug, PartialEq, kernel::EntityState)]
+    #[state(crate = "crate::kernel")]
+    struct Person {
+        age: i32,
+        #[state(rename = "is_sick")]
+        sick: bool,
+    }
+
+    #[test]
+    fn kernel_is_reexported() {
+        init();
+        let model = kernel::Model::new();
+        model.create_entity("a".to_string(), kernel::EntityType::Agent);
+        assert_eq!(model.get_all_entities().len(), 1);
+    }
+
+    #[test]
+    fn derive_works_through_the_reexport() {
+        let person = Person { age: 30, sick: true };
+        let mut state = kernel::Variable::new();
+        for (key, value) in person.to_values() {
+            state.set(key, value.unwrap());
+        }
+        assert_eq!(Person::keys(), &["age", "is_sick"]);
+        assert_eq!(Person::from_variable(&state).unwrap(), person);
+    }
+}